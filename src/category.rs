@@ -0,0 +1,250 @@
+//! Coarse classification of maps into memory categories, for high-level reports.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::shm::ShmKind;
+use crate::{Map, Pathname, DELETED_SUFFIX};
+
+/// A coarse category of memory, as used in high-level memory reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum MemoryCategory {
+    /// Executable, file-backed memory, i.e. program and library text.
+    Code,
+    /// Writable, private, file-backed memory, i.e. program and library data.
+    Data,
+    /// The process's heap.
+    Heap,
+    /// The process's (or a thread's) stack.
+    Stack,
+    /// Anonymous memory, e.g. from `mmap` or the allocator's arenas.
+    Anonymous,
+    /// Any other file-backed memory, e.g. read-only data, or shared file maps.
+    MappedFile,
+    /// Special regions provided by the kernel, e.g. the vDSO.
+    Special,
+}
+
+/// Knobs for how maps are classified and counted in memory reports.
+///
+/// The default policy matches [`Map::category`] and [`category_totals`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClassificationPolicy {
+    /// Whether maps of shared memory objects (`/dev/shm` files, memfds, SysV segments,
+    /// and shared anonymous maps) are `Anonymous` rather than file-backed.
+    pub shm_as_anonymous: bool,
+    /// Whether maps of deleted files are `Anonymous` rather than file-backed.
+    pub deleted_as_anonymous: bool,
+    /// Whether guard regions (see [`Map::is_guard`]) count towards totals.
+    pub count_guard_in_total: bool,
+}
+
+impl Default for ClassificationPolicy {
+    fn default() -> Self {
+        Self {
+            shm_as_anonymous: false,
+            deleted_as_anonymous: false,
+            count_guard_in_total: true,
+        }
+    }
+}
+
+impl ClassificationPolicy {
+    /// Returns whether `map` counts towards totals under this policy.
+    pub(crate) fn counts(&self, map: &Map) -> bool {
+        self.count_guard_in_total || !map.is_guard()
+    }
+}
+
+impl Map {
+    /// Classifies this map into a [`MemoryCategory`], based on its pathname and permissions.
+    ///
+    /// All anonymous maps (including executable ones, e.g. JIT code) are `Anonymous`.
+    /// Android's `[anon:...]` and `[stack:...]` pseudo-paths are recognized as
+    /// `Anonymous` and `Stack` respectively; other pseudo-paths are `Special`.
+    pub fn category(&self) -> MemoryCategory {
+        self.category_with(&ClassificationPolicy::default())
+    }
+
+    /// Classifies this map into a [`MemoryCategory`], like [`Map::category`], but
+    /// under the given policy.
+    pub fn category_with(&self, policy: &ClassificationPolicy) -> MemoryCategory {
+        if let Pathname::Path(path) = &self.pathname {
+            if policy.shm_as_anonymous && ShmKind::classify(self).is_some() {
+                return MemoryCategory::Anonymous;
+            }
+            if policy.deleted_as_anonymous && (self.deleted() || path.ends_with(DELETED_SUFFIX)) {
+                return MemoryCategory::Anonymous;
+            }
+        }
+
+        match &self.pathname {
+            Pathname::Heap => MemoryCategory::Heap,
+            Pathname::Stack => MemoryCategory::Stack,
+            Pathname::Mmap => MemoryCategory::Anonymous,
+            Pathname::Path(_) if self.permissions.executable => MemoryCategory::Code,
+            Pathname::Path(_) if self.permissions.writable && self.permissions.private => {
+                MemoryCategory::Data
+            }
+            Pathname::Path(_) => MemoryCategory::MappedFile,
+            Pathname::OtherPseudo(pseudo) if pseudo.starts_with("[anon:") => {
+                MemoryCategory::Anonymous
+            }
+            Pathname::OtherPseudo(pseudo) if pseudo.starts_with("[stack:") => MemoryCategory::Stack,
+            _ => MemoryCategory::Special,
+        }
+    }
+}
+
+/// Returns the total size of the given maps in each [`MemoryCategory`], in bytes.
+///
+/// Categories without any maps are omitted.
+pub fn category_totals(maps: &[Map]) -> BTreeMap<MemoryCategory, u64> {
+    category_totals_with(maps, &ClassificationPolicy::default())
+}
+
+/// Returns the total size of the given maps in each [`MemoryCategory`], like
+/// [`category_totals`], but classified and counted under the given policy.
+pub fn category_totals_with(
+    maps: &[Map],
+    policy: &ClassificationPolicy,
+) -> BTreeMap<MemoryCategory, u64> {
+    let mut totals = BTreeMap::new();
+
+    for map in maps.iter().filter(|map| policy.counts(map)) {
+        *totals.entry(map.category_with(policy)).or_insert(0) += map.address_range.len();
+    }
+
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{from_path, from_str};
+
+    fn category(line: &str) -> MemoryCategory {
+        from_str(line).next().unwrap().unwrap().category()
+    }
+
+    #[test]
+    fn test_category() {
+        assert_eq!(
+            category("5610d526f000-5610d5274000 r-xp 00002000 08:11 6572575 /bin/cat"),
+            MemoryCategory::Code
+        );
+        assert_eq!(
+            category("5610d527a000-5610d527b000 rw-p 0000b000 08:11 6572575 /bin/cat"),
+            MemoryCategory::Data
+        );
+        assert_eq!(
+            category("5610d5278000-5610d5279000 r--p 00009000 08:11 6572575 /bin/cat"),
+            MemoryCategory::MappedFile
+        );
+        assert_eq!(
+            category("7f0b6d800000-7f0b6d900000 rw-s 00000000 00:1a 2201 /dev/shm/foo"),
+            MemoryCategory::MappedFile
+        );
+        assert_eq!(
+            category("5610d6a1a000-5610d6a3b000 rw-p 00000000 00:00 0 [heap]"),
+            MemoryCategory::Heap
+        );
+        assert_eq!(
+            category("7ffd5a1c0000-7ffd5a1e1000 rw-p 00000000 00:00 0 [stack]"),
+            MemoryCategory::Stack
+        );
+        assert_eq!(
+            category("7fee89500000-7fee89504000 rw-p 00000000 00:00 0 "),
+            MemoryCategory::Anonymous
+        );
+        assert_eq!(
+            category("7a3c000000-7a3c400000 rw-p 00000000 00:00 0 [anon:libc_malloc]"),
+            MemoryCategory::Anonymous
+        );
+        assert_eq!(
+            category("7ffd5a1f8000-7ffd5a1fa000 r-xp 00000000 00:00 0 [vdso]"),
+            MemoryCategory::Special
+        );
+    }
+
+    #[test]
+    fn test_category_totals() {
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let totals = category_totals(&maps);
+
+        assert_eq!(
+            totals.values().sum::<u64>(),
+            maps.iter().map(|m| m.address_range.len()).sum::<u64>()
+        );
+        assert_eq!(totals[&MemoryCategory::Heap], 0x21000);
+        assert!(totals[&MemoryCategory::Code] > 0);
+        assert!(totals.contains_key(&MemoryCategory::Special));
+    }
+
+    #[test]
+    fn test_category_with_policy() {
+        let policy = ClassificationPolicy {
+            shm_as_anonymous: true,
+            deleted_as_anonymous: true,
+            count_guard_in_total: false,
+        };
+        let category_with = |line: &str| {
+            from_str(line)
+                .next()
+                .unwrap()
+                .unwrap()
+                .category_with(&policy)
+        };
+
+        assert_eq!(
+            category_with("7f0b6d800000-7f0b6d900000 rw-s 00000000 00:1a 2201 /dev/shm/foo"),
+            MemoryCategory::Anonymous
+        );
+        assert_eq!(
+            category_with("7f0b6d600000-7f0b6d700000 rw-s 00000000 00:01 5120 /memfd:wl (deleted)"),
+            MemoryCategory::Anonymous
+        );
+        assert_eq!(
+            category_with("5610d527a000-5610d527b000 rw-p 0000b000 08:11 657 /bin/cat (deleted)"),
+            MemoryCategory::Anonymous
+        );
+        assert_eq!(
+            category_with("5610d526f000-5610d5274000 r-xp 00002000 08:11 6572575 /bin/cat"),
+            MemoryCategory::Code
+        );
+    }
+
+    #[test]
+    fn test_category_totals_guards() {
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/threads.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let guards = maps
+            .iter()
+            .filter(|m| m.is_guard())
+            .map(|m| m.address_range.len())
+            .sum::<u64>();
+        assert!(guards > 0);
+
+        let with = category_totals(&maps);
+        let without = category_totals_with(
+            &maps,
+            &ClassificationPolicy {
+                count_guard_in_total: false,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            with[&MemoryCategory::Anonymous] - without[&MemoryCategory::Anonymous],
+            guards
+        );
+        assert_eq!(with[&MemoryCategory::Code], without[&MemoryCategory::Code]);
+    }
+}
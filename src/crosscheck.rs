@@ -0,0 +1,235 @@
+//! Cross-checking maps against the kernel's own memory accounting.
+//!
+//! This is primarily a diagnostic facility: if the totals computed from a
+//! process's maps disagree with `/proc/<pid>/status` by more than a little,
+//! then either the maps were misparsed or the process changed out from under us.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{from_pid, Map, Pathname, Pid};
+
+/// How far (in bytes) the totals computed from maps may stray outside of the
+/// kernel's counters before a cross-check fails.
+///
+/// The maps and the counters can't be read atomically, so [`crosscheck`] samples
+/// the counters both before and after reading the maps, and accepts any total
+/// within the range of those samples, widened by this tolerance. The widening
+/// absorbs small maps created and destroyed between the reads (e.g. by other threads).
+pub const TOLERANCE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// The fields of `/proc/<pid>/statm`, in pages. See `man 5 proc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Statm {
+    /// The total program size (same as `VmSize`).
+    pub size: u64,
+    /// The resident set size (same as `VmRSS`).
+    pub resident: u64,
+    /// The number of resident shared pages, i.e. those backed by a file.
+    pub shared: u64,
+    /// The size of the text (code).
+    pub text: u64,
+    /// Unused since Linux 2.6; always 0.
+    pub lib: u64,
+    /// The size of the data and stack.
+    pub data: u64,
+}
+
+impl Statm {
+    /// Reads the `statm` file for the given pid.
+    pub fn from_pid(pid: impl Into<Pid>) -> Result<Statm, Error> {
+        Statm::parse(&fs::read_to_string(
+            Path::new("/proc")
+                .join(pid.into().validate()?.to_string())
+                .join("statm"),
+        )?)
+    }
+
+    fn parse(contents: &str) -> Result<Statm, Error> {
+        let mut fields = contents.split_whitespace();
+        let mut field = |name: &str| -> Result<u64, Error> {
+            Ok(fields
+                .next()
+                .ok_or_else(|| Error::MissingCounter(format!("statm {}", name)))?
+                .parse()?)
+        };
+
+        Ok(Statm {
+            size: field("size")?,
+            resident: field("resident")?,
+            shared: field("shared")?,
+            text: field("text")?,
+            lib: field("lib")?,
+            data: field("data")?,
+        })
+    }
+}
+
+/// Reads the `Vm*` counters (e.g. `VmSize`) from the given pid's `status`
+/// file, in kB.
+///
+/// Kernel threads don't have an address space, and so don't have any `Vm*` counters.
+pub fn vm_counters(pid: impl Into<Pid>) -> Result<BTreeMap<String, u64>, Error> {
+    parse_vm_counters(&fs::read_to_string(
+        Path::new("/proc")
+            .join(pid.into().validate()?.to_string())
+            .join("status"),
+    )?)
+}
+
+fn parse_vm_counters(contents: &str) -> Result<BTreeMap<String, u64>, Error> {
+    contents
+        .lines()
+        .filter(|line| line.starts_with("Vm"))
+        .map(|line| {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| Error::MissingCounter(line.into()))?;
+            let value = value.trim().trim_end_matches("kB").trim_end().parse()?;
+            Ok((key.into(), value))
+        })
+        .collect()
+}
+
+/// The result of cross-checking a process's maps against its kernel counters.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CrossCheck {
+    /// The total size of every map, in bytes.
+    pub maps_total: u64,
+    /// The process's `VmSize`, in kB.
+    pub vm_size_kb: u64,
+    /// The process's size according to `statm`, in pages.
+    pub statm_size_pages: u64,
+    /// The difference between `maps_total` and `VmSize`, in bytes.
+    pub delta: i64,
+    /// Whether `maps_total` agrees with `VmSize`, within [`TOLERANCE_BYTES`].
+    pub within_tolerance: bool,
+    /// The total size of every executable (and not writable) map, in bytes.
+    pub exec_total: u64,
+    /// The process's `VmExe`, in kB.
+    pub vm_exe_kb: u64,
+    /// The process's `VmLib`, in kB.
+    pub vm_lib_kb: u64,
+    /// The difference between `exec_total` and `VmExe + VmLib`, in bytes.
+    pub exec_delta: i64,
+    /// Whether `exec_total` agrees with `VmExe + VmLib`, within [`TOLERANCE_BYTES`].
+    pub exec_within_tolerance: bool,
+}
+
+/// Cross-checks the given pid's maps against its `statm` and `status` counters.
+///
+/// `VmSize` should equal the total size of the maps, and `VmExe + VmLib` should equal
+/// the total size of the executable, non-writable maps. Neither counts the
+/// `[vsyscall]` page, which isn't really part of the process's address space.
+/// See [`TOLERANCE_BYTES`] for how disagreements are judged.
+pub fn crosscheck(pid: impl Into<Pid>) -> Result<CrossCheck, Error> {
+    let pid = pid.into();
+    let before = vm_counters(pid)?;
+    let statm = Statm::from_pid(pid)?;
+    let maps = from_pid(pid)?.collect::<Result<Vec<_>, _>>()?;
+    let after = vm_counters(pid)?;
+
+    let counter = |counters: &BTreeMap<String, u64>, name: &str| {
+        counters
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::MissingCounter(name.into()))
+    };
+    let size = |map: &Map| map.address_range.len();
+    let counted = maps.iter().filter(|m| m.pathname != Pathname::Vsyscall);
+
+    let maps_total = counted.clone().map(size).sum();
+    let exec_total = counted
+        .filter(|m| m.permissions.executable && !m.permissions.writable)
+        .map(size)
+        .sum();
+
+    let vm_size_kb = counter(&before, "VmSize")?;
+    let vm_exe_kb = counter(&before, "VmExe")?;
+    let vm_lib_kb = counter(&before, "VmLib")?;
+
+    let exec_kb = |counters: &BTreeMap<String, u64>| -> Result<u64, Error> {
+        Ok(counter(counters, "VmExe")? + counter(counters, "VmLib")?)
+    };
+
+    Ok(CrossCheck {
+        maps_total,
+        vm_size_kb,
+        statm_size_pages: statm.size,
+        delta: maps_total as i64 - (vm_size_kb * 1024) as i64,
+        within_tolerance: within_tolerance(maps_total, vm_size_kb, counter(&after, "VmSize")?),
+        exec_total,
+        vm_exe_kb,
+        vm_lib_kb,
+        exec_delta: exec_total as i64 - ((vm_exe_kb + vm_lib_kb) * 1024) as i64,
+        exec_within_tolerance: within_tolerance(exec_total, exec_kb(&before)?, exec_kb(&after)?),
+    })
+}
+
+fn within_tolerance(total: u64, before_kb: u64, after_kb: u64) -> bool {
+    let low = (before_kb.min(after_kb) * 1024).saturating_sub(TOLERANCE_BYTES);
+    let high = before_kb.max(after_kb) * 1024 + TOLERANCE_BYTES;
+
+    low <= total && total <= high
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_statm() {
+        assert_eq!(
+            Statm::parse("2045 210 185 5 0 89 0\n").unwrap(),
+            Statm {
+                size: 2045,
+                resident: 210,
+                shared: 185,
+                text: 5,
+                lib: 0,
+                data: 89,
+            }
+        );
+
+        assert!(matches!(
+            Statm::parse("2045 210"),
+            Err(Error::MissingCounter(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_vm_counters() {
+        let status = "Name:\tcat\nVmPeak:\t    8180 kB\nVmSize:\t    8180 kB\n\
+                      VmExe:\t      20 kB\nVmLib:\t    1660 kB\nThreads:\t1\n";
+        let counters = parse_vm_counters(status).unwrap();
+
+        assert_eq!(counters.len(), 4);
+        assert_eq!(counters["VmSize"], 8180);
+        assert_eq!(counters["VmLib"], 1660);
+    }
+
+    #[test]
+    fn test_within_tolerance() {
+        assert!(within_tolerance(1024 * 1024, 1024, 1024));
+        assert!(within_tolerance(TOLERANCE_BYTES, 0, 0));
+        assert!(!within_tolerance(TOLERANCE_BYTES + 1, 0, 0));
+        assert!(within_tolerance(
+            3 * 1024 * 1024 + TOLERANCE_BYTES,
+            1024,
+            3 * 1024
+        ));
+    }
+
+    #[test]
+    fn test_crosscheck_self() {
+        let check = crosscheck(std::process::id()).unwrap();
+
+        assert!(check.within_tolerance, "{:?}", check);
+        assert!(check.exec_within_tolerance, "{:?}", check);
+        assert!(check.delta.unsigned_abs() < 64 * 1024 * 1024, "{:?}", check);
+    }
+}
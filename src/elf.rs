@@ -0,0 +1,403 @@
+//! Minimal ELF header inspection for mapped images.
+//!
+//! This is deliberately not a full ELF parser: it understands just enough
+//! of the ELF header, program headers, and notes to sanity-check that a map
+//! contains an ELF image and to extract its build-id.
+
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::memory;
+use crate::{Map, Pid};
+
+/// The size of the ELF identification plus the fields of the ELF header
+/// that we inspect. This is also the size of a 64-bit ELF header.
+const PROBE_SIZE: usize = 64;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+const DT_NULL: u64 = 0;
+pub(crate) const DT_DEBUG: u64 = 21;
+
+/// The most program headers we're willing to read. Real images have a few dozen.
+const MAX_PROGRAM_HEADERS: u16 = 1024;
+
+/// The largest note segment we're willing to read.
+pub(crate) const MAX_NOTE_SIZE: u64 = 64 * 1024;
+
+/// Represents the class (i.e. word size) of an ELF image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ElfClass {
+    /// A 32-bit image (`ELFCLASS32`).
+    Elf32,
+    /// A 64-bit image (`ELFCLASS64`).
+    Elf64,
+}
+
+/// The result of probing a map for an ELF header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ElfProbe {
+    /// Whether the map begins with the ELF magic.
+    pub is_elf: bool,
+    /// The image's class, if recognized.
+    pub class: Option<ElfClass>,
+    /// The image's `e_machine`, if the header could be decoded.
+    pub machine: Option<u16>,
+    /// The image's `e_type`, if the header could be decoded.
+    pub etype: Option<u16>,
+}
+
+impl ElfProbe {
+    /// Probes the given bytes, which should be the beginning of an ELF image.
+    pub fn from_bytes(bytes: &[u8]) -> ElfProbe {
+        if !bytes.starts_with(ELF_MAGIC) {
+            return Default::default();
+        }
+
+        let class = match bytes.get(4) {
+            Some(1) => Some(ElfClass::Elf32),
+            Some(2) => Some(ElfClass::Elf64),
+            _ => None,
+        };
+
+        // NOTE(ww): e_type and e_machine come right after the identification,
+        // so they're at the same offsets for both classes.
+        let read_u16: fn([u8; 2]) -> u16 = match bytes.get(5) {
+            Some(1) => u16::from_le_bytes,
+            Some(2) => u16::from_be_bytes,
+            _ => {
+                return ElfProbe {
+                    is_elf: true,
+                    class,
+                    ..Default::default()
+                }
+            }
+        };
+        let field = |offset: usize| {
+            bytes
+                .get(offset..offset + 2)
+                .map(|b| read_u16([b[0], b[1]]))
+        };
+
+        ElfProbe {
+            is_elf: true,
+            class,
+            etype: field(16),
+            machine: field(18),
+        }
+    }
+}
+
+/// The parts of an ELF header needed to locate its program headers.
+#[derive(Debug)]
+pub(crate) struct ElfLayout {
+    class: ElfClass,
+    little_endian: bool,
+    pub(crate) phoff: u64,
+    phentsize: u16,
+    phnum: u16,
+}
+
+/// A program header, reduced to the fields that we use.
+#[derive(Debug)]
+pub(crate) struct ProgramHeader {
+    pub(crate) p_type: u32,
+    pub(crate) offset: u64,
+    pub(crate) vaddr: u64,
+    pub(crate) memsz: u64,
+    pub(crate) align: u64,
+}
+
+impl ElfLayout {
+    /// Decodes the layout from the beginning of an ELF image, if it's
+    /// a well-formed ELF header.
+    pub(crate) fn from_header(bytes: &[u8]) -> Option<ElfLayout> {
+        if !bytes.starts_with(ELF_MAGIC) {
+            return None;
+        }
+
+        let class = match bytes.get(4)? {
+            1 => ElfClass::Elf32,
+            2 => ElfClass::Elf64,
+            _ => return None,
+        };
+        let little_endian = match bytes.get(5)? {
+            1 => true,
+            2 => false,
+            _ => return None,
+        };
+
+        let mut layout = ElfLayout {
+            class,
+            little_endian,
+            phoff: 0,
+            phentsize: 0,
+            phnum: 0,
+        };
+
+        let (phoff, phentsize, phnum, min_phentsize) = match class {
+            ElfClass::Elf32 => (layout.u32(bytes, 0x1c)? as u64, 0x2a, 0x2c, 0x20),
+            ElfClass::Elf64 => (layout.u64(bytes, 0x20)?, 0x36, 0x38, 0x38),
+        };
+        layout.phoff = phoff;
+        layout.phentsize = layout.u16(bytes, phentsize)?;
+        layout.phnum = layout.u16(bytes, phnum)?;
+
+        if layout.phentsize < min_phentsize || layout.phnum > MAX_PROGRAM_HEADERS {
+            return None;
+        }
+
+        Some(layout)
+    }
+
+    /// Returns the size of the program header table, in bytes.
+    pub(crate) fn program_headers_size(&self) -> usize {
+        self.phentsize as usize * self.phnum as usize
+    }
+
+    /// Decodes the program header table, which must be [`ElfLayout::program_headers_size`]
+    /// bytes long. Returns `None` if it's truncated.
+    pub(crate) fn program_headers(&self, bytes: &[u8]) -> Option<Vec<ProgramHeader>> {
+        bytes
+            .chunks(self.phentsize as usize)
+            .take(self.phnum as usize)
+            .map(|phdr| match self.class {
+                ElfClass::Elf32 => Some(ProgramHeader {
+                    p_type: self.u32(phdr, 0)?,
+                    offset: self.u32(phdr, 0x4)? as u64,
+                    vaddr: self.u32(phdr, 0x8)? as u64,
+                    memsz: self.u32(phdr, 0x14)? as u64,
+                    align: self.u32(phdr, 0x1c)? as u64,
+                }),
+                ElfClass::Elf64 => Some(ProgramHeader {
+                    p_type: self.u32(phdr, 0)?,
+                    offset: self.u64(phdr, 0x8)?,
+                    vaddr: self.u64(phdr, 0x10)?,
+                    memsz: self.u64(phdr, 0x28)?,
+                    align: self.u64(phdr, 0x30)?,
+                }),
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|phdrs| phdrs.len() == self.phnum as usize)
+    }
+
+    /// Returns the load bias of an image whose first page is mapped at `base`,
+    /// i.e. the difference between its runtime and link-time addresses.
+    pub(crate) fn load_bias(&self, phdrs: &[ProgramHeader], base: u64) -> Option<u64> {
+        let first_load = phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .min_by_key(|phdr| phdr.vaddr)?;
+
+        base.checked_sub(first_load.vaddr.checked_sub(first_load.offset)?)
+    }
+
+    /// Returns the note segments among the given program headers.
+    pub(crate) fn notes<'a>(
+        &self,
+        phdrs: &'a [ProgramHeader],
+    ) -> impl Iterator<Item = &'a ProgramHeader> {
+        phdrs.iter().filter(|phdr| phdr.p_type == PT_NOTE)
+    }
+
+    /// Returns the dynamic segment among the given program headers, if there is one.
+    pub(crate) fn dynamic<'a>(&self, phdrs: &'a [ProgramHeader]) -> Option<&'a ProgramHeader> {
+        phdrs.iter().find(|phdr| phdr.p_type == PT_DYNAMIC)
+    }
+
+    /// Scans a dynamic segment for the first entry with the given tag, returning its value.
+    pub(crate) fn find_dynamic(&self, dynamic: &[u8], tag: u64) -> Option<u64> {
+        dynamic
+            .chunks_exact(self.word_size() * 2)
+            .map(|entry| Some((self.word(entry, 0)?, self.word(entry, self.word_size())?)))
+            .take_while(|entry| entry.is_some_and(|(d_tag, _)| d_tag != DT_NULL))
+            .find_map(|entry| entry.filter(|(d_tag, _)| *d_tag == tag))
+            .map(|(_, d_val)| d_val)
+    }
+
+    /// Returns the size of a word (i.e. an address) in this image.
+    pub(crate) fn word_size(&self) -> usize {
+        match self.class {
+            ElfClass::Elf32 => 4,
+            ElfClass::Elf64 => 8,
+        }
+    }
+
+    /// Reads a word at the given offset.
+    pub(crate) fn word(&self, bytes: &[u8], offset: usize) -> Option<u64> {
+        match self.class {
+            ElfClass::Elf32 => self.u32(bytes, offset).map(u64::from),
+            ElfClass::Elf64 => self.u64(bytes, offset),
+        }
+    }
+
+    /// Scans a note segment for a GNU build-id note.
+    pub(crate) fn find_build_id(&self, notes: &[u8], align: u64) -> Option<Vec<u8>> {
+        // NOTE(ww): Notes are 4-byte aligned, except in segments that
+        // explicitly ask for 8-byte alignment (e.g. GNU property notes).
+        let align = if align == 8 { 8 } else { 4 };
+        let pad = |n: usize| n.div_ceil(align) * align;
+
+        let mut rest = notes;
+        while rest.len() >= 12 {
+            let namesz = self.u32(rest, 0)? as usize;
+            let descsz = self.u32(rest, 4)? as usize;
+            let n_type = self.u32(rest, 8)?;
+
+            let name = rest.get(12..12usize.checked_add(namesz)?)?;
+            let desc_start = 12 + pad(namesz);
+            let desc = rest.get(desc_start..desc_start.checked_add(descsz)?)?;
+
+            if n_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+                return Some(desc.to_vec());
+            }
+
+            rest = rest.get(desc_start + pad(descsz)..)?;
+        }
+
+        None
+    }
+
+    fn u16(&self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let bytes = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let bytes = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, bytes: &[u8], offset: usize) -> Option<u64> {
+        let bytes = bytes.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+}
+
+/// Reads the ELF and program headers of the image whose first page is mapped
+/// by `base_map`, in the given pid's memory.
+///
+/// Every read is bounded, so a corrupt or malicious header can't cause a huge
+/// allocation. Returns `None` if the map doesn't contain a recognizable ELF image.
+pub(crate) fn read_program_headers(
+    pid: Pid,
+    base_map: &Map,
+) -> Result<Option<(ElfLayout, Vec<ProgramHeader>)>, Error> {
+    let layout = match ElfLayout::from_header(&base_map.read_bytes(pid, PROBE_SIZE)?) {
+        Some(layout) => layout,
+        None => return Ok(None),
+    };
+
+    let phdrs = match base_map.address_range.begin.checked_add(layout.phoff) {
+        Some(phdrs_addr) => memory::read(pid, phdrs_addr, layout.program_headers_size())?,
+        None => return Ok(None),
+    };
+
+    Ok(layout.program_headers(&phdrs).map(|phdrs| (layout, phdrs)))
+}
+
+impl Map {
+    /// Probes this map in the given pid's memory for an ELF header.
+    ///
+    /// Only the map at the start of a module (i.e. with a file offset of 0)
+    /// contains the ELF header; probing any other map returns
+    /// [`Error::NotModuleBase`].
+    pub fn probe_elf(&self, pid: impl Into<Pid>) -> Result<ElfProbe, Error> {
+        if self.offset != 0 {
+            return Err(Error::NotModuleBase);
+        }
+
+        Ok(ElfProbe::from_bytes(&self.read_bytes(pid, PROBE_SIZE)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_pid, main_executable_maps, Pathname};
+
+    #[test]
+    fn test_probe_bytes() {
+        assert_eq!(ElfProbe::from_bytes(b"not an elf"), Default::default());
+
+        let mut header = [0u8; PROBE_SIZE];
+        header[..4].copy_from_slice(ELF_MAGIC);
+        header[4] = 2;
+        header[5] = 1;
+        header[16..18].copy_from_slice(&3u16.to_le_bytes());
+        header[18..20].copy_from_slice(&62u16.to_le_bytes());
+        assert_eq!(
+            ElfProbe::from_bytes(&header),
+            ElfProbe {
+                is_elf: true,
+                class: Some(ElfClass::Elf64),
+                machine: Some(62),
+                etype: Some(3),
+            }
+        );
+
+        header[4] = 1;
+        header[5] = 2;
+        header[16..18].copy_from_slice(&2u16.to_be_bytes());
+        header[18..20].copy_from_slice(&40u16.to_be_bytes());
+        assert_eq!(
+            ElfProbe::from_bytes(&header),
+            ElfProbe {
+                is_elf: true,
+                class: Some(ElfClass::Elf32),
+                machine: Some(40),
+                etype: Some(2),
+            }
+        );
+
+        // A truncated header still gets recognized as ELF.
+        let probe = ElfProbe::from_bytes(&header[..8]);
+        assert!(probe.is_elf);
+        assert_eq!(probe.etype, None);
+    }
+
+    #[test]
+    fn test_probe_self() {
+        let pid = Pid::from(std::process::id());
+        let maps = from_pid(pid)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let exe_base = &main_executable_maps(pid).unwrap()[0];
+        let probe = exe_base.probe_elf(pid).unwrap();
+        assert!(probe.is_elf);
+        assert!(probe.etype.is_some());
+
+        // libc isn't guaranteed to be mapped (e.g. in a static build).
+        if let Some(libc) = maps.iter().find(|m| {
+            m.offset == 0 && matches!(&m.pathname, Pathname::Path(p) if p.contains("libc.so") || p.contains("libc-"))
+        }) {
+            let probe = libc.probe_elf(pid).unwrap();
+            assert!(probe.is_elf);
+            assert_eq!(probe.etype, Some(3));
+            assert_eq!(probe.class, exe_base.probe_elf(pid).unwrap().class);
+        }
+
+        let non_base = maps.iter().find(|m| m.offset != 0).unwrap();
+        assert!(matches!(non_base.probe_elf(pid), Err(Error::NotModuleBase)));
+    }
+}
@@ -3,14 +3,18 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::mem;
 use std::num;
+use std::path::PathBuf;
+use std::process::ExitStatus;
 
 use pest::error::Error as PestError;
 
-use crate::Rule;
+use crate::{AddressRange, Device, Pid, Rule};
 
 /// An enumeration of possible error states for `rsprocmaps`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// An I/O error.
     Io(io::Error),
@@ -23,6 +27,87 @@ pub enum Error {
     // at the pest/actual parsing level.
     /// An integer-width parsing error.
     WidthError(num::ParseIntError),
+    /// A map's backing file no longer matches the map, i.e. it was replaced
+    /// after being mapped. Each side is a `(device, inode)` pair.
+    BackingFileChanged {
+        /// The device and inode recorded in the map.
+        expected: (Device, u64),
+        /// The device and inode of the file currently at the map's path.
+        found: (Device, u64),
+    },
+    /// A map's backing file has been deleted, so it can't be opened by path.
+    /// The mapped contents may still be reachable via `/proc/<pid>/map_files/`.
+    DeletedBackingFile(String),
+    /// A map doesn't have a backing file (e.g. it's anonymous or a pseudo-path).
+    NotFileBacked,
+    /// A map isn't the base of its module, i.e. it doesn't begin at file offset 0.
+    NotModuleBase,
+    /// A malformed permission pattern.
+    InvalidPattern(String),
+    /// A counter was missing from one of the kernel's accounting files,
+    /// e.g. `VmSize` from `/proc/<pid>/status`.
+    MissingCounter(String),
+    /// An integer didn't fit into another representation, e.g. when converting
+    /// a map into another crate's types.
+    ConversionError(num::TryFromIntError),
+    /// A process in another pid namespace couldn't be accessed, e.g. because
+    /// either pid doesn't exist or we lack permission to enter the host process's root.
+    NamespaceInaccessible {
+        /// The path that couldn't be opened.
+        path: PathBuf,
+        /// The underlying I/O error.
+        source: io::Error,
+    },
+    /// Maps weren't sorted by address. The map at `index` begins before the one preceding it.
+    Unsorted {
+        /// The index of the first out-of-order map.
+        index: usize,
+        /// The address range of the preceding map.
+        previous: AddressRange,
+        /// The address range of the out-of-order map.
+        found: AddressRange,
+    },
+    /// A callback passed to a monitor panicked, with the given message.
+    CallbackPanicked(String),
+    /// The process with the given pid exists, but is hidden from us by procfs's
+    /// `hidepid` mount option.
+    Hidden(Pid),
+    /// A size or offset doesn't fit in a `usize` on this platform.
+    TooLarge {
+        /// What the value describes, e.g. "map size".
+        what: &'static str,
+        /// The value that didn't fit.
+        value: u64,
+    },
+    /// A region in another platform's native listing (e.g. FreeBSD's `procstat -v`
+    /// or macOS's `vmmap`) couldn't be parsed.
+    MalformedRegion(String),
+    /// An external command that produces a native listing (e.g. `procstat` or
+    /// `vmmap`) exited unsuccessfully, e.g. because the process is gone or
+    /// we lack permission to inspect it.
+    CommandFailed {
+        /// The command's name.
+        command: &'static str,
+        /// The command's exit status.
+        status: ExitStatus,
+        /// The command's standard error, without surrounding whitespace.
+        stderr: String,
+    },
+    /// A value isn't a valid pid, e.g. because it's negative.
+    InvalidPid(i64),
+    /// A line that should contain a map is empty, or only whitespace.
+    EmptyLine,
+    /// An address range would end before it begins.
+    InvalidRange {
+        /// The range's start.
+        begin: u64,
+        /// The range's (earlier) end.
+        end: u64,
+    },
+    /// A line of a `mountinfo` file couldn't be parsed.
+    MalformedMountInfo(String),
+    /// A human-readable size (e.g. `2MiB`) couldn't be parsed.
+    InvalidSize(String),
 }
 
 impl From<io::Error> for Error {
@@ -43,22 +128,148 @@ impl From<num::ParseIntError> for Error {
     }
 }
 
+impl From<num::TryFromIntError> for Error {
+    fn from(err: num::TryFromIntError) -> Error {
+        Error::ConversionError(err)
+    }
+}
+
+/// Converts an `Error` into an `io::Error`, for propagating with `?` in functions
+/// that return `io::Result`.
+///
+/// `Error::Io` is unwrapped, and `Error::NamespaceInaccessible` keeps the kind of
+/// its underlying I/O error. Everything else is wrapped with the closest
+/// `io::ErrorKind`, e.g. `InvalidData` for parse errors.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io(e) => return e,
+            Error::NamespaceInaccessible { ref source, .. } => source.kind(),
+            Error::ParseError(_)
+            | Error::WidthError(_)
+            | Error::ConversionError(_)
+            | Error::MissingCounter(_)
+            | Error::Unsorted { .. }
+            | Error::TooLarge { .. }
+            | Error::MalformedRegion(_)
+            | Error::EmptyLine
+            | Error::MalformedMountInfo(_) => io::ErrorKind::InvalidData,
+            Error::NotFileBacked
+            | Error::NotModuleBase
+            | Error::InvalidPattern(_)
+            | Error::InvalidSize(_)
+            | Error::InvalidPid(_)
+            | Error::InvalidRange { .. } => io::ErrorKind::InvalidInput,
+            Error::DeletedBackingFile(_) => io::ErrorKind::NotFound,
+            Error::Hidden(_) => io::ErrorKind::PermissionDenied,
+            Error::BackingFileChanged { .. }
+            | Error::CallbackPanicked(_)
+            | Error::CommandFailed { .. } => io::ErrorKind::Other,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Io(ref e) => e.fmt(f),
             Error::ParseError(ref e) => e.fmt(f),
             Error::WidthError(ref e) => e.fmt(f),
+            Error::BackingFileChanged {
+                ref expected,
+                ref found,
+            } => write!(
+                f,
+                "backing file changed: expected {} inode {}, found {} inode {}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            Error::DeletedBackingFile(ref path) => write!(
+                f,
+                "backing file {} has been deleted; try /proc/<pid>/map_files/ instead",
+                path
+            ),
+            Error::NotFileBacked => write!(f, "map is not backed by a file"),
+            Error::NotModuleBase => write!(f, "map is not the base of its module"),
+            Error::InvalidPattern(ref pattern) => {
+                write!(f, "invalid permission pattern: {:?}", pattern)
+            }
+            Error::MissingCounter(ref counter) => write!(f, "missing counter: {}", counter),
+            Error::ConversionError(ref e) => e.fmt(f),
+            Error::NamespaceInaccessible {
+                ref path,
+                ref source,
+            } => write!(f, "can't access {}: {}", path.display(), source),
+            Error::Unsorted {
+                index,
+                ref previous,
+                ref found,
+            } => write!(
+                f,
+                "maps aren't sorted by address: map {} ({}) begins before the preceding map ({})",
+                index, found, previous
+            ),
+            Error::CallbackPanicked(ref message) => write!(f, "callback panicked: {}", message),
+            Error::Hidden(pid) => write!(f, "process {} is hidden by procfs (hidepid)", pid),
+            Error::TooLarge { what, value } => {
+                write!(f, "{} ({}) is too large for this platform", what, value)
+            }
+            Error::MalformedRegion(ref region) => write!(f, "malformed region: {}", region),
+            Error::CommandFailed {
+                command,
+                status,
+                ref stderr,
+            } => write!(f, "{} failed ({}): {}", command, status, stderr),
+            Error::InvalidPid(pid) => write!(f, "invalid pid: {}", pid),
+            Error::EmptyLine => write!(f, "empty line"),
+            Error::MalformedMountInfo(ref line) => write!(f, "malformed mountinfo line: {}", line),
+            Error::InvalidSize(ref size) => write!(f, "invalid size: {:?}", size),
+            Error::InvalidRange { begin, end } => write!(
+                f,
+                "invalid address range: {:x}-{:x} ends before it begins",
+                begin, end
+            ),
         }
     }
 }
 
+/// Errors are equal if they're the same variant and have the same message.
+///
+/// This is intended for test assertions: the underlying I/O and parse errors
+/// don't implement `PartialEq`, so e.g. two `Error::Io`s with different OS error
+/// codes but the same message compare equal.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        mem::discriminant(self) == mem::discriminant(other) && self.to_string() == other.to_string()
+    }
+}
+
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             Error::Io(ref e) => Some(e),
             Error::ParseError(ref e) => Some(e),
             Error::WidthError(ref e) => Some(e),
+            Error::BackingFileChanged { .. } => None,
+            Error::DeletedBackingFile(_) => None,
+            Error::NotFileBacked => None,
+            Error::NotModuleBase => None,
+            Error::InvalidPattern(_) => None,
+            Error::MissingCounter(_) => None,
+            Error::ConversionError(ref e) => Some(e),
+            Error::NamespaceInaccessible { ref source, .. } => Some(source),
+            Error::Unsorted { .. } => None,
+            Error::CallbackPanicked(_) => None,
+            Error::Hidden(_) => None,
+            Error::TooLarge { .. } => None,
+            Error::MalformedRegion(_) => None,
+            Error::CommandFailed { .. } => None,
+            Error::InvalidPid(_) => None,
+            Error::EmptyLine => None,
+            Error::InvalidRange { .. } => None,
+            Error::MalformedMountInfo(_) => None,
+            Error::InvalidSize(_) => None,
         }
     }
 }
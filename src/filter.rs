@@ -0,0 +1,169 @@
+//! Composable predicates for filtering maps.
+
+use std::fmt;
+
+use crate::{AddressRange, Map, Pathname};
+
+type Predicate = Box<dyn Fn(&Map) -> bool + Send + Sync>;
+
+/// A builder for predicates over `Map`s.
+///
+/// Each builder method adds a predicate; a map matches the filter only
+/// if it matches every predicate. An empty filter matches every map.
+///
+/// ```rust
+/// use rsprocmaps::filter::MapFilter;
+/// use rsprocmaps::AddressRange;
+///
+/// let filter = MapFilter::new()
+///     .executable()
+///     .file_backed()
+///     .within(AddressRange { begin: 0x1000, end: 0x8000 });
+/// ```
+#[derive(Default)]
+pub struct MapFilter {
+    predicates: Vec<Predicate>,
+}
+
+impl MapFilter {
+    /// Creates a new, empty `MapFilter`.
+    pub fn new() -> MapFilter {
+        Default::default()
+    }
+
+    /// Adds an arbitrary predicate to this filter.
+    pub fn matching<F>(mut self, predicate: F) -> MapFilter
+    where
+        F: Fn(&Map) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Matches only readable maps.
+    pub fn readable(self) -> MapFilter {
+        self.matching(|m| m.permissions.readable)
+    }
+
+    /// Matches only writable maps.
+    pub fn writable(self) -> MapFilter {
+        self.matching(|m| m.permissions.writable)
+    }
+
+    /// Matches only executable maps.
+    pub fn executable(self) -> MapFilter {
+        self.matching(|m| m.permissions.executable)
+    }
+
+    /// Matches only shared maps.
+    pub fn shared(self) -> MapFilter {
+        self.matching(|m| m.permissions.shared)
+    }
+
+    /// Matches only private maps.
+    pub fn private(self) -> MapFilter {
+        self.matching(|m| m.permissions.private)
+    }
+
+    /// Matches only maps that appear to be backed by a file.
+    pub fn file_backed(self) -> MapFilter {
+        self.matching(|m| matches!(m.pathname, Pathname::Path(_)))
+    }
+
+    /// Matches only anonymous maps.
+    pub fn anonymous(self) -> MapFilter {
+        self.matching(|m| m.pathname == Pathname::Mmap)
+    }
+
+    /// Matches only maps that lie entirely within `range`.
+    pub fn within(self, range: AddressRange) -> MapFilter {
+        self.matching(move |m| {
+            range.begin <= m.address_range.begin && m.address_range.end <= range.end
+        })
+    }
+
+    /// Returns whether `map` matches every predicate in this filter.
+    pub fn matches(&self, map: &Map) -> bool {
+        self.predicates.iter().all(|p| p(map))
+    }
+
+    /// Consumes this filter, returning it as a plain closure.
+    pub fn into_fn(self) -> impl Fn(&Map) -> bool {
+        move |map| self.matches(map)
+    }
+}
+
+impl fmt::Debug for MapFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapFilter")
+            .field("predicates", &self.predicates.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::from_path;
+
+    fn golden() -> Vec<Map> {
+        from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let maps = golden();
+        let filter = MapFilter::new();
+
+        assert!(maps.iter().all(|m| filter.matches(m)));
+    }
+
+    #[test]
+    fn test_combined_filter() {
+        let maps = golden();
+
+        let filter = MapFilter::new().executable().file_backed();
+        let matched = maps
+            .iter()
+            .filter(|m| filter.matches(m))
+            .collect::<Vec<_>>();
+        assert_eq!(matched.len(), 3);
+        assert!(matched
+            .iter()
+            .all(|m| m.permissions.executable && matches!(m.pathname, Pathname::Path(_))));
+
+        // Narrowing to /bin/cat's address range leaves only its text segment.
+        let filter = MapFilter::new()
+            .executable()
+            .file_backed()
+            .within(AddressRange {
+                begin: 0x5610d526d000,
+                end: 0x5610d5279000,
+            });
+        let is_match = filter.into_fn();
+        let matched = maps.iter().filter(|m| is_match(m)).collect::<Vec<_>>();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].address_range.begin, 0x5610d526f000);
+
+        let filter = MapFilter::new().anonymous().writable().readable();
+        assert_eq!(maps.iter().filter(|m| filter.matches(m)).count(), 3);
+    }
+
+    #[test]
+    fn test_filter_with() {
+        let filter = MapFilter::new().executable();
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .filter_with(filter)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(maps.len(), 5);
+        assert!(maps.iter().all(|m| m.permissions.executable));
+    }
+}
@@ -0,0 +1,222 @@
+//! Human-oriented formatting (and parsing) of sizes, addresses, and permissions.
+//!
+//! Everything here is fixed-format, independent of locale, so that reports and
+//! summaries built on top of this crate look the same everywhere.
+
+use crate::error::Error;
+use crate::Permissions;
+
+/// The binary units used by [`size`], in ascending order.
+const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Formats a size in bytes with binary units, e.g. `512 B` or `1.8 MiB`.
+///
+/// Sizes of at least 1 KiB are rounded to one decimal place in the largest unit
+/// that they fill, with halves rounded up. A size that rounds up to 1024 of one
+/// unit is shown in the next unit instead, e.g. `1.0 MiB` rather than `1024.0 KiB`.
+pub fn size(bytes: u64) -> String {
+    let mut unit = 0;
+    while unit + 1 < UNITS.len() && bytes >= 1 << (10 * (unit + 1)) {
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} B", bytes);
+    }
+
+    let tenths = |unit: usize| {
+        let divisor = 1u128 << (10 * unit);
+        ((bytes as u128 * 10 + divisor / 2) / divisor) as u64
+    };
+
+    let mut rounded = tenths(unit);
+    if rounded >= 10240 && unit + 1 < UNITS.len() {
+        unit += 1;
+        rounded = tenths(unit);
+    }
+
+    format!("{}.{} {}", rounded / 10, rounded % 10, UNITS[unit])
+}
+
+/// Formats an address in hex with a `0x` prefix, e.g. `0x7f1c2e400000`.
+///
+/// With `grouping`, the digits are separated into groups of four with underscores,
+/// as in a Rust literal: `0x7f1c_2e40_0000`.
+pub fn addr(a: u64, grouping: bool) -> String {
+    let digits = format!("{:x}", a);
+    if !grouping {
+        return format!("0x{}", digits);
+    }
+
+    let mut grouped = String::from("0x");
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 4 == 0 {
+            grouped.push('_');
+        }
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+/// Formats permissions as the kernel does, e.g. `r-xp`.
+///
+/// Permissions that are neither shared nor private (which the kernel never
+/// emits) have a `-` in the last position.
+pub fn perms(permissions: &Permissions) -> String {
+    [
+        if permissions.readable { 'r' } else { '-' },
+        if permissions.writable { 'w' } else { '-' },
+        if permissions.executable { 'x' } else { '-' },
+        if permissions.shared {
+            's'
+        } else if permissions.private {
+            'p'
+        } else {
+            '-'
+        },
+    ]
+    .iter()
+    .collect()
+}
+
+/// Parses a human-readable size into bytes, e.g. `4096`, `64K`, `2MiB`, or `1 GB`.
+///
+/// The size is a whole number, optionally followed (after any whitespace) by a
+/// unit of `K`, `M`, or `G` (in either case), which can in turn be followed by
+/// an `i`, a `B`, or both. A bare `B` is also accepted. Every unit is binary, as
+/// in procfs (where `kB` means 1024 bytes), so `2MB` and `2MiB` are the same size.
+///
+/// Returns [`Error::InvalidSize`] on anything else, including sizes that
+/// overflow a `u64`.
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+    let invalid = || Error::InvalidSize(s.into());
+
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    if number.is_empty() {
+        return Err(invalid());
+    }
+
+    let unit = unit.trim_start();
+    let shift = match unit.as_bytes() {
+        [] | [b'B'] => 0,
+        [prefix] | [prefix, b'i'] | [prefix, b'B'] | [prefix, b'i', b'B'] => {
+            match prefix.to_ascii_uppercase() {
+                b'K' => 10,
+                b'M' => 20,
+                b'G' => 30,
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(size(0), "0 B");
+        assert_eq!(size(1), "1 B");
+        assert_eq!(size(1023), "1023 B");
+        assert_eq!(size(1024), "1.0 KiB");
+        assert_eq!(size(1536), "1.5 KiB");
+        assert_eq!(size(4096), "4.0 KiB");
+        assert_eq!(size(1887437), "1.8 MiB");
+        assert_eq!(size(3 << 30), "3.0 GiB");
+        assert_eq!(size(5 << 40), "5.0 TiB");
+        assert_eq!(size(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    fn test_size_rounding() {
+        // 1.04 KiB rounds down, 1.05 KiB rounds up.
+        assert_eq!(size(1064), "1.0 KiB");
+        assert_eq!(size(1075), "1.0 KiB");
+        assert_eq!(size(1076), "1.1 KiB");
+
+        // Just below a unit boundary rounds up into the next unit...
+        assert_eq!(size((1 << 20) - 1), "1.0 MiB");
+        assert_eq!(size((1 << 20) - 51), "1.0 MiB");
+        assert_eq!(size((1 << 30) - 1), "1.0 GiB");
+        // ...but only once it rounds to 1024.0.
+        assert_eq!(size((1 << 20) - 52), "1023.9 KiB");
+        assert_eq!(size((1 << 20) - 103), "1023.9 KiB");
+        assert_eq!(size((1 << 20) - 1024), "1023.0 KiB");
+
+        // At the boundary itself.
+        assert_eq!(size(1 << 20), "1.0 MiB");
+        assert_eq!(size((1 << 20) + 1), "1.0 MiB");
+    }
+
+    #[test]
+    fn test_addr() {
+        assert_eq!(addr(0x7f1c2e400000, false), "0x7f1c2e400000");
+        assert_eq!(addr(0x7f1c2e400000, true), "0x7f1c_2e40_0000");
+        assert_eq!(addr(0, false), "0x0");
+        assert_eq!(addr(0, true), "0x0");
+        assert_eq!(addr(0xffff, true), "0xffff");
+        assert_eq!(addr(0x10000, true), "0x1_0000");
+        assert_eq!(addr(u64::MAX, true), "0xffff_ffff_ffff_ffff");
+    }
+
+    #[test]
+    fn test_perms() {
+        for mask in ["r-xp", "rw-s", "---p", "rwxs"] {
+            assert_eq!(perms(&mask.parse().unwrap()), mask);
+        }
+        assert_eq!(perms(&Permissions::default()), "----");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("4096B").unwrap(), 4096);
+        assert_eq!(parse_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("64kB").unwrap(), 64 << 10);
+        assert_eq!(parse_size("2MiB").unwrap(), 2 << 20);
+        assert_eq!(parse_size("2Mi").unwrap(), 2 << 20);
+        assert_eq!(parse_size("2MB").unwrap(), 2 << 20);
+        assert_eq!(parse_size(" 1 GiB ").unwrap(), 1 << 30);
+        assert_eq!(parse_size("16G").unwrap(), 16 << 30);
+    }
+
+    #[test]
+    fn test_parse_size_invalid() {
+        for bad in [
+            "", " ", "K", "MiB", "-1", "1.5M", "2T", "2 MiBs", "2iB", "2BB", "0x10", "2 M B",
+        ] {
+            assert_eq!(
+                parse_size(bad),
+                Err(Error::InvalidSize(bad.into())),
+                "{}",
+                bad
+            );
+        }
+
+        // Overflow, both in the number and once the unit is applied.
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("17179869184G").is_err());
+        assert_eq!(parse_size("16777215G").unwrap(), 16777215 << 30);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for bytes in [0, 1, 1 << 10, 1 << 20, 1 << 30] {
+            let formatted = size(bytes).replace(".0", "");
+            assert_eq!(parse_size(&formatted).unwrap(), bytes);
+        }
+    }
+}
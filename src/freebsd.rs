@@ -0,0 +1,181 @@
+//! Support for reading memory maps on FreeBSD.
+//!
+//! FreeBSD has no `/proc/<pid>/maps` (unless `linprocfs` is mounted); its native
+//! source of region information is `kinfo_getvmmap(3)`, as surfaced by
+//! `procstat -v`. Calling `kinfo_getvmmap` directly requires `unsafe`, so this
+//! module runs `procstat -v` and parses its output instead.
+//!
+//! `procstat` reports less than the Linux maps format, so some of each `Map` is
+//! necessarily synthesized:
+//!
+//! * `offset`, `device`, and `inode` are always zero.
+//! * `private` is set for copy-on-write regions (the `C` flag), as well as for
+//!   guard and objectless regions, which can't be shared; `shared` is its inverse.
+//! * Regions that grow down (the `D` flag) are `Stack`; other regions without a path
+//!   are `Mmap`, including the shared page that holds the signal trampoline.
+
+use std::process::Command;
+
+use crate::error::Error;
+use crate::{run_command, AddressRange, Map, Pathname, Permissions, Pid};
+
+/// Returns the maps for the given pid, as reported by `procstat -v`.
+///
+/// Fails with [`Error::CommandFailed`] if `procstat` does, e.g. because the
+/// process doesn't exist.
+pub fn from_pid(pid: impl Into<Pid>) -> Result<Vec<Map>, Error> {
+    let output = run_command(
+        "procstat",
+        Command::new("procstat")
+            .arg("-v")
+            .arg(pid.into().validate()?.to_string()),
+    )?;
+
+    parse_procstat(&output)
+}
+
+/// Parses the output of `procstat -v` into maps.
+///
+/// The header line is skipped, as are blank lines.
+pub fn parse_procstat(output: &str) -> Result<Vec<Map>, Error> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with("PID"))
+        .map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<Map, Error> {
+    let malformed = || Error::MalformedRegion(line.into());
+
+    // PID START END PRT RES PRES REF SHD FLAG TP PATH
+    let mut rest = line;
+    let mut field = || {
+        let trimmed = rest.trim_start();
+        let len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        let (field, tail) = trimmed.split_at(len);
+        rest = tail;
+
+        if field.is_empty() {
+            Err(malformed())
+        } else {
+            Ok(field)
+        }
+    };
+
+    let _pid = field()?;
+    let begin = parse_hex(field()?).ok_or_else(malformed)?;
+    let end = parse_hex(field()?).ok_or_else(malformed)?;
+    let prot = field()?.as_bytes();
+    for _ in 0..4 {
+        field()?;
+    }
+    let flags = field()?;
+    let kind = field()?;
+
+    if prot.len() != 3 {
+        return Err(malformed());
+    }
+
+    // NOTE(ww): The path is the rest of the line, and may contain spaces.
+    let path = rest.trim();
+
+    let private = flags.contains('C') || kind == "gd" || kind == "--";
+    let pathname = if !path.is_empty() {
+        Pathname::Path(path.into())
+    } else if flags.contains('D') {
+        Pathname::Stack
+    } else {
+        Pathname::Mmap
+    };
+
+    Ok(Map {
+        address_range: AddressRange { begin, end },
+        permissions: Permissions {
+            readable: prot[0] == b'r',
+            writable: prot[1] == b'w',
+            executable: prot[2] == b'x',
+            shared: !private,
+            private,
+        },
+        pathname,
+        ..Default::default()
+    })
+}
+
+fn parse_hex(field: &str) -> Option<u64> {
+    u64::from_str_radix(field.strip_prefix("0x")?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCSTAT: &str =
+        "  PID              START                END PRT  RES PRES REF SHD FLAG  TP PATH
+72136           0x200000           0x201000 r--    1    3   2   1 CN--- vn /bin/cat
+72136           0x201000           0x203000 r-x    2    3   2   1 CN--- vn /bin/cat
+72136        0x800210000        0x800211000 rw-    1    1   1   0 C---- sw
+72136        0x800400000        0x800600000 rw-    0    0   1   0 ----- vn /tmp/my file
+72136     0x7fffdfffe000     0x7ffffffdf000 ---    0    0   0   0 ----- gd
+72136     0x7ffffffdf000     0x7ffffffff000 rw-    2    2   1   0 C--D- sw
+72136     0x7ffffffff000     0x800000000000 r-x    1    1  33   0 ----- ph
+";
+
+    #[test]
+    fn test_parse_procstat() {
+        let maps = parse_procstat(PROCSTAT).unwrap();
+        assert_eq!(maps.len(), 7);
+
+        assert_eq!(
+            maps[1].address_range,
+            AddressRange {
+                begin: 0x201000,
+                end: 0x203000
+            }
+        );
+        assert_eq!(maps[1].permissions, Permissions::RX_PRIVATE);
+        assert_eq!(maps[1].pathname, Pathname::Path("/bin/cat".into()));
+        assert_eq!((maps[1].offset, maps[1].inode), (0, 0));
+
+        assert_eq!(maps[2].pathname, Pathname::Mmap);
+        assert!(maps[2].permissions.private);
+
+        assert_eq!(maps[3].pathname, Pathname::Path("/tmp/my file".into()));
+        assert!(maps[3].permissions.shared);
+
+        assert!(maps[4].is_guard());
+        assert_eq!(maps[5].pathname, Pathname::Stack);
+        assert_eq!(maps[6].pathname, Pathname::Mmap);
+    }
+
+    #[test]
+    fn test_parse_procstat_malformed() {
+        for bad in [
+            "72136 0x200000",
+            "72136 200000 201000 r-- 1 3 2 1 CN--- vn /bin/cat",
+            "72136 0x200000 0x201000 r-xp 1 3 2 1 CN--- vn /bin/cat",
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                parse_procstat(bad),
+                Err(Error::MalformedRegion(_))
+            ));
+        }
+
+        assert!(parse_procstat("").unwrap().is_empty());
+    }
+
+    #[cfg(target_os = "freebsd")]
+    #[test]
+    fn test_from_pid_self() {
+        let maps = from_pid(std::process::id()).unwrap();
+        let exe = std::env::current_exe().unwrap();
+
+        assert!(maps.iter().any(|m| m.pathname == Pathname::Stack));
+        assert!(maps
+            .iter()
+            .any(|m| m.pathname == Pathname::Path(exe.to_str().unwrap().into())));
+    }
+}
@@ -0,0 +1,408 @@
+//! Attributing anonymous memory growth between two snapshots of a process.
+//!
+//! This is the first step of leak triage without symbols: given snapshots taken
+//! before and after some workload, which anonymous regions grew, shrank, or appeared?
+//! Since both snapshots come from the same process, addresses are stable (ASLR is
+//! applied once, at exec) and can be used to match regions across them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::category::MemoryCategory;
+use crate::snapshot::MapsSnapshot;
+use crate::{AddressRange, Map, Pathname};
+
+/// The size above which [`growth_report`] lists a newly appeared region as large.
+pub const DEFAULT_LARGE_REGION_THRESHOLD: u64 = 1024 * 1024;
+
+/// The change in size of a region that appears in both snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RegionDelta {
+    /// The span of the region in the older snapshot.
+    pub before: AddressRange,
+    /// The span of the region in the newer snapshot.
+    pub after: AddressRange,
+    /// The region's label (e.g. `[heap]` or `[anon:libc_malloc]`), if it has one.
+    pub label: Option<String>,
+    /// The region's mapped bytes in the older snapshot.
+    pub old_bytes: u64,
+    /// The region's mapped bytes in the newer snapshot.
+    pub new_bytes: u64,
+    /// `new_bytes - old_bytes`; negative if the region shrank.
+    pub delta: i64,
+}
+
+/// Formats the delta on one line, e.g. `[heap]: 132.0 KiB -> 260.0 KiB (+128.0 KiB)`.
+///
+/// Regions without a label are shown by their span in the newer snapshot.
+impl fmt::Display for RegionDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{}", label)?,
+            None => write!(
+                f,
+                "{}-{}",
+                crate::fmt::addr(self.after.begin, false),
+                crate::fmt::addr(self.after.end, false)
+            )?,
+        }
+
+        write!(
+            f,
+            ": {} -> {} ({})",
+            crate::fmt::size(self.old_bytes),
+            crate::fmt::size(self.new_bytes),
+            signed_size(self.delta)
+        )
+    }
+}
+
+/// The anonymous memory growth between two snapshots, as returned by [`growth_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GrowthReport {
+    /// The total anonymous bytes in the older snapshot.
+    pub total_before: u64,
+    /// The total anonymous bytes in the newer snapshot.
+    pub total_after: u64,
+    /// The regions that changed size, largest growth first.
+    pub deltas: Vec<RegionDelta>,
+    /// The regions that only appear in the newer snapshot and are at least the
+    /// threshold in size, largest first.
+    pub new_large_regions: Vec<Map>,
+}
+
+impl GrowthReport {
+    /// Returns the net change in anonymous bytes between the two snapshots.
+    pub fn net_growth(&self) -> i64 {
+        self.total_after as i64 - self.total_before as i64
+    }
+}
+
+/// Formats the report over several lines: the totals, then each delta, then
+/// each new large region.
+impl fmt::Display for GrowthReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "anonymous: {} -> {} ({})",
+            crate::fmt::size(self.total_before),
+            crate::fmt::size(self.total_after),
+            signed_size(self.net_growth())
+        )?;
+
+        for delta in &self.deltas {
+            writeln!(f, "  {}", delta)?;
+        }
+
+        for map in &self.new_large_regions {
+            writeln!(
+                f,
+                "  new: {}-{} ({})",
+                crate::fmt::addr(map.address_range.begin, false),
+                crate::fmt::addr(map.address_range.end, false),
+                crate::fmt::size(size(map))
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reports how the anonymous memory (including the heap) changed from `old` to `new`,
+/// listing new regions of at least [`DEFAULT_LARGE_REGION_THRESHOLD`] bytes.
+///
+/// See [`growth_report_with_threshold`].
+pub fn growth_report(old: &MapsSnapshot, new: &MapsSnapshot) -> GrowthReport {
+    growth_report_with_threshold(old, new, DEFAULT_LARGE_REGION_THRESHOLD)
+}
+
+/// Reports how the anonymous memory (including the heap) changed from `old` to `new`,
+/// listing new regions of at least `threshold` bytes.
+///
+/// Regions are matched across the snapshots by overlapping address ranges, so a
+/// region that was split, merged, or resized in place is netted as a single delta.
+/// Regions that don't overlap anything in the other snapshot are then matched by
+/// label (e.g. an `[anon:label]` that was unmapped and mapped again elsewhere).
+/// Whatever remains in the newer snapshot is new.
+pub fn growth_report_with_threshold(
+    old: &MapsSnapshot,
+    new: &MapsSnapshot,
+    threshold: u64,
+) -> GrowthReport {
+    let old = anonymous(old);
+    let new = anonymous(new);
+
+    let mut report = GrowthReport {
+        total_before: old.iter().map(|m| size(m)).sum(),
+        total_after: new.iter().map(|m| size(m)).sum(),
+        ..Default::default()
+    };
+
+    let mut unmatched_old = vec![];
+    let mut unmatched_new = vec![];
+    for cluster in overlapping(&old, &new) {
+        match (cluster.old.is_empty(), cluster.new.is_empty()) {
+            (false, false) => report.deltas.push(delta(&cluster.old, &cluster.new)),
+            (false, true) => unmatched_old.extend(cluster.old),
+            (true, false) => unmatched_new.extend(cluster.new),
+            (true, true) => unreachable!(),
+        }
+    }
+
+    let mut by_label = BTreeMap::<String, (Vec<&Map>, Vec<&Map>)>::new();
+    for map in &unmatched_old {
+        if let Some(label) = label(map) {
+            by_label.entry(label).or_default().0.push(map);
+        }
+    }
+    for map in &unmatched_new {
+        if let Some(label) = label(map) {
+            by_label.entry(label).or_default().1.push(map);
+        }
+    }
+
+    let mut relabeled = vec![];
+    for (old, new) in by_label.into_values() {
+        if !old.is_empty() && !new.is_empty() {
+            report.deltas.push(delta(&old, &new));
+            relabeled.extend(new.into_iter().map(|m| m.address_range.begin));
+        }
+    }
+
+    report.deltas.retain(|d| d.delta != 0);
+    report.deltas.sort_by_key(|d| std::cmp::Reverse(d.delta));
+
+    report.new_large_regions = unmatched_new
+        .into_iter()
+        .filter(|m| !relabeled.contains(&m.address_range.begin) && size(m) >= threshold)
+        .cloned()
+        .collect();
+    report
+        .new_large_regions
+        .sort_by_key(|m| std::cmp::Reverse(size(m)));
+
+    report
+}
+
+/// Maps from both snapshots whose address ranges transitively overlap.
+#[derive(Default)]
+struct Cluster<'a> {
+    old: Vec<&'a Map>,
+    new: Vec<&'a Map>,
+}
+
+/// Returns the anonymous and heap maps in `snapshot`, in address order.
+fn anonymous(snapshot: &MapsSnapshot) -> Vec<&Map> {
+    let mut maps = snapshot
+        .iter()
+        .filter(|m| {
+            matches!(
+                m.category(),
+                MemoryCategory::Anonymous | MemoryCategory::Heap
+            ) && size(m) > 0
+        })
+        .collect::<Vec<_>>();
+    maps.sort_by_key(|m| m.address_range.begin);
+    maps
+}
+
+/// Groups the maps from both sides into clusters of (transitively) overlapping maps.
+fn overlapping<'a>(old: &[&'a Map], new: &[&'a Map]) -> Vec<Cluster<'a>> {
+    let mut all = old
+        .iter()
+        .map(|m| (false, *m))
+        .chain(new.iter().map(|m| (true, *m)))
+        .collect::<Vec<_>>();
+    all.sort_by_key(|(_, m)| m.address_range.begin);
+
+    let mut clusters: Vec<Cluster> = vec![];
+    let mut end = 0;
+    for (is_new, map) in all {
+        if clusters.is_empty() || map.address_range.begin >= end {
+            clusters.push(Default::default());
+        }
+        end = end.max(map.address_range.end);
+
+        let cluster = clusters.last_mut().unwrap();
+        if is_new {
+            cluster.new.push(map);
+        } else {
+            cluster.old.push(map);
+        }
+    }
+
+    clusters
+}
+
+fn delta(old: &[&Map], new: &[&Map]) -> RegionDelta {
+    let old_bytes = old.iter().map(|m| size(m)).sum::<u64>();
+    let new_bytes = new.iter().map(|m| size(m)).sum::<u64>();
+
+    RegionDelta {
+        before: span(old),
+        after: span(new),
+        label: new.iter().chain(old).find_map(|m| label(m)),
+        old_bytes,
+        new_bytes,
+        delta: new_bytes as i64 - old_bytes as i64,
+    }
+}
+
+fn span(maps: &[&Map]) -> AddressRange {
+    AddressRange {
+        begin: maps.iter().map(|m| m.address_range.begin).min().unwrap(),
+        end: maps.iter().map(|m| m.address_range.end).max().unwrap(),
+    }
+}
+
+fn size(map: &Map) -> u64 {
+    map.address_range.len()
+}
+
+/// Formats a change in bytes with an explicit sign, e.g. `+1.0 MiB` or `-4.0 KiB`.
+fn signed_size(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{}{}", sign, crate::fmt::size(delta.unsigned_abs()))
+}
+
+fn label(map: &Map) -> Option<String> {
+    match &map.pathname {
+        Pathname::Heap => Some(map.pathname.to_string()),
+        Pathname::OtherPseudo(pseudo) if pseudo.starts_with("[anon:") => Some(pseudo.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn snapshot(input: &str) -> MapsSnapshot {
+        from_str(input).collect::<Result<_, _>>().unwrap()
+    }
+
+    fn old() -> MapsSnapshot {
+        snapshot(
+            "00400000-00401000 r-xp 00000000 08:01 1 /bin/leaky\n\
+             01000000-01021000 rw-p 00000000 00:00 0 [heap]\n\
+             10000000-10100000 rw-p 00000000 00:00 0 \n\
+             20000000-20010000 rw-p 00000000 00:00 0 [anon:cache]\n\
+             30000000-30010000 rw-p 00000000 00:00 0 \n\
+             40000000-40010000 rw-p 00000000 00:00 0 \n",
+        )
+    }
+
+    fn new() -> MapsSnapshot {
+        snapshot(
+            // The heap grew by 0x20000, the arena at 0x10000000 was split (and grew
+            // by 0x100000), [anon:cache] moved and grew by 0x10000, the region at
+            // 0x30000000 is unchanged, the one at 0x40000000 was unmapped, and two
+            // regions are new.
+            "00400000-00401000 r-xp 00000000 08:01 1 /bin/leaky\n\
+             01000000-01041000 rw-p 00000000 00:00 0 [heap]\n\
+             10000000-10080000 rw-p 00000000 00:00 0 \n\
+             10080000-10200000 rw-p 00000000 00:00 0 \n\
+             28000000-28020000 rw-p 00000000 00:00 0 [anon:cache]\n\
+             30000000-30010000 rw-p 00000000 00:00 0 \n\
+             50000000-50400000 rw-p 00000000 00:00 0 \n\
+             60000000-60001000 rw-p 00000000 00:00 0 \n",
+        )
+    }
+
+    #[test]
+    fn test_growth_report() {
+        let report = growth_report(&old(), &new());
+
+        assert_eq!(report.total_before, 0x21000 + 0x100000 + 0x10000 * 3);
+        assert_eq!(
+            report.total_after,
+            0x41000 + 0x200000 + 0x20000 + 0x10000 + 0x400000 + 0x1000
+        );
+        assert_eq!(
+            report.net_growth(),
+            report.total_after as i64 - report.total_before as i64
+        );
+
+        assert_eq!(
+            report
+                .deltas
+                .iter()
+                .map(|d| (d.before.begin, d.after.begin, d.label.as_deref(), d.delta))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x10000000, 0x10000000, None, 0x100000),
+                (0x1000000, 0x1000000, Some("[heap]"), 0x20000),
+                (0x20000000, 0x28000000, Some("[anon:cache]"), 0x10000),
+            ]
+        );
+
+        let split = &report.deltas[0];
+        assert_eq!(
+            split.after,
+            AddressRange {
+                begin: 0x10000000,
+                end: 0x10200000
+            }
+        );
+        assert_eq!((split.old_bytes, split.new_bytes), (0x100000, 0x200000));
+
+        // Only the 4 MiB region is large enough to report.
+        assert_eq!(report.new_large_regions.len(), 1);
+        assert_eq!(report.new_large_regions[0].address_range.begin, 0x50000000);
+    }
+
+    #[test]
+    fn test_growth_report_threshold() {
+        let report = growth_report_with_threshold(&old(), &new(), 0x1000);
+        assert_eq!(
+            report
+                .new_large_regions
+                .iter()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x50000000, 0x60000000]
+        );
+
+        // Shrinking is a negative delta.
+        let report = growth_report(&new(), &old());
+        assert_eq!(report.deltas.last().unwrap().delta, -0x100000);
+        assert!(report.new_large_regions.is_empty());
+
+        assert_eq!(growth_report(&old(), &old()).deltas, vec![]);
+        assert_eq!(
+            growth_report(&MapsSnapshot::default(), &MapsSnapshot::default()),
+            GrowthReport::default()
+        );
+    }
+
+    #[test]
+    fn test_growth_report_display() {
+        assert_eq!(
+            growth_report(&old(), &new()).to_string(),
+            "anonymous: 1.3 MiB -> 6.4 MiB (+5.1 MiB)\n\
+             \x20 0x10000000-0x10200000: 1.0 MiB -> 2.0 MiB (+1.0 MiB)\n\
+             \x20 [heap]: 132.0 KiB -> 260.0 KiB (+128.0 KiB)\n\
+             \x20 [anon:cache]: 64.0 KiB -> 128.0 KiB (+64.0 KiB)\n\
+             \x20 new: 0x50000000-0x50400000 (4.0 MiB)\n"
+        );
+        assert_eq!(
+            growth_report(&new(), &old())
+                .deltas
+                .last()
+                .unwrap()
+                .to_string(),
+            "0x10000000-0x10100000: 2.0 MiB -> 1.0 MiB (-1.0 MiB)"
+        );
+    }
+
+    #[test]
+    fn test_growth_report_serialize() {
+        let report = growth_report(&old(), &new());
+        let json = serde_json::to_string(&report).unwrap();
+
+        assert_eq!(serde_json::from_str::<GrowthReport>(&json).unwrap(), report);
+    }
+}
@@ -0,0 +1,286 @@
+//! Measuring the savings from kernel same-page merging (KSM).
+//!
+//! With KSM enabled, identical anonymous pages (within or across processes) are
+//! merged into a single copy-on-write page, so naive RSS accounting counts them
+//! once per process that maps them. This module walks a process's page table via
+//! `/proc/<pid>/pagemap` and looks up each resident page's flags in
+//! `/proc/kpageflags`, which requires `CAP_SYS_ADMIN` (in practice, root).
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::category::MemoryCategory;
+use crate::error::Error;
+use crate::{from_pid, Map, Pid};
+
+/// Set in a pagemap entry if the page is resident.
+const PM_PRESENT: u64 = 1 << 63;
+/// The bits of a pagemap entry that hold the page frame number.
+const PM_PFN_MASK: u64 = (1 << 55) - 1;
+/// Set in a page's kpageflags if it's been merged by KSM.
+const KPF_KSM: u64 = 1 << 21;
+/// Set in a page's kpageflags if it's the zero page (or the huge zero page).
+const KPF_ZERO_PAGE: u64 = 1 << 24;
+
+/// The size of an entry in both pagemap and kpageflags.
+const ENTRY_SIZE: u64 = 8;
+/// The number of pagemap entries to read at once.
+const CHUNK_PAGES: u64 = 4096;
+
+/// KSM statistics for a single anonymous map.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KsmMap {
+    /// The map that these statistics are for.
+    pub map: Map,
+    /// The number of resident pages in the map that KSM has merged.
+    pub ksm_pages: u64,
+    /// An estimate of the bytes that the map shares via KSM, i.e. `ksm_pages`
+    /// times the page size.
+    pub ksm_bytes: u64,
+    /// The number of resident bytes in the map that are backed by the zero page.
+    pub zero_page_bytes: u64,
+}
+
+/// KSM statistics for a process, as returned by [`savings`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KsmReport {
+    /// The statistics for each of the process's anonymous maps, in address order.
+    pub maps: Vec<KsmMap>,
+    /// The total number of merged pages across all maps.
+    pub ksm_pages: u64,
+    /// The total estimated bytes shared via KSM across all maps.
+    pub ksm_bytes: u64,
+    /// The total resident bytes backed by the zero page across all maps.
+    pub zero_page_bytes: u64,
+}
+
+/// The number of merged and zero pages among some resident pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PageCounts {
+    ksm: u64,
+    zero: u64,
+}
+
+impl PageCounts {
+    /// Counts the merged and zero pages, given each resident page's kpageflags.
+    fn from_flags<I: IntoIterator<Item = u64>>(flags: I) -> PageCounts {
+        flags
+            .into_iter()
+            .fold(PageCounts::default(), |mut counts, flags| {
+                if flags & KPF_KSM != 0 {
+                    counts.ksm += 1;
+                }
+                if flags & KPF_ZERO_PAGE != 0 {
+                    counts.zero += 1;
+                }
+                counts
+            })
+    }
+}
+
+impl KsmReport {
+    fn from_counts(counts: Vec<(Map, PageCounts)>, page_size: u64) -> KsmReport {
+        let mut report = KsmReport::default();
+
+        for (map, counts) in counts {
+            let map = KsmMap {
+                map,
+                ksm_pages: counts.ksm,
+                ksm_bytes: counts.ksm * page_size,
+                zero_page_bytes: counts.zero * page_size,
+            };
+
+            report.ksm_pages += map.ksm_pages;
+            report.ksm_bytes += map.ksm_bytes;
+            report.zero_page_bytes += map.zero_page_bytes;
+            report.maps.push(map);
+        }
+
+        report
+    }
+}
+
+/// Reports how many of the given pid's anonymous pages have been merged by KSM,
+/// and how many are backed by the zero page.
+///
+/// Anonymous maps are those whose [`Map::category`] is anonymous memory, the heap,
+/// or a stack. Maps without any permissions (e.g. guard regions and address space
+/// reservations) are skipped, since they can be huge and are almost never resident.
+///
+/// This requires `CAP_SYS_ADMIN` to read `/proc/kpageflags`; without it, this
+/// returns an [`Error::Io`] with `io::ErrorKind::PermissionDenied`.
+pub fn savings(pid: impl Into<Pid>) -> Result<KsmReport, Error> {
+    let pid = pid.into().validate()?;
+
+    // NOTE(ww): We open kpageflags first, since without CAP_SYS_ADMIN the PFNs in
+    // pagemap are zeroed and everything else would succeed uselessly.
+    let mut kpageflags = File::open("/proc/kpageflags")?;
+    let mut pagemap = File::open(Path::new("/proc").join(pid.to_string()).join("pagemap"))?;
+    let page_size = rustix::param::page_size() as u64;
+
+    let mut counts = vec![];
+    for map in from_pid(pid)? {
+        let map = map?;
+        let anonymous = matches!(
+            map.category(),
+            MemoryCategory::Anonymous | MemoryCategory::Heap | MemoryCategory::Stack
+        );
+        let accessible =
+            map.permissions.readable || map.permissions.writable || map.permissions.executable;
+        if !anonymous || !accessible {
+            continue;
+        }
+
+        let mut flags = vec![];
+        for pfn in resident_pfns(&mut pagemap, &map, page_size)? {
+            flags.push(read_entry(&mut kpageflags, pfn)?);
+        }
+
+        counts.push((map, PageCounts::from_flags(flags)));
+    }
+
+    Ok(KsmReport::from_counts(counts, page_size))
+}
+
+/// Returns the page frame numbers of the map's resident pages.
+fn resident_pfns(pagemap: &mut File, map: &Map, page_size: u64) -> Result<Vec<u64>, Error> {
+    let first = map.address_range.begin / page_size;
+    let last = map.address_range.end.div_ceil(page_size);
+
+    let mut pfns = vec![];
+    let mut page = first;
+    while page < last {
+        let count = CHUNK_PAGES.min(last - page);
+        let mut buf = vec![0; (count * ENTRY_SIZE) as usize];
+
+        pagemap.seek(SeekFrom::Start(page * ENTRY_SIZE))?;
+        pagemap.read_exact(&mut buf)?;
+
+        pfns.extend(
+            buf.chunks_exact(ENTRY_SIZE as usize)
+                .map(|entry| u64::from_le_bytes(entry.try_into().unwrap()))
+                .filter(|entry| entry & PM_PRESENT != 0)
+                .map(|entry| entry & PM_PFN_MASK),
+        );
+
+        page += count;
+    }
+
+    Ok(pfns)
+}
+
+fn read_entry(file: &mut File, index: u64) -> Result<u64, Error> {
+    let mut buf = [0; ENTRY_SIZE as usize];
+
+    file.seek(SeekFrom::Start(index * ENTRY_SIZE))?;
+    file.read_exact(&mut buf)?;
+
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::{AddressRange, Pathname};
+
+    fn map(begin: u64, end: u64, pathname: Pathname) -> Map {
+        Map {
+            address_range: AddressRange { begin, end },
+            pathname,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_page_counts() {
+        assert_eq!(PageCounts::from_flags(vec![]), PageCounts::default());
+
+        // Other flags (here, KPF_REFERENCED and KPF_ANON) don't matter.
+        assert_eq!(
+            PageCounts::from_flags(vec![
+                KPF_KSM,
+                0,
+                KPF_ZERO_PAGE,
+                KPF_KSM | (1 << 2) | (1 << 12),
+                1 << 12,
+                KPF_ZERO_PAGE,
+            ]),
+            PageCounts { ksm: 2, zero: 2 }
+        );
+    }
+
+    #[test]
+    fn test_report_from_counts() {
+        let heap = map(0x1000, 0x9000, Pathname::Heap);
+        let arena = map(0x10000, 0x20000, Pathname::Mmap);
+
+        let report = KsmReport::from_counts(
+            vec![
+                (
+                    heap.clone(),
+                    PageCounts::from_flags(vec![KPF_KSM, KPF_KSM, 0]),
+                ),
+                (
+                    arena.clone(),
+                    PageCounts::from_flags(vec![KPF_KSM, KPF_ZERO_PAGE, KPF_ZERO_PAGE]),
+                ),
+            ],
+            4096,
+        );
+
+        assert_eq!(
+            report.maps,
+            vec![
+                KsmMap {
+                    map: heap,
+                    ksm_pages: 2,
+                    ksm_bytes: 8192,
+                    zero_page_bytes: 0,
+                },
+                KsmMap {
+                    map: arena,
+                    ksm_pages: 1,
+                    ksm_bytes: 4096,
+                    zero_page_bytes: 8192,
+                },
+            ]
+        );
+        assert_eq!(report.ksm_pages, 3);
+        assert_eq!(report.ksm_bytes, 3 * 4096);
+        assert_eq!(report.zero_page_bytes, 2 * 4096);
+
+        assert_eq!(KsmReport::from_counts(vec![], 4096), KsmReport::default());
+    }
+
+    #[test]
+    fn test_savings_self() {
+        let report = match savings(std::process::id()) {
+            Ok(report) => report,
+            Err(Error::Io(e)) if !rustix::process::geteuid().is_root() => {
+                assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        };
+
+        assert!(!report.maps.is_empty());
+        assert!(report.maps.iter().all(|m| matches!(
+            m.map.category(),
+            MemoryCategory::Anonymous | MemoryCategory::Heap | MemoryCategory::Stack
+        )));
+        assert_eq!(
+            report.ksm_pages,
+            report.maps.iter().map(|m| m.ksm_pages).sum::<u64>()
+        );
+        assert_eq!(
+            report.ksm_bytes,
+            report.ksm_pages * rustix::param::page_size() as u64
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Reducing an address space to fixed-width buckets, e.g. for rendering a strip map.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::category::MemoryCategory;
+use crate::{AddressRange, Map};
+
+/// A single bucket of the address space, as produced by [`bucketize`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Bucket {
+    /// The range of addresses that this bucket covers.
+    pub range: AddressRange,
+    /// The number of bytes in this bucket that are mapped.
+    pub mapped_bytes: u64,
+    /// The category with the most mapped bytes in this bucket, or `None` if
+    /// nothing in it is mapped. Ties go to the category that sorts first.
+    pub dominant_kind: Option<MemoryCategory>,
+    /// The number of maps that overlap this bucket.
+    pub map_count: usize,
+}
+
+/// Formats the bucket on one line, e.g.
+/// `0x1000-0x2000: 3.0 KiB mapped by 2 maps (Code)`.
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}: {} mapped by {} maps",
+            crate::fmt::addr(self.range.begin, false),
+            crate::fmt::addr(self.range.end, false),
+            crate::fmt::size(self.mapped_bytes),
+            self.map_count
+        )?;
+
+        match self.dominant_kind {
+            Some(kind) => write!(f, " ({:?})", kind),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Divides `bounds` into `buckets` equal-width buckets (up to rounding), and
+/// reports how much of each is covered by `maps`.
+///
+/// `bounds` defaults to the span of `maps`, i.e. from the lowest begin address to
+/// the highest end address. Maps that straddle a bucket boundary contribute
+/// only the bytes that fall within each bucket, and parts of maps outside of
+/// `bounds` are ignored.
+///
+/// Returns no buckets if `buckets` is 0 or the bounds are empty.
+pub fn bucketize(maps: &[Map], buckets: usize, bounds: Option<AddressRange>) -> Vec<Bucket> {
+    let bounds = match bounds {
+        Some(bounds) => bounds,
+        None => match (
+            maps.iter().map(|m| m.address_range.begin).min(),
+            maps.iter().map(|m| m.address_range.end).max(),
+        ) {
+            (Some(begin), Some(end)) => AddressRange { begin, end },
+            _ => return vec![],
+        },
+    };
+
+    if buckets == 0 || bounds.begin >= bounds.end {
+        return vec![];
+    }
+
+    // NOTE(ww): Boundaries are computed in u128 so that span * i can't overflow,
+    // even for a bucket count that doesn't divide the span evenly.
+    let span = u128::from(bounds.len());
+    let boundaries = (0..=buckets)
+        .map(|i| bounds.begin + (span * i as u128 / buckets as u128) as u64)
+        .collect::<Vec<_>>();
+
+    let mut kinds = vec![BTreeMap::<MemoryCategory, u64>::new(); buckets];
+    let mut result = boundaries
+        .windows(2)
+        .map(|pair| Bucket {
+            range: AddressRange {
+                begin: pair[0],
+                end: pair[1],
+            },
+            mapped_bytes: 0,
+            dominant_kind: None,
+            map_count: 0,
+        })
+        .collect::<Vec<_>>();
+
+    for map in maps {
+        let begin = map.address_range.begin.max(bounds.begin);
+        let end = map.address_range.end.min(bounds.end);
+        if begin >= end {
+            continue;
+        }
+
+        let first = boundaries[1..].partition_point(|boundary| *boundary <= begin);
+        for (bucket, kinds) in result[first..].iter_mut().zip(&mut kinds[first..]) {
+            if bucket.range.begin >= end {
+                break;
+            }
+
+            let overlap = end.min(bucket.range.end) - begin.max(bucket.range.begin);
+            if overlap == 0 {
+                continue;
+            }
+
+            bucket.mapped_bytes += overlap;
+            bucket.map_count += 1;
+            *kinds.entry(map.category()).or_insert(0) += overlap;
+        }
+    }
+
+    for (bucket, kinds) in result.iter_mut().zip(kinds) {
+        // NOTE(ww): max_by_key returns the last maximum, so we iterate in
+        // reverse to break ties in favor of the first category.
+        bucket.dominant_kind = kinds
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, bytes)| *bytes)
+            .map(|(kind, _)| kind);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn maps(input: &str) -> Vec<Map> {
+        from_str(input).collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    fn summary(buckets: &[Bucket]) -> Vec<(u64, u64, u64, Option<MemoryCategory>, usize)> {
+        buckets
+            .iter()
+            .map(|b| {
+                (
+                    b.range.begin,
+                    b.range.end,
+                    b.mapped_bytes,
+                    b.dominant_kind,
+                    b.map_count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bucketize() {
+        // [0x1000, 0x5000) in four 0x1000-byte buckets.
+        let maps = maps(
+            "1000-1800 r-xp 00000000 08:01 1 /bin/cat\n\
+             1800-2800 rw-p 00000000 00:00 0 \n\
+             3000-3400 rw-p 00000000 00:00 0 [heap]\n\
+             3400-3500 r-xp 00000000 08:01 1 /bin/cat\n\
+             4000-5000 rw-p 00000000 00:00 0 [stack]\n",
+        );
+
+        assert_eq!(
+            summary(&bucketize(&maps, 4, None)),
+            vec![
+                (0x1000, 0x2000, 0x1000, Some(MemoryCategory::Code), 2),
+                (0x2000, 0x3000, 0x800, Some(MemoryCategory::Anonymous), 1),
+                (0x3000, 0x4000, 0x500, Some(MemoryCategory::Heap), 2),
+                (0x4000, 0x5000, 0x1000, Some(MemoryCategory::Stack), 1),
+            ]
+        );
+
+        let buckets = bucketize(&maps, 4, None);
+        assert_eq!(
+            buckets[0].to_string(),
+            "0x1000-0x2000: 4.0 KiB mapped by 2 maps (Code)"
+        );
+        assert_eq!(
+            buckets[2].to_string(),
+            "0x3000-0x4000: 1.3 KiB mapped by 2 maps (Heap)"
+        );
+    }
+
+    #[test]
+    fn test_bucketize_large_map() {
+        // A single map spanning several whole buckets, and part of the last.
+        let maps = maps(
+            "0-3800 rw-p 00000000 00:00 0 \n\
+             3c00-4000 r-xp 00000000 08:01 1 /bin/cat\n",
+        );
+
+        assert_eq!(
+            summary(&bucketize(&maps, 4, None)),
+            vec![
+                (0x0, 0x1000, 0x1000, Some(MemoryCategory::Anonymous), 1),
+                (0x1000, 0x2000, 0x1000, Some(MemoryCategory::Anonymous), 1),
+                (0x2000, 0x3000, 0x1000, Some(MemoryCategory::Anonymous), 1),
+                (0x3000, 0x4000, 0xc00, Some(MemoryCategory::Anonymous), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bucketize_bounds() {
+        let maps = maps("1000-3000 rw-p 00000000 00:00 0 \n");
+
+        // Parts of maps outside of the bounds are ignored, and unmapped buckets
+        // have no dominant kind.
+        assert_eq!(
+            summary(&bucketize(
+                &maps,
+                3,
+                Some(AddressRange {
+                    begin: 0x2000,
+                    end: 0x5000
+                })
+            )),
+            vec![
+                (0x2000, 0x3000, 0x1000, Some(MemoryCategory::Anonymous), 1),
+                (0x3000, 0x4000, 0, None, 0),
+                (0x4000, 0x5000, 0, None, 0),
+            ]
+        );
+
+        // Uneven divisions still cover the bounds exactly.
+        let buckets = bucketize(&maps, 3, None);
+        assert_eq!(buckets.first().unwrap().range.begin, 0x1000);
+        assert_eq!(buckets.last().unwrap().range.end, 0x3000);
+        assert_eq!(buckets.iter().map(|b| b.mapped_bytes).sum::<u64>(), 0x2000);
+
+        assert!(bucketize(&maps, 0, None).is_empty());
+        assert!(bucketize(&[], 4, None).is_empty());
+    }
+
+    #[test]
+    fn test_bucket_serialize() {
+        let maps = maps("1000-2000 rw-p 00000000 00:00 0 [heap]\n");
+        let json = serde_json::to_string(&bucketize(&maps, 1, None)).unwrap();
+
+        assert_eq!(
+            json,
+            r#"[{"range":{"begin":4096,"end":8192},"mapped_bytes":4096,"dominant_kind":"Heap","map_count":1}]"#
+        );
+    }
+}
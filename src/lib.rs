@@ -4,20 +4,61 @@
 #![allow(clippy::redundant_field_names)]
 #![forbid(unsafe_code)]
 
-use std::fmt;
-use std::fs::File;
-use std::io::Lines;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Lines};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::ops;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use libc::pid_t;
+use pest::iterators::Pair;
 use pest::Parser as ParserTrait;
 use pest_derive::Parser;
 use phf::phf_map;
 use serde::{Deserialize, Serialize};
 
+pub mod category;
+pub mod crosscheck;
+pub mod elf;
 pub mod error;
+pub mod filter;
+pub mod fmt;
+#[cfg(feature = "freebsd")]
+pub mod freebsd;
+pub mod growth;
+pub mod ksm;
+pub mod layout;
+#[cfg(feature = "macos")]
+pub mod macos;
+pub mod memory;
+pub mod module;
+pub mod mount;
+mod mountinfo;
+pub mod multimap;
+#[cfg(feature = "procfs-interop")]
+mod procfs_interop;
+pub mod region;
+#[cfg(target_os = "linux")]
+pub mod self_check;
+pub mod shm;
+pub mod smaps;
+pub mod snapshot;
+pub mod visibility;
+pub mod watch;
 use error::Error;
+use filter::MapFilter;
+
+/// The suffix that the kernel appends to the pathname of a map whose
+/// backing file has been deleted.
+pub(crate) const DELETED_SUFFIX: &str = " (deleted)";
 
 static PSUEDO_PATH_MAP: phf::Map<&'static str, Pathname> = phf_map! {
     "[stack]" => Pathname::Stack,
@@ -25,14 +66,57 @@ static PSUEDO_PATH_MAP: phf::Map<&'static str, Pathname> = phf_map! {
     "[vvar]" => Pathname::Vvar,
     "[vsyscall]" => Pathname::Vsyscall,
     "[heap]" => Pathname::Heap,
+    "[vectors]" => Pathname::Vectors,
+    "[sigpage]" => Pathname::Sigpage,
 };
 
 #[derive(Parser)]
 #[grammar = "map.pest"]
 struct MapParser;
 
+/// Converts a size or offset to a `usize`, failing rather than truncating on
+/// targets where `usize` is narrower than 64 bits (e.g. a 32-bit agent parsing
+/// a 64-bit host's maps).
+pub(crate) fn to_usize(what: &'static str, value: u64) -> Result<usize, Error> {
+    checked_usize(what, value, usize::MAX as u64)
+}
+
+fn checked_usize(what: &'static str, value: u64, max: u64) -> Result<usize, Error> {
+    match usize::try_from(value) {
+        Ok(converted) if value <= max => Ok(converted),
+        _ => Err(Error::TooLarge { what, value }),
+    }
+}
+
+/// Runs `command` (named `name`), returning its standard output, or
+/// [`Error::CommandFailed`] if it exits unsuccessfully.
+#[cfg(any(feature = "freebsd", feature = "macos"))]
+pub(crate) fn run_command(
+    name: &'static str,
+    command: &mut std::process::Command,
+) -> Result<String, Error> {
+    let output = command.output()?;
+
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            command: name,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+        });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses `field` in its entirety with `rule`, returning the field's pair.
+fn parse_field(rule: Rule, field: &str) -> Result<Pair<'_, Rule>, Error> {
+    // NOTE(ww): The field rules are silent wrappers around a single field rule,
+    // so this next + unwrap is safe after a successful parse.
+    Ok(MapParser::parse(rule, field)?.next().unwrap())
+}
+
 /// Represents the variants of the "pathname" field in a map.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub enum Pathname {
     /// This map is the initial process's (i.e., main thread's) stack.
     Stack,
@@ -44,18 +128,82 @@ pub enum Pathname {
     Vsyscall,
     /// This map is the process's heap.
     Heap,
+    /// This map contains the exception vector page (32-bit ARM only).
+    Vectors,
+    /// This map contains the signal return trampoline page (32-bit ARM only).
+    Sigpage,
     /// This map was created by a call to `mmap`.
     Mmap,
     /// This map looks like another, unparsed, pseudo-path. See `man 5 proc`.
     OtherPseudo(String),
     // NOTE(ww): This should really be a PathBuf, but pest uses UTF-8 strings.
-    // Better hope your paths are valid UTF-8!
+    // Paths that aren't valid UTF-8 are converted lossily; see Map::pathname_bytes.
     /// This map appears to correspond to a filesystem path.
     Path(String),
 }
 
+impl Pathname {
+    /// Classifies a raw pathname field, as it appears in the kernel's maps format.
+    ///
+    /// An empty pathname is `Mmap`, the pseudo-paths that this crate knows about
+    /// get their own variants, other bracketed names are `OtherPseudo`, and
+    /// anything else is a `Path`.
+    pub fn classify(pathname: &str) -> Pathname {
+        if pathname.is_empty() {
+            // An empty path indicates an mmap'd region.
+            Pathname::Mmap
+        } else if let Some(known) = PSUEDO_PATH_MAP.get(pathname) {
+            // There are some pseudo-files that we know; use their enum variants
+            // if we see them.
+            known.clone()
+        } else if pathname.starts_with('[') && pathname.ends_with(']') {
+            // There are probably other pseudo-files that we don't know;
+            // if we see something that looks like one, mark it as such.
+            Pathname::OtherPseudo(pathname.into())
+        } else {
+            // Finally, treat anything else like a path.
+            // As proc(5) notes, there are a few ambiguities here with escaped
+            // newlines and the "(deleted)" suffix; leave these to the user to figure out.
+            Pathname::Path(pathname.into())
+        }
+    }
+
+    /// Returns this pathname as it appears in the kernel's maps format, e.g. `"[stack]"`
+    /// for `Stack` and `""` for `Mmap`.
+    pub fn label(&self) -> Cow<'_, str> {
+        match self {
+            Pathname::Stack => "[stack]".into(),
+            Pathname::Vdso => "[vdso]".into(),
+            Pathname::Vvar => "[vvar]".into(),
+            Pathname::Vsyscall => "[vsyscall]".into(),
+            Pathname::Heap => "[heap]".into(),
+            Pathname::Vectors => "[vectors]".into(),
+            Pathname::Sigpage => "[sigpage]".into(),
+            Pathname::Mmap => "".into(),
+            Pathname::OtherPseudo(pseudo) => pseudo.as_str().into(),
+            Pathname::Path(path) => path.as_str().into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Pathname {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.label())
+    }
+}
+
+/// Formats an integer in hex under `{:?}`, for addresses and other numbers
+/// that are conventionally shown in hex.
+struct Hex(u64);
+
+impl std::fmt::Debug for Hex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&fmt::addr(self.0, false))
+    }
+}
+
 /// Represents the address range of a map.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct AddressRange {
     /// The (inclusive) start of the address range.
     pub begin: u64,
@@ -63,14 +211,164 @@ pub struct AddressRange {
     pub end: u64,
 }
 
-impl fmt::Display for AddressRange {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:x}-{:x}", self.begin, self.end)
+impl AddressRange {
+    /// Creates a new address range, failing with [`Error::InvalidRange`] if `end`
+    /// is before `begin`. Empty ranges (where `begin == end`) are allowed.
+    pub fn new(begin: u64, end: u64) -> Result<AddressRange, Error> {
+        if end < begin {
+            return Err(Error::InvalidRange { begin, end });
+        }
+
+        Ok(AddressRange { begin, end })
+    }
+
+    /// Creates a new address range without checking that `end` isn't before `begin`.
+    ///
+    /// This is equivalent to setting the fields directly. Inverted ranges are
+    /// memory-safe, but will confuse most of this crate's APIs.
+    pub const fn new_unchecked(begin: u64, end: u64) -> AddressRange {
+        AddressRange { begin, end }
+    }
+
+    /// Returns the number of bytes in this address range.
+    ///
+    /// An inverted range (which the parser accepts; see [`validate_maps`]) has a
+    /// length of zero, rather than underflowing.
+    pub fn len(&self) -> u64 {
+        self.end.saturating_sub(self.begin)
+    }
+
+    /// Returns whether this address range is empty (or inverted).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits this address range in two at `addr`, which must be strictly inside it.
+    ///
+    /// Returns `None` if `addr` is at or outside of either end, since one of
+    /// the halves would be empty (or inverted).
+    pub fn split_at(&self, addr: u64) -> Option<(AddressRange, AddressRange)> {
+        if self.begin < addr && addr < self.end {
+            Some((
+                AddressRange {
+                    begin: self.begin,
+                    end: addr,
+                },
+                AddressRange {
+                    begin: addr,
+                    end: self.end,
+                },
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the start address of every `page_size`-aligned page that begins
+    /// within this address range, in ascending order.
+    ///
+    /// Iteration starts at the first aligned address at or after `begin`, so an
+    /// unaligned range skips its leading partial page. A `page_size` of zero
+    /// yields nothing.
+    pub fn iter_pages(&self, page_size: u64) -> impl Iterator<Item = u64> {
+        let end = self.end;
+
+        std::iter::successors(
+            self.begin.checked_next_multiple_of(page_size),
+            move |page| page.checked_add(page_size),
+        )
+        .take_while(move |page| *page < end)
+    }
+
+    /// Returns whether this address range begins in kernel space.
+    ///
+    /// Kernel addresses shouldn't appear in a user process's maps, but can
+    /// appear in other sources like `/proc/kcore`. On x86-64, user space occupies the
+    /// lower canonical half of the address space (up to `0x0000_7fff_ffff_ffff`
+    /// with 4-level paging), and the kernel occupies the upper half, starting at
+    /// `0xffff_8000_0000_0000`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn is_kernel_space(&self) -> bool {
+        self.begin >= 0xffff_8000_0000_0000
+    }
+
+    /// Returns whether this address range begins in kernel space.
+    ///
+    /// Kernel addresses shouldn't appear in a user process's maps, but can
+    /// appear in other sources like `/proc/kcore`. On AArch64, user space is
+    /// translated via `TTBR0` and lives at the bottom of the address space, while
+    /// the kernel is translated via `TTBR1` and lives at the top. With 48-bit
+    /// virtual addresses the kernel starts at `0xffff_0000_0000_0000`; with 52-bit
+    /// virtual addresses it starts at `0xfff0_0000_0000_0000`.
+    #[cfg(target_arch = "aarch64")]
+    pub fn is_kernel_space(&self) -> bool {
+        self.begin >= 0xfff0_0000_0000_0000
+    }
+
+    /// Returns whether this address range begins in kernel space.
+    ///
+    /// Kernel addresses shouldn't appear in a user process's maps, but can
+    /// appear in other sources like `/proc/kcore`. On RISC-V, user space
+    /// occupies the lower half of the address space and the kernel the upper half,
+    /// with the split depending on the paging mode: `0xffff_ffc0_0000_0000`
+    /// for Sv39, `0xffff_8000_0000_0000` for Sv48, and `0xff00_0000_0000_0000` for Sv57.
+    #[cfg(target_arch = "riscv64")]
+    pub fn is_kernel_space(&self) -> bool {
+        self.begin >= 0xff00_0000_0000_0000
+    }
+}
+
+impl AddressRange {
+    fn from_pair(pair: Pair<Rule>) -> Result<AddressRange, Error> {
+        let mut address_range = pair.into_inner();
+
+        AddressRange::from_raw(
+            address_range.next().unwrap().as_str(),
+            address_range.next().unwrap().as_str(),
+        )
+    }
+
+    fn from_raw(begin: &str, end: &str) -> Result<AddressRange, Error> {
+        Ok(AddressRange {
+            begin: u64::from_str_radix(begin, 16)?,
+            end: u64::from_str_radix(end, 16)?,
+        })
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = Error;
+
+    /// Parses an address range in the kernel's maps format, e.g. `7f1c2e400000-7f1c2e5c8000`.
+    fn from_str(s: &str) -> Result<AddressRange, Error> {
+        AddressRange::from_pair(parse_field(Rule::address_range_field, s)?)
+    }
+}
+
+impl std::fmt::Debug for AddressRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AddressRange")
+            .field("begin", &Hex(self.begin))
+            .field("end", &Hex(self.end))
+            .finish()
+    }
+}
+
+/// Formats the address range as the kernel does, i.e. in hex and zero-padded to at
+/// least 8 digits, so that a 32-bit process's addresses keep their width.
+impl std::fmt::Display for AddressRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:08x}-{:08x}", self.begin, self.end)
     }
 }
 
 /// Represents the permissions associated with a map.
-#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+///
+/// The default permissions are the null permission: nothing is granted, and the
+/// map is neither shared nor private, so they display (and parse) as `----`.
+/// Inaccessible maps parsed from the kernel (e.g. guard pages) are always one or
+/// the other, i.e. [`Permissions::NONE`] (`---p`) or `---s`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Permissions {
     /// Is this map readable?
     pub readable: bool,
@@ -84,40 +382,200 @@ pub struct Permissions {
     pub private: bool,
 }
 
-impl fmt::Display for Permissions {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut mask = String::new();
+impl Permissions {
+    /// The bit for readable maps, in [`Permissions::bits`].
+    pub const READ_BIT: u8 = 1;
+    /// The bit for writable maps, in [`Permissions::bits`].
+    pub const WRITE_BIT: u8 = 2;
+    /// The bit for executable maps, in [`Permissions::bits`].
+    pub const EXECUTE_BIT: u8 = 4;
+    /// The bit for shared maps, in [`Permissions::bits`]. Maps without it are private.
+    pub const SHARED_BIT: u8 = 8;
+
+    /// No permissions, i.e. `---p`.
+    pub const NONE: Permissions = Permissions::from_bits_truncate(0);
+    /// Read-only and private, i.e. `r--p`.
+    pub const R: Permissions = Permissions::from_bits_truncate(Permissions::READ_BIT);
+    /// Readable, writable, and private, i.e. `rw-p`.
+    pub const RW_PRIVATE: Permissions =
+        Permissions::from_bits_truncate(Permissions::READ_BIT | Permissions::WRITE_BIT);
+    /// Readable, executable, and private, i.e. `r-xp`.
+    pub const RX_PRIVATE: Permissions =
+        Permissions::from_bits_truncate(Permissions::READ_BIT | Permissions::EXECUTE_BIT);
+
+    const ALL_BITS: u8 = Permissions::READ_BIT
+        | Permissions::WRITE_BIT
+        | Permissions::EXECUTE_BIT
+        | Permissions::SHARED_BIT;
 
+    /// Returns these permissions as a bitmask of [`Permissions::READ_BIT`],
+    /// [`Permissions::WRITE_BIT`], [`Permissions::EXECUTE_BIT`], and
+    /// [`Permissions::SHARED_BIT`]. Private maps are those without the shared bit.
+    ///
+    /// Permissions that are neither shared nor private (like the default) have no
+    /// bitmask of their own, and come back from [`Permissions::from_bits`] as private.
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
         if self.readable {
-            mask.push('r');
-        } else {
-            mask.push('-');
+            bits |= Permissions::READ_BIT;
         }
-
         if self.writable {
-            mask.push('w');
-        } else {
-            mask.push('-');
+            bits |= Permissions::WRITE_BIT;
         }
-
         if self.executable {
-            mask.push('e');
-        } else {
-            mask.push('-');
+            bits |= Permissions::EXECUTE_BIT;
         }
-
         if self.shared {
-            mask.push('s')
-        } else {
-            mask.push('p')
+            bits |= Permissions::SHARED_BIT;
+        }
+        bits
+    }
+
+    /// Creates permissions from a bitmask, as returned by [`Permissions::bits`].
+    ///
+    /// Returns `None` if any undefined bits are set.
+    pub fn from_bits(bits: u8) -> Option<Permissions> {
+        if bits & !Permissions::ALL_BITS != 0 {
+            return None;
+        }
+
+        Some(Permissions::from_bits_truncate(bits))
+    }
+
+    const fn from_bits_truncate(bits: u8) -> Permissions {
+        Permissions {
+            readable: bits & Permissions::READ_BIT != 0,
+            writable: bits & Permissions::WRITE_BIT != 0,
+            executable: bits & Permissions::EXECUTE_BIT != 0,
+            shared: bits & Permissions::SHARED_BIT != 0,
+            private: bits & Permissions::SHARED_BIT == 0,
+        }
+    }
+
+    /// Returns the permissions set in either `self` or `other`.
+    ///
+    /// Like every combinator here, this operates on [`Permissions::bits`], so
+    /// the result is shared if either side is.
+    pub fn union(&self, other: &Permissions) -> Permissions {
+        Permissions::from_bits_truncate(self.bits() | other.bits())
+    }
+
+    /// Returns the permissions set in both `self` and `other`.
+    pub fn intersection(&self, other: &Permissions) -> Permissions {
+        Permissions::from_bits_truncate(self.bits() & other.bits())
+    }
+
+    /// Returns the permissions set in `self` but not in `other`.
+    pub fn difference(&self, other: &Permissions) -> Permissions {
+        Permissions::from_bits_truncate(self.bits() & !other.bits())
+    }
+
+    /// Returns whether these permissions include every permission set in `required`.
+    ///
+    /// The shared/private distinction is only checked if `required` sets
+    /// `shared` or `private`.
+    pub fn satisfies(&self, required: &Permissions) -> bool {
+        (!required.readable || self.readable)
+            && (!required.writable || self.writable)
+            && (!required.executable || self.executable)
+            && (!required.shared || self.shared)
+            && (!required.private || self.private)
+    }
+
+    /// Returns whether these permissions match the given pattern.
+    ///
+    /// A pattern has the same four positions as a permission mask (e.g. `r-xp`).
+    /// In each position, the permission's letter means that it must be set,
+    /// `-` means that it must be unset, and `?` means that it's ignored.
+    /// The last position takes `s` (shared), `p` (private), or `?`.
+    pub fn matches_pattern(&self, pattern: &str) -> Result<bool, Error> {
+        let pattern_bytes = pattern.as_bytes();
+        if pattern_bytes.len() != 4 {
+            return Err(Error::InvalidPattern(pattern.into()));
+        }
+
+        let checks = [
+            (pattern_bytes[0], b'r', self.readable),
+            (pattern_bytes[1], b'w', self.writable),
+            (pattern_bytes[2], b'x', self.executable),
+        ];
+
+        let mut matched = true;
+        for (c, letter, set) in checks.iter() {
+            matched &= match c {
+                b'?' => true,
+                b'-' => !set,
+                c if c == letter => *set,
+                _ => return Err(Error::InvalidPattern(pattern.into())),
+            };
         }
 
-        write!(f, "{}", mask)
+        matched &= match pattern_bytes[3] {
+            b'?' => true,
+            b's' => self.shared,
+            b'p' => self.private,
+            _ => return Err(Error::InvalidPattern(pattern.into())),
+        };
+
+        Ok(matched)
+    }
+
+    fn from_raw(permissions: &str) -> Permissions {
+        let permissions = permissions.as_bytes();
+
+        Permissions {
+            readable: permissions[0] == b'r',
+            writable: permissions[1] == b'w',
+            executable: permissions[2] == b'x',
+            shared: permissions[3] == b's',
+            private: permissions[3] == b'p',
+        }
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = Error;
+
+    /// Parses permissions in the kernel's maps format, e.g. `r-xp`.
+    fn from_str(s: &str) -> Result<Permissions, Error> {
+        Ok(Permissions::from_raw(
+            parse_field(Rule::permissions_field, s)?.as_str(),
+        ))
+    }
+}
+
+impl std::fmt::Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&fmt::perms(self))
+    }
+}
+
+impl ops::BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, rhs: Permissions) -> Permissions {
+        self.union(&rhs)
+    }
+}
+
+impl ops::BitAnd for Permissions {
+    type Output = Permissions;
+
+    fn bitand(self, rhs: Permissions) -> Permissions {
+        self.intersection(&rhs)
+    }
+}
+
+impl ops::Not for Permissions {
+    type Output = Permissions;
+
+    fn not(self) -> Permissions {
+        Permissions::from_bits_truncate(!self.bits() & Permissions::ALL_BITS)
     }
 }
 
 /// Represents the device associated with a map.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Device {
     /// The device's major number.
     pub major: u64,
@@ -125,14 +583,150 @@ pub struct Device {
     pub minor: u64,
 }
 
-impl fmt::Display for Device {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Device {
+    /// Decodes a Linux `dev_t` (e.g. from `stat(2)`) into a `Device`.
+    ///
+    /// This handles the full modern encoding (as in glibc's `gnu_dev_major` and
+    /// `gnu_dev_minor`), not just the legacy 8/8 split: the major's low 12 bits are
+    /// bits 8-19 and its remaining bits are 44-63, while the minor's low 8 bits are
+    /// bits 0-7 and its remaining bits are 20-43.
+    pub fn from_dev_t(dev: u64) -> Device {
+        Device {
+            major: ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000),
+            minor: (dev & 0xff) | ((dev >> 12) & 0xffff_ff00),
+        }
+    }
+
+    /// Encodes this `Device` as a Linux `dev_t`; the inverse of [`Device::from_dev_t`].
+    ///
+    /// The encoding has room for 32-bit majors and minors; any higher bits are discarded.
+    pub fn to_dev_t(&self) -> u64 {
+        ((self.major & 0xfff) << 8)
+            | ((self.major & 0xffff_f000) << 32)
+            | (self.minor & 0xff)
+            | ((self.minor & 0xffff_ff00) << 12)
+    }
+
+    /// Returns whether the file described by `metadata` lives on this device.
+    pub fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        Device::from_dev_t(metadata.dev()) == *self
+    }
+
+    /// Resolves this device to its block device node, e.g. `/dev/sda1` for `8:1`.
+    ///
+    /// This looks up the device's name in `/sys/dev/block/<major>:<minor>/uevent`
+    /// (the same source that udev names devices from), and falls back to scanning
+    /// `/dev` for a block device with this device's number. Only the top level of
+    /// `/dev` is scanned, and the result is only a snapshot: devices can be renamed
+    /// or removed at any time, so the path may no longer refer to this device by the
+    /// time it's used.
+    ///
+    /// Devices with major number 0 (e.g. those of tmpfs, overlayfs, and anonymous
+    /// maps) are "unnamed" and never have a node, and so resolve to `None`.
+    pub fn resolve(&self) -> Option<PathBuf> {
+        self.resolve_in(Path::new("/sys"), Path::new("/dev"))
+    }
+
+    fn resolve_in(&self, sys_root: &Path, dev_root: &Path) -> Option<PathBuf> {
+        if self.major == 0 {
+            return None;
+        }
+
+        let uevent = sys_root
+            .join("dev/block")
+            .join(format!("{}:{}", self.major, self.minor))
+            .join("uevent");
+        let devname = fs::read_to_string(uevent).ok().and_then(|uevent| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix("DEVNAME="))
+                .map(|devname| dev_root.join(devname))
+        });
+
+        match devname {
+            Some(path) if path.exists() => Some(path),
+            _ => {
+                let dev_t = self.to_dev_t();
+                fs::read_dir(dev_root)
+                    .ok()?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .find(|path| {
+                        fs::symlink_metadata(path).is_ok_and(|metadata| {
+                            metadata.file_type().is_block_device() && metadata.rdev() == dev_t
+                        })
+                    })
+            }
+        }
+    }
+
+    fn from_pair(pair: Pair<Rule>) -> Result<Device, Error> {
+        let mut device = pair.into_inner();
+
+        Device::from_raw(
+            device.next().unwrap().as_str(),
+            device.next().unwrap().as_str(),
+        )
+    }
+
+    fn from_raw(major: &str, minor: &str) -> Result<Device, Error> {
+        Ok(Device {
+            major: u64::from_str_radix(major, 16)?,
+            minor: u64::from_str_radix(minor, 16)?,
+        })
+    }
+}
+
+impl FromStr for Device {
+    type Err = Error;
+
+    /// Parses a device in the kernel's maps format, i.e. `major:minor` in hex (e.g. `08:11`).
+    fn from_str(s: &str) -> Result<Device, Error> {
+        Device::from_pair(parse_field(Rule::device_field, s)?)
+    }
+}
+
+impl std::fmt::Debug for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("major", &Hex(self.major))
+            .field("minor", &Hex(self.minor))
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:02}-{:02}", self.major, self.minor)
     }
 }
 
+/// The pointer width of the kernel that produced a maps file.
+///
+/// This determines the column that pathnames are aligned to: the kernel pads each
+/// line to 73 columns on 64-bit kernels and 49 on 32-bit ones. A 32-bit process
+/// under a 64-bit kernel (e.g. an i386 program on x86-64) still gets the 64-bit layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelWidth {
+    /// A 32-bit kernel, e.g. on armv7.
+    ThirtyTwoBit,
+    /// A 64-bit kernel.
+    SixtyFourBit,
+}
+
+impl KernelWidth {
+    /// Returns the column that pathnames are padded to, minus the separating space.
+    fn pathname_padding(self) -> usize {
+        // NOTE(ww): This mirrors show_map_vma's `25 + sizeof(void *) * 6 - 1`.
+        match self {
+            KernelWidth::ThirtyTwoBit => 25 + 4 * 6 - 1,
+            KernelWidth::SixtyFourBit => 25 + 8 * 6 - 1,
+        }
+    }
+}
+
 /// Represents a map, i.e. a region of program memory.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Map {
     /// The map's address range.
     pub address_range: AddressRange,
@@ -151,6 +745,39 @@ pub struct Map {
 
     /// The map's pathname field.
     pub pathname: Pathname,
+
+    #[serde(default)]
+    deleted: bool,
+
+    #[serde(default)]
+    extra: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_line: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_offset: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pathname_bytes: Option<Vec<u8>>,
+}
+
+impl std::fmt::Debug for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Map")
+            .field("address_range", &self.address_range)
+            .field("permissions", &self.permissions)
+            .field("offset", &Hex(self.offset))
+            .field("device", &self.device)
+            .field("inode", &self.inode)
+            .field("pathname", &self.pathname)
+            .field("deleted", &self.deleted)
+            .field("extra", &self.extra)
+            .field("raw_line", &self.raw_line)
+            .field("source_offset", &self.source_offset)
+            .field("pathname_bytes", &self.pathname_bytes)
+            .finish()
+    }
 }
 
 impl Default for Map {
@@ -162,215 +789,3383 @@ impl Default for Map {
             device: Device { major: 0, minor: 0 },
             inode: 0,
             pathname: Pathname::Mmap,
+            deleted: false,
+            extra: None,
+            raw_line: None,
+            source_offset: None,
+            pathname_bytes: None,
         }
     }
 }
 
-impl Map {
-    fn parse(line: &str) -> Result<Map, Error> {
-        // NOTE(ww): The map rule is singular, so this next + unwrap is safe after
-        // a successful parse.
-        let parsed = MapParser::parse(Rule::map, line)?.next().unwrap();
-        let mut map: Map = Default::default();
+impl TryFrom<&[u8]> for Map {
+    type Error = Error;
 
-        for entry in parsed.into_inner() {
-            match entry.as_rule() {
-                Rule::address_range => {
-                    let mut address_range = entry.into_inner();
-                    map.address_range.begin =
-                        u64::from_str_radix(address_range.next().unwrap().as_str(), 16)?;
-                    map.address_range.end =
-                        u64::from_str_radix(address_range.next().unwrap().as_str(), 16)?;
-                }
-                Rule::permissions => {
-                    let permissions = entry.as_str().as_bytes();
+    /// Parses a single line of a maps file from bytes, with or without its trailing newline.
+    ///
+    /// As with [`from_bytes`], the pathname doesn't have to be valid UTF-8;
+    /// see [`Map::pathname_bytes`].
+    fn try_from(line: &[u8]) -> Result<Map, Error> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        Map::parse_bytes_with(line, &Default::default())
+    }
+}
 
-                    map.permissions.readable = permissions[0] == b'r';
-                    map.permissions.writable = permissions[1] == b'w';
-                    map.permissions.executable = permissions[2] == b'x';
-                    map.permissions.shared = permissions[3] == b's';
-                    map.permissions.private = !map.permissions.shared;
-                }
-                Rule::offset => {
-                    let offset = entry.as_str();
-                    map.offset = u64::from_str_radix(offset, 16)?;
-                }
-                Rule::device => {
-                    let mut device = entry.into_inner();
+impl From<Map> for serde_json::Value {
+    fn from(map: Map) -> serde_json::Value {
+        // NOTE(ww): Maps only contain strings, integers, and bools, so
+        // serializing one to a Value can't fail.
+        serde_json::to_value(map).unwrap()
+    }
+}
 
-                    map.device.major = u64::from_str_radix(device.next().unwrap().as_str(), 16)?;
-                    map.device.minor = u64::from_str_radix(device.next().unwrap().as_str(), 16)?;
-                }
-                Rule::inode => {
-                    map.inode = entry.as_str().parse()?;
-                }
-                Rule::pathname => {
-                    let pathname = entry.as_str();
-
-                    if pathname.is_empty() {
-                        // An empty path indicates an mmap'd region.
-                        map.pathname = Pathname::Mmap;
-                    } else if PSUEDO_PATH_MAP.contains_key(pathname) {
-                        // There are some pseudo-files that we know; use their enum variants
-                        // if we see them.
-                        map.pathname = PSUEDO_PATH_MAP.get(pathname).unwrap().clone();
-                    } else if pathname.starts_with('[') && pathname.ends_with(']') {
-                        // There are probably other pseudo-files that we don't know;
-                        // if we see something that looks like one, mark it as such.
-                        map.pathname = Pathname::OtherPseudo(pathname.into());
-                    } else {
-                        // Finally, treat anything else like a path.
-                        // As proc(5) notes, there are a few ambiguities here with escaped
-                        // newlines and the "(deleted)" suffix; leave these to the user to figure out.
-                        map.pathname = Pathname::Path(pathname.into());
-                    }
-                }
-                // NOTE(ww): There are other rules, but we should never be able to match them in this context.
-                _ => {
-                    unreachable!();
-                }
-            }
-        }
+impl TryFrom<&Map> for serde_json::Value {
+    type Error = serde_json::Error;
 
-        Ok(map)
+    fn try_from(map: &Map) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(map)
     }
 }
 
-/// A wrapper structure for consuming individual `Map`s from a reader.
-pub struct MapsLines<T> {
-    lines: Lines<T>,
-}
+impl Map {
+    /// Returns a [`MapBuilder`] for constructing a map by hand, starting from
+    /// [`Map::default`].
+    ///
+    /// ```rust
+    /// use rsprocmaps::{AddressRange, Map, Pathname, Permissions};
+    ///
+    /// let map = Map::builder()
+    ///     .address_range(AddressRange { begin: 0x1000, end: 0x2000 })
+    ///     .permissions(Permissions::RX_PRIVATE)
+    ///     .pathname(Pathname::Path("/bin/cat".into()))
+    ///     .build();
+    /// ```
+    pub fn builder() -> MapBuilder {
+        MapBuilder::default()
+    }
 
-impl<T> MapsLines<T> {
-    /// Creates a new `Maps` from the given `reader`.
-    pub fn new(lines: Lines<T>) -> MapsLines<T> {
-        MapsLines { lines }
+    /// Returns the exact line that this map was parsed from, without its trailing
+    /// newline, if it was parsed with [`ParseOptions::keep_raw_lines`].
+    pub fn raw_line(&self) -> Option<&str> {
+        self.raw_line.as_deref()
     }
-}
 
-impl<T> Iterator for MapsLines<T>
-where
-    T: BufRead,
-{
-    type Item = Result<Map, Error>;
+    /// Returns whether the map's backing file is known to have been deleted.
+    ///
+    /// Parsed maps always report `false`, since a "(deleted)" suffix is ambiguous
+    /// with a real path. Only [`snapshot::MapsSnapshot::canonicalize`] and
+    /// [`MapBuilder::deleted`] set this.
+    pub fn deleted(&self) -> bool {
+        self.deleted
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.lines.next() {
-            Some(Ok(line)) => Some(Map::parse(&line)),
-            Some(Err(e)) => Some(Err(e.into())),
-            None => None,
-        }
+    /// Returns any extra annotation following the pathname, as emitted by some Android
+    /// kernels (e.g. the `1234` in `/system/lib64/libc.so@1234`).
+    ///
+    /// This is only set by the parser with [`ParseOptions::android_annotations`].
+    pub fn extra(&self) -> Option<&str> {
+        self.extra.as_deref()
     }
-}
 
-/// A wrapper structure for consuming individual `Map`s from a reader.
-pub struct Maps<T: BufRead> {
-    reader: T,
-}
+    /// Returns the byte offset of the line that this map was parsed from, relative to
+    /// where its `Maps` began reading, if it was parsed with
+    /// [`ParseOptions::record_source_offsets`].
+    pub fn source_offset(&self) -> Option<u64> {
+        self.source_offset
+    }
 
-impl<T: BufRead> Maps<T> {
-    /// Creates a new `Maps` from the given `reader`.
-    pub fn new(reader: T) -> Maps<T> {
-        Maps { reader }
+    /// Formats this map as a line in the kernel's maps format, as produced by a
+    /// 64-bit kernel, without a trailing newline.
+    ///
+    /// Numbers are zero-padded to the kernel's minimum widths, so addresses that fit in
+    /// 32 bits are written with 8 digits rather than being widened. The deleted suffix
+    /// and any Android annotation (see [`Map::extra`]) are written after the pathname.
+    pub fn to_maps_line(&self) -> String {
+        self.to_maps_line_for(KernelWidth::SixtyFourBit)
     }
-}
 
-impl<T: BufRead> Iterator for Maps<T> {
-    type Item = Result<Map, Error>;
+    /// Like [`Map::to_maps_line`], but with the layout of the given kernel's maps.
+    pub fn to_maps_line_for(&self, kernel: KernelWidth) -> String {
+        let mut line = format!(
+            "{} {} {:08x} {:02x}:{:02x} {} ",
+            self.address_range,
+            self.permissions,
+            self.offset,
+            self.device.major,
+            self.device.minor,
+            self.inode
+        );
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut line_buf = String::new();
-        match self.reader.read_line(&mut line_buf) {
-            Ok(0) => None,
-            Ok(_) => {
-                // NOTE(ww): Annoying: the Lines iterator yields lines
-                // without their trailing delimiters, but read_line includes them.
-                if line_buf.ends_with('\n') {
-                    line_buf.pop();
-                }
-                Some(Map::parse(&line_buf))
+        let mut pathname = self.pathname.label().into_owned();
+        if self.deleted {
+            pathname.push_str(DELETED_SUFFIX);
+        }
+        if let Some(extra) = &self.extra {
+            pathname.push('@');
+            pathname.push_str(extra);
+        }
+
+        if !pathname.is_empty() {
+            let padding = kernel.pathname_padding().saturating_sub(line.len());
+            line.extend(std::iter::repeat_n(' ', padding + 1));
+            line.push_str(&pathname);
+        }
+
+        line
+    }
+
+    /// Returns the exact bytes of this map's pathname field, if it's a path or
+    /// an unrecognized pseudo-path.
+    ///
+    /// Pathnames that aren't valid UTF-8 are stored in [`Map::pathname`] with
+    /// their invalid sequences replaced by `U+FFFD`, so this is the only lossless
+    /// way to recover them (e.g. via `OsStr::from_bytes`).
+    pub fn pathname_bytes(&self) -> Option<&[u8]> {
+        match (&self.pathname_bytes, &self.pathname) {
+            (Some(bytes), _) => Some(bytes),
+            (None, Pathname::Path(path)) | (None, Pathname::OtherPseudo(path)) => {
+                Some(path.as_bytes())
             }
-            Err(e) => Some(Err(e.into())),
+            _ => None,
         }
     }
-}
 
-/// Returns an iterable `Maps` for the given pid.
-pub fn from_pid(pid: pid_t) -> Result<Maps<BufReader<File>>, Error> {
-    let path = Path::new("/proc").join(pid.to_string()).join("maps");
-    from_path(path)
-}
+    /// Opens this map's backing file, verifying that it's the same file that was mapped.
+    ///
+    /// The file is opened by path and then `fstat`ed, so the check applies to the
+    /// file actually opened: if its device or inode doesn't match this map's,
+    /// the file has been replaced since it was mapped and
+    /// [`Error::BackingFileChanged`] is returned.
+    ///
+    /// Maps whose backing file has been deleted return [`Error::DeletedBackingFile`],
+    /// and maps without a backing file return [`Error::NotFileBacked`].
+    pub fn open_backing_file(&self) -> Result<File, Error> {
+        let path = match &self.pathname {
+            Pathname::Path(path) => path,
+            _ => return Err(Error::NotFileBacked),
+        };
 
-/// Returns an iterable `Maps` parsed from the given file.
-pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Maps<BufReader<File>>, Error> {
-    let reader = {
-        let f = File::open(path)?;
-        BufReader::new(f)
-    };
+        if self.deleted || path.ends_with(DELETED_SUFFIX) {
+            return Err(Error::DeletedBackingFile(path.clone()));
+        }
 
-    Ok(Maps::new(reader))
-}
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
 
-/// Returns an iterable `Maps` parsed from the given string.
-pub fn from_str(maps_data: &str) -> Maps<&[u8]> {
-    Maps::new(maps_data.as_bytes())
-}
+        let found = (Device::from_dev_t(metadata.dev()), metadata.ino());
+        if found.0 != self.device || found.1 != self.inode {
+            return Err(Error::BackingFileChanged {
+                expected: (self.device.clone(), self.inode),
+                found,
+            });
+        }
 
-/// Returns an iterable `Maps` parsed from the given [Lines].
-pub fn from_lines<T>(maps_lines: Lines<T>) -> MapsLines<T> {
+        Ok(file)
+    }
+
+    /// Returns whether this map contains `addr`.
+    pub fn contains_address(&self, addr: u64) -> bool {
+        self.address_range.begin <= addr && addr < self.address_range.end
+    }
+
+    /// Returns the size of this map in bytes, as a `usize`.
+    ///
+    /// This fails with [`Error::TooLarge`] if the size doesn't fit, which can only
+    /// happen on targets where `usize` is narrower than 64 bits.
+    pub fn size_usize(&self) -> Result<usize, Error> {
+        to_usize("map size", self.address_range.len())
+    }
+
+    /// Returns the path of the block device that this map's file lives on, if any;
+    /// see [`Device::resolve`].
+    pub fn device_path(&self) -> Option<PathBuf> {
+        self.device.resolve()
+    }
+
+    /// Returns whether this map belongs to the given executable.
+    ///
+    /// Paths are compared textually first, then after canonicalization. If neither
+    /// matches (e.g. because the executable has been deleted, or `exe` is a magic
+    /// link like `/proc/<pid>/exe`), this falls back to comparing the device and
+    /// inode of `exe` against this map's.
+    pub fn is_main_executable(&self, exe: &Path) -> bool {
+        let path = match &self.pathname {
+            Pathname::Path(path) => Path::new(path),
+            _ => return false,
+        };
+
+        if path == exe {
+            return true;
+        }
+
+        if let (Ok(path), Ok(exe)) = (path.canonicalize(), exe.canonicalize()) {
+            if path == exe {
+                return true;
+            }
+        }
+
+        match exe.metadata() {
+            Ok(metadata) => self.device.matches_metadata(&metadata) && metadata.ino() == self.inode,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns whether this map is anonymous memory, i.e. it has no pathname
+    /// (not even a pseudo-path) and no backing device.
+    pub fn is_anonymous(&self) -> bool {
+        self.pathname == Pathname::Mmap && self.device.major == 0 && self.device.minor == 0
+    }
+
+    /// Returns whether this map is backed by a file, i.e. it has a path and a
+    /// backing device.
+    ///
+    /// This includes files that have since been deleted, memfds, and shared
+    /// anonymous memory (which the kernel backs with `/dev/zero`).
+    pub fn is_file_backed(&self) -> bool {
+        matches!(self.pathname, Pathname::Path(_))
+            && (self.device.major != 0 || self.device.minor != 0)
+    }
+
+    /// Returns whether this map is executable.
+    pub fn is_executable(&self) -> bool {
+        self.permissions.executable
+    }
+
+    /// Returns whether this map looks like a guard region, i.e. an anonymous map
+    /// with no permissions at all (`---p` or `---s`).
+    ///
+    /// Guard regions reserve address space (e.g. below thread stacks, or at the end
+    /// of allocator arenas) without committing any memory to it.
+    pub fn is_guard(&self) -> bool {
+        !self.permissions.readable
+            && !self.permissions.writable
+            && !self.permissions.executable
+            && self.pathname == Pathname::Mmap
+    }
+
+    /// Returns whether this map is backed by hugetlbfs pages, as far as can be
+    /// told from its pathname alone.
+    ///
+    /// This recognizes anonymous `MAP_HUGETLB` mappings, which the kernel shows as
+    /// `/anon_hugepage` (or `/anon_hugepage (deleted)`). Maps of files on a hugetlbfs
+    /// mount can't be recognized without knowing where hugetlbfs is mounted;
+    /// see [`Map::is_hugetlb_in`].
+    pub fn is_hugetlb(&self) -> bool {
+        match &self.pathname {
+            Pathname::Path(path) => {
+                path == "/anon_hugepage" || path.starts_with("/anon_hugepage (deleted)")
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether this map is backed by hugetlbfs pages, given the
+    /// hugetlbfs mountpoints on the system (e.g. `/dev/hugepages`).
+    pub fn is_hugetlb_in(&self, mountpoints: &[&Path]) -> bool {
+        match &self.pathname {
+            Pathname::Path(path) => {
+                self.is_hugetlb() || mountpoints.iter().any(|m| Path::new(path).starts_with(m))
+            }
+            _ => false,
+        }
+    }
+
+    fn parse(line: &str) -> Result<Map, Error> {
+        Map::parse_with(line, &Default::default())
+    }
+
+    fn parse_with(line: &str, options: &ParseOptions) -> Result<Map, Error> {
+        Map::from_raw(RawMap::parse_with(line, options)?, line, options)
+    }
+
+    fn parse_bytes_with(line: &[u8], options: &ParseOptions) -> Result<Map, Error> {
+        let lossy = match std::str::from_utf8(line) {
+            Ok(line) => return Map::parse_with(line, options),
+            Err(_) => String::from_utf8_lossy(line),
+        };
+
+        // NOTE(ww): Every field before the pathname is ASCII, so the invalid
+        // sequences (and their replacements) can only be in the pathname.
+        // Everything after it (i.e. the extra suffix) is ASCII too, so the
+        // pathname's bytes are what's left of the line between the two.
+        let raw = RawMap::parse_with(&lossy, options)?;
+        let start = raw.pathname.as_ptr() as usize - lossy.as_ptr() as usize;
+        let suffix = lossy.len() - start - raw.pathname.len();
+
+        let mut map = Map::from_raw(raw, &lossy, options)?;
+        map.pathname_bytes = Some(line[start..line.len() - suffix].to_vec());
+
+        Ok(map)
+    }
+
+    fn from_raw(raw: RawMap, line: &str, options: &ParseOptions) -> Result<Map, Error> {
+        Ok(Map {
+            address_range: AddressRange::from_raw(raw.address_begin, raw.address_end)?,
+            permissions: Permissions::from_raw(raw.permissions),
+            offset: u64::from_str_radix(raw.offset, 16)?,
+            device: Device::from_raw(raw.dev_major, raw.dev_minor)?,
+            inode: raw.inode.parse()?,
+            pathname: options.classify(raw.pathname),
+            extra: raw.extra.map(Into::into),
+            raw_line: if options.keep_raw_lines {
+                Some(line.into())
+            } else {
+                None
+            },
+            ..Default::default()
+        })
+    }
+}
+
+/// A builder for `Map`s; see [`Map::builder`].
+///
+/// Fields that aren't set keep their [`Map::default`] values.
+#[derive(Debug, Clone, Default)]
+pub struct MapBuilder {
+    map: Map,
+}
+
+impl MapBuilder {
+    /// Sets the map's address range.
+    pub fn address_range(mut self, address_range: AddressRange) -> MapBuilder {
+        self.map.address_range = address_range;
+        self
+    }
+
+    /// Sets the map's permissions.
+    pub fn permissions(mut self, permissions: Permissions) -> MapBuilder {
+        self.map.permissions = permissions;
+        self
+    }
+
+    /// Sets the map's offset within its source.
+    pub fn offset(mut self, offset: u64) -> MapBuilder {
+        self.map.offset = offset;
+        self
+    }
+
+    /// Sets the device that the map's inode belongs on.
+    pub fn device(mut self, device: Device) -> MapBuilder {
+        self.map.device = device;
+        self
+    }
+
+    /// Sets the map's inode.
+    pub fn inode(mut self, inode: u64) -> MapBuilder {
+        self.map.inode = inode;
+        self
+    }
+
+    /// Sets the map's pathname.
+    pub fn pathname(mut self, pathname: Pathname) -> MapBuilder {
+        self.map.pathname = pathname;
+        self
+    }
+
+    /// Sets whether the map's backing file has been deleted.
+    pub fn deleted(mut self, deleted: bool) -> MapBuilder {
+        self.map.deleted = deleted;
+        self
+    }
+
+    /// Sets the map's extra annotation, as emitted by some Android kernels.
+    pub fn extra(mut self, extra: Option<String>) -> MapBuilder {
+        self.map.extra = extra;
+        self
+    }
+
+    /// Returns the built map.
+    pub fn build(self) -> Map {
+        self.map
+    }
+}
+
+/// The fields of a single line of a maps file, split and validated but not converted.
+///
+/// This is the first half of [`Map`] parsing, for consumers that want this crate's
+/// tokenization but their own typing: every field borrows from the parsed line
+/// exactly as it appeared, so e.g. addresses keep their original width and case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawMap<'a> {
+    /// The start of the address range, in hex.
+    pub address_begin: &'a str,
+    /// The end of the address range, in hex.
+    pub address_end: &'a str,
+    /// The permissions, e.g. `r-xp`.
+    pub permissions: &'a str,
+    /// The offset into the mapped file, in hex.
+    pub offset: &'a str,
+    /// The device's major number, in hex.
+    pub dev_major: &'a str,
+    /// The device's minor number, in hex.
+    pub dev_minor: &'a str,
+    /// The inode, in decimal.
+    pub inode: &'a str,
+    /// The pathname, which may be empty.
+    pub pathname: &'a str,
+    /// The Android `@`-suffixed annotation after the pathname, if present (without the `@`).
+    pub extra: Option<&'a str>,
+}
+
+impl<'a> RawMap<'a> {
+    /// Splits a single line of a maps file into its fields.
+    ///
+    /// Each field is checked against the maps grammar (e.g. addresses must be hex
+    /// and permissions must be four characters like `rw-p`), but nothing is
+    /// converted, so values too large for their eventual types are accepted here.
+    /// Empty and whitespace-only lines fail with [`Error::EmptyLine`].
+    pub fn parse(line: &'a str) -> Result<RawMap<'a>, Error> {
+        RawMap::parse_with(line, &Default::default())
+    }
+
+    /// Splits a single line of a maps file into its fields, like [`RawMap::parse`],
+    /// but with the given `options`.
+    ///
+    /// Only [`ParseOptions::android_annotations`] affects splitting.
+    pub fn parse_with(line: &'a str, options: &ParseOptions) -> Result<RawMap<'a>, Error> {
+        if line.trim().is_empty() {
+            return Err(Error::EmptyLine);
+        }
+
+        let rule = if options.android_annotations {
+            Rule::android_map
+        } else {
+            Rule::map
+        };
+
+        // NOTE(ww): The map rules are singular, so this next + unwrap is safe after
+        // a successful parse.
+        let parsed = MapParser::parse(rule, line)?.next().unwrap();
+        let mut raw = RawMap {
+            address_begin: "",
+            address_end: "",
+            permissions: "",
+            offset: "",
+            dev_major: "",
+            dev_minor: "",
+            inode: "",
+            pathname: "",
+            extra: None,
+        };
+
+        for entry in parsed.into_inner() {
+            match entry.as_rule() {
+                Rule::address_range => {
+                    let mut address_range = entry.into_inner();
+                    raw.address_begin = address_range.next().unwrap().as_str();
+                    raw.address_end = address_range.next().unwrap().as_str();
+                }
+                Rule::permissions => {
+                    raw.permissions = entry.as_str();
+                }
+                Rule::offset => {
+                    raw.offset = entry.as_str();
+                }
+                Rule::device => {
+                    let mut device = entry.into_inner();
+                    raw.dev_major = device.next().unwrap().as_str();
+                    raw.dev_minor = device.next().unwrap().as_str();
+                }
+                Rule::inode => {
+                    raw.inode = entry.as_str();
+                }
+                Rule::pathname | Rule::android_pathname => {
+                    raw.pathname = entry.as_str();
+                }
+                Rule::extra => {
+                    raw.extra = Some(entry.as_str());
+                }
+                // A line without a pathname (or the space before it) ends after the inode.
+                Rule::EOI => {}
+                // NOTE(ww): There are other rules, but we should never be able to match them in this context.
+                _ => {
+                    unreachable!();
+                }
+            }
+        }
+
+        Ok(raw)
+    }
+}
+
+/// A wrapper structure for consuming individual `Map`s from a reader.
+pub struct MapsLines<T> {
+    lines: Lines<T>,
+}
+
+impl<T> MapsLines<T> {
+    /// Creates a new `Maps` from the given `reader`.
+    pub fn new(lines: Lines<T>) -> MapsLines<T> {
+        MapsLines { lines }
+    }
+}
+
+impl<T> Iterator for MapsLines<T>
+where
+    T: BufRead,
+{
+    type Item = Result<Map, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.lines.next() {
+            Some(Ok(line)) => Some(Map::parse(&line)),
+            Some(Err(e)) => Some(Err(e.into())),
+            None => None,
+        }
+    }
+}
+
+/// Statistics about the lines consumed by a `Maps`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ParseStats {
+    /// The number of lines successfully parsed into maps.
+    pub parsed: usize,
+    /// The number of lines that failed to parse, or couldn't be read.
+    pub failed: usize,
+    /// The number of lines that were skipped, rather than yielded as either maps or errors.
+    pub skipped: usize,
+}
+
+type PseudoPathClassifier = Arc<dyn Fn(&str) -> Option<Pathname> + Send + Sync>;
+
+/// Options that control how a `Maps` parses its input.
+#[derive(Clone, Default)]
+pub struct ParseOptions {
+    lenient: bool,
+    record_skipped: bool,
+    keep_raw_lines: bool,
+    record_source_offsets: bool,
+    android_annotations: bool,
+    pseudo_paths: HashMap<String, Pathname>,
+    classify_pseudo_path: Option<PseudoPathClassifier>,
+}
+
+impl ParseOptions {
+    /// Returns the default (strict) options, under which every line that
+    /// fails to parse is yielded as an error.
+    pub fn new() -> ParseOptions {
+        Default::default()
+    }
+
+    /// Returns lenient options, under which lines that fail to parse are
+    /// skipped rather than yielded as errors.
+    ///
+    /// I/O errors are still yielded, since they can't be skipped past.
+    pub fn lenient() -> ParseOptions {
+        ParseOptions {
+            lenient: true,
+            ..Default::default()
+        }
+    }
+
+    /// Sets whether skipped lines are recorded, for retrieval with [`Maps::skipped`].
+    pub fn record_skipped(mut self, record_skipped: bool) -> ParseOptions {
+        self.record_skipped = record_skipped;
+        self
+    }
+
+    /// Sets whether each map keeps the line that it was parsed from, for retrieval
+    /// with [`Map::raw_line`].
+    ///
+    /// This is off by default, since it roughly doubles the memory used by each map.
+    pub fn keep_raw_lines(mut self, keep_raw_lines: bool) -> ParseOptions {
+        self.keep_raw_lines = keep_raw_lines;
+        self
+    }
+
+    /// Sets whether each map records the byte offset of the line that it was parsed
+    /// from, for retrieval with [`Map::source_offset`].
+    pub fn record_source_offsets(mut self, record_source_offsets: bool) -> ParseOptions {
+        self.record_source_offsets = record_source_offsets;
+        self
+    }
+
+    /// Sets whether a trailing `@` and digits after a pathname are split off into
+    /// [`Map::extra`], as annotated by some Android kernels (e.g. `libc.so@1234`).
+    ///
+    /// This is off by default, since such a suffix is ambiguous with a real path:
+    /// `/tmp/x@2` is a perfectly good filename on any platform.
+    pub fn android_annotations(mut self, android_annotations: bool) -> ParseOptions {
+        self.android_annotations = android_annotations;
+        self
+    }
+
+    /// Registers an additional pseudo-path, so that maps whose pathname is exactly
+    /// `name` (e.g. `"[mything]"`) are parsed as `pathname` rather than as `OtherPseudo`.
+    ///
+    /// The pseudo-paths that this crate already knows about take precedence.
+    pub fn pseudo_path<S: Into<String>>(mut self, name: S, pathname: Pathname) -> ParseOptions {
+        self.pseudo_paths.insert(name.into(), pathname);
+        self
+    }
+
+    /// Sets a callback for classifying unrecognized pseudo-paths, i.e. bracketed
+    /// pathnames that are neither known to this crate nor registered with
+    /// [`ParseOptions::pseudo_path`].
+    ///
+    /// The callback receives the pathname (brackets included); if it returns `None`,
+    /// the map is parsed as `OtherPseudo` as usual.
+    pub fn classify_pseudo_path<F>(mut self, classify: F) -> ParseOptions
+    where
+        F: Fn(&str) -> Option<Pathname> + Send + Sync + 'static,
+    {
+        self.classify_pseudo_path = Some(Arc::new(classify));
+        self
+    }
+}
+
+impl ParseOptions {
+    /// Classifies a raw pathname field, taking any user-registered pseudo-paths
+    /// and classification callback into account.
+    fn classify(&self, pathname: &str) -> Pathname {
+        match Pathname::classify(pathname) {
+            // The user might also know about some pseudo-paths that we don't,
+            // and might want a chance to classify the rest.
+            Pathname::OtherPseudo(_) | Pathname::Path(_)
+                if self.pseudo_paths.contains_key(pathname) =>
+            {
+                self.pseudo_paths[pathname].clone()
+            }
+            Pathname::OtherPseudo(pseudo) => self
+                .classify_pseudo_path
+                .as_ref()
+                .and_then(|classify| classify(&pseudo))
+                .unwrap_or(Pathname::OtherPseudo(pseudo)),
+            pathname => pathname,
+        }
+    }
+}
+
+impl std::fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("lenient", &self.lenient)
+            .field("record_skipped", &self.record_skipped)
+            .field("keep_raw_lines", &self.keep_raw_lines)
+            .field("record_source_offsets", &self.record_source_offsets)
+            .field("android_annotations", &self.android_annotations)
+            .field("pseudo_paths", &self.pseudo_paths)
+            .field("classify_pseudo_path", &self.classify_pseudo_path.is_some())
+            .finish()
+    }
+}
+
+/// A line skipped during lenient parsing: its (1-based) line number and the
+/// error it failed to parse with.
+pub type SkippedLine = (usize, Error);
+
+/// A wrapper structure for consuming individual `Map`s from a reader.
+pub struct Maps<T: BufRead> {
+    reader: T,
+    options: ParseOptions,
+    line_number: usize,
+    byte_offset: u64,
+    stats: ParseStats,
+    skipped: Vec<SkippedLine>,
+}
+
+impl<T: BufRead> Maps<T> {
+    /// Creates a new `Maps` from the given `reader`.
+    pub fn new(reader: T) -> Maps<T> {
+        Maps::with_options(reader, Default::default())
+    }
+
+    /// Creates a new `Maps` from the given `reader`, parsing with the given `options`.
+    pub fn with_options(reader: T, options: ParseOptions) -> Maps<T> {
+        Maps {
+            reader,
+            options,
+            line_number: 0,
+            byte_offset: 0,
+            stats: Default::default(),
+            skipped: vec![],
+        }
+    }
+
+    /// Returns statistics about the lines consumed so far.
+    pub fn stats(&self) -> ParseStats {
+        self.stats
+    }
+
+    /// Returns the lines skipped so far in lenient mode.
+    ///
+    /// This is always empty unless skipped lines are being recorded; see
+    /// [`ParseOptions::record_skipped`].
+    pub fn skipped(&self) -> &[SkippedLine] {
+        &self.skipped
+    }
+
+    /// Folds every map into an accumulator, stopping at the first error.
+    ///
+    /// This is the fallible equivalent of [`Iterator::fold`]: `f` is called with
+    /// the accumulator and each successfully parsed map, and the first error
+    /// encountered is returned instead of the accumulator.
+    pub fn fold_result<B, F>(mut self, init: B, mut f: F) -> Result<B, Error>
+    where
+        F: FnMut(B, Map) -> B,
+    {
+        self.try_fold(init, |acc, map| Ok(f(acc, map?)))
+    }
+
+    /// Maps each successfully parsed map to zero or more values, flattening the results.
+    ///
+    /// This is the fallible equivalent of [`Iterator::flat_map`]: `f` is called
+    /// with each successfully parsed map, and errors are passed through unchanged.
+    /// Like `flat_map`, this is lazy.
+    pub fn flat_map_result<U, I, F>(self, mut f: F) -> impl Iterator<Item = Result<U, Error>>
+    where
+        I: IntoIterator<Item = U>,
+        F: FnMut(Map) -> I,
+    {
+        self.flat_map(move |map| {
+            let (values, err) = match map {
+                Ok(map) => (Some(f(map)), None),
+                Err(e) => (None, Some(Err(e))),
+            };
+
+            values.into_iter().flatten().map(Ok).chain(err)
+        })
+    }
+
+    /// Consumes this `Maps`, checking the parsed maps for internal consistency.
+    ///
+    /// Returns every violation found (see [`validate_maps`]), or the first
+    /// parse error, if any.
+    pub fn validate(self) -> Result<Vec<ValidationError>, Error> {
+        let maps = self.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(validate_maps(&maps))
+    }
+
+    /// Consumes this `Maps`, grouping its executable, file-backed maps by their files.
+    ///
+    /// Each group contains consecutive (by address) executable segments of a
+    /// single loaded file, and groups are ordered by their lowest addresses.
+    /// This answers the question "which file is mapped at address X?" for
+    /// symbolization. Returns the first parse error, if any.
+    pub fn collect_by_executable_regions(self) -> Result<Vec<(PathBuf, Vec<Map>)>, Error> {
+        let mut maps = self
+            .filter_with(MapFilter::new().executable().file_backed())
+            .collect::<Result<Vec<_>, _>>()?;
+        maps.sort_by_key(|map| map.address_range.begin);
+
+        let mut groups: Vec<(PathBuf, Vec<Map>)> = vec![];
+        for map in maps {
+            let path = match &map.pathname {
+                Pathname::Path(path) => PathBuf::from(path),
+                _ => unreachable!(),
+            };
+
+            match groups.last_mut() {
+                Some((last, group)) if *last == path => group.push(map),
+                _ => groups.push((path, vec![map])),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Consumes this `Maps`, yielding only the maps that match `filter`.
+    ///
+    /// Parse errors are passed through unfiltered.
+    pub fn filter_with(self, filter: MapFilter) -> impl Iterator<Item = Result<Map, Error>> {
+        self.filter(move |map| match map {
+            Ok(map) => filter.matches(map),
+            Err(_) => true,
+        })
+    }
+
+    /// Consumes this `Maps`, dropping each map whose address range is identical
+    /// to that of the map immediately before it.
+    ///
+    /// Only consecutive duplicates are dropped, which is sufficient since maps
+    /// are sorted by address. Errors are passed through, and don't reset the
+    /// comparison.
+    pub fn deduplicate_by_address(self) -> impl Iterator<Item = Result<Map, Error>> {
+        let mut last: Option<AddressRange> = None;
+        self.filter(move |map| match map {
+            Ok(map) if last.as_ref() == Some(&map.address_range) => false,
+            Ok(map) => {
+                last = Some(map.address_range.clone());
+                true
+            }
+            Err(_) => true,
+        })
+    }
+}
+
+impl<T: BufRead + Seek> Maps<T> {
+    /// Rewinds this `Maps` to the beginning of its input, so that it can be
+    /// iterated over again (e.g. once to count, and once to process).
+    ///
+    /// This also resets the line numbering, source offsets, statistics, and skipped lines.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.line_number = 0;
+        self.byte_offset = 0;
+        self.stats = Default::default();
+        self.skipped.clear();
+
+        Ok(())
+    }
+}
+
+impl<T: BufRead> Iterator for Maps<T> {
+    type Item = Result<Map, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut line_buf = vec![];
+            match self.reader.read_until(b'\n', &mut line_buf) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    let offset = self.byte_offset;
+                    self.line_number += 1;
+                    self.byte_offset += len as u64;
+
+                    // NOTE(ww): Annoying: the Lines iterator yields lines
+                    // without their trailing delimiters, but read_line includes them.
+                    if line_buf.ends_with(b"\n") {
+                        line_buf.pop();
+                    }
+
+                    match Map::parse_bytes_with(&line_buf, &self.options) {
+                        Ok(mut map) => {
+                            if self.options.record_source_offsets {
+                                map.source_offset = Some(offset);
+                            }
+                            self.stats.parsed += 1;
+                            return Some(Ok(map));
+                        }
+                        Err(e) if self.options.lenient => {
+                            self.stats.skipped += 1;
+                            if self.options.record_skipped {
+                                self.skipped.push((self.line_number, e));
+                            }
+                        }
+                        Err(e) => {
+                            self.stats.failed += 1;
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.stats.failed += 1;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+/// A process ID.
+///
+/// Functions that take a pid accept anything that converts into a `Pid`, so
+/// `pid_t`s, `std::process::id()`'s `u32`s, and (with the `nix` feature)
+/// `nix::unistd::Pid`s can all be passed directly. Those conversions never fail;
+/// instead, functions that use a pid fail with [`Error::InvalidPid`] if it's negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(pid_t);
+
+impl Pid {
+    /// Creates a `Pid` from a raw `pid_t`, failing with [`Error::InvalidPid`] if it's negative.
+    pub fn new(pid: pid_t) -> Result<Pid, Error> {
+        if pid < 0 {
+            return Err(Error::InvalidPid(pid.into()));
+        }
+
+        Ok(Pid(pid))
+    }
+
+    /// Returns this pid as a raw `pid_t`.
+    pub fn as_raw(self) -> pid_t {
+        self.0
+    }
+
+    /// Returns this pid if it's valid, or [`Error::InvalidPid`] if it's negative.
+    pub(crate) fn validate(self) -> Result<Pid, Error> {
+        Pid::new(self.0)
+    }
+}
+
+/// Stores `pid` as-is, even if it's negative; use [`Pid::new`] to check it up front.
+impl From<pid_t> for Pid {
+    fn from(pid: pid_t) -> Pid {
+        Pid(pid)
+    }
+}
+
+/// Stores `pid` as a `pid_t`. Values too large for a `pid_t` wrap around to negative
+/// pids, which are then rejected like any other negative pid.
+impl From<u32> for Pid {
+    fn from(pid: u32) -> Pid {
+        Pid(pid as pid_t)
+    }
+}
+
+#[cfg(feature = "nix")]
+impl From<nix::unistd::Pid> for Pid {
+    fn from(pid: nix::unistd::Pid) -> Pid {
+        Pid::from(pid.as_raw())
+    }
+}
+
+impl std::fmt::Display for Pid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A wrapper around `Maps` that tags each map with the pid that it came from,
+/// for analyses that merge the maps of several processes.
+pub struct MapsWithContext<T: BufRead> {
+    pid: Pid,
+    maps: Maps<T>,
+}
+
+impl<T: BufRead> MapsWithContext<T> {
+    /// Returns the pid that these maps came from.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the underlying `Maps`, e.g. for its [`Maps::stats`].
+    pub fn maps(&self) -> &Maps<T> {
+        &self.maps
+    }
+
+    /// Consumes this wrapper, returning the underlying `Maps`.
+    pub fn into_inner(self) -> Maps<T> {
+        self.maps
+    }
+}
+
+impl<T: BufRead> Iterator for MapsWithContext<T> {
+    type Item = Result<(Pid, Map), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pid = self.pid;
+        self.maps.next().map(|map| map.map(|map| (pid, map)))
+    }
+}
+
+/// Returns the version of this crate, e.g. for diagnostic logging.
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Returns an iterable `Maps` for the given pid.
+///
+/// If the process exists but is hidden from us by procfs's `hidepid` mount option,
+/// this returns [`Error::Hidden`] rather than a "not found" I/O error. See
+/// [`visibility`] for details.
+pub fn from_pid(pid: impl Into<Pid>) -> Result<Maps<BufReader<File>>, Error> {
+    from_pid_in(Path::new("/proc"), pid.into())
+}
+
+fn from_pid_in(proc_root: &Path, pid: Pid) -> Result<Maps<BufReader<File>>, Error> {
+    let pid = pid.validate()?;
+
+    match File::open(proc_root.join(pid.to_string()).join("maps")) {
+        Ok(f) => Ok(Maps::new(BufReader::new(f))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(visibility::hidden_or(proc_root, pid, e))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns an iterable `Maps` for a process in another pid namespace (e.g. a container),
+/// where `host_pid` is any process in that namespace as seen from ours (e.g. the
+/// container's init), and `ns_pid` is the target process's pid within the namespace.
+///
+/// The maps are read via `/proc/<host_pid>/root/proc/<ns_pid>/maps`, which requires
+/// the namespace to have `/proc` mounted, as well as permission to access the host
+/// process's root (usually `CAP_SYS_PTRACE`). If they can't be opened, this returns
+/// [`Error::NamespaceInaccessible`].
+pub fn from_pid_namespace(
+    host_pid: impl Into<Pid>,
+    ns_pid: impl Into<Pid>,
+) -> Result<Maps<BufReader<File>>, Error> {
+    let path = Path::new("/proc")
+        .join(host_pid.into().validate()?.to_string())
+        .join("root/proc")
+        .join(ns_pid.into().validate()?.to_string())
+        .join("maps");
+
+    match File::open(&path) {
+        Ok(f) => Ok(Maps::new(BufReader::new(f))),
+        Err(source) => Err(Error::NamespaceInaccessible { path, source }),
+    }
+}
+
+/// Rewrites a file-backed pathname from the given pid's maps into a path that's
+/// openable from our mount namespace, i.e. `/proc/<pid>/root/<path>`.
+///
+/// Paths in a process's maps are relative to its own root, which differs from ours
+/// when it's in a container (or chroot). Pseudo-paths, anonymous maps, and paths that
+/// aren't absolute (e.g. `anon_inode:[...]`) return `None`.
+pub fn resolve_in_namespace(pid: impl Into<Pid>, pathname: &Pathname) -> Option<PathBuf> {
+    resolve_in_proc_root(Path::new("/proc"), pid.into(), pathname)
+}
+
+fn resolve_in_proc_root(proc_root: &Path, pid: Pid, pathname: &Pathname) -> Option<PathBuf> {
+    let pid = pid.validate().ok()?;
+
+    match pathname {
+        Pathname::Path(path) => {
+            let relative = Path::new(path).strip_prefix("/").ok()?;
+            Some(proc_root.join(pid.to_string()).join("root").join(relative))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the maps belonging to the given pid's main executable, i.e. the
+/// program binary itself rather than any of its libraries.
+pub fn main_executable_maps(pid: impl Into<Pid>) -> Result<Vec<Map>, Error> {
+    let pid = pid.into().validate()?;
+    let exe = Path::new("/proc").join(pid.to_string()).join("exe");
+
+    from_pid(pid)?
+        .filter(|map| match map {
+            Ok(map) => map.is_main_executable(&exe),
+            Err(_) => true,
+        })
+        .collect()
+}
+
+/// Returns the base address of the given pid's main executable, i.e. the
+/// lowest address that it's mapped at.
+pub fn main_executable_base(pid: impl Into<Pid>) -> Result<Option<u64>, Error> {
+    Ok(main_executable_maps(pid)?
+        .iter()
+        .map(|map| map.address_range.begin)
+        .min())
+}
+
+/// Returns an iterable `Maps` parsed from the given file.
+pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Maps<BufReader<File>>, Error> {
+    let reader = {
+        let f = File::open(path)?;
+        BufReader::new(f)
+    };
+
+    Ok(Maps::new(reader))
+}
+
+/// Returns an iterable `Maps` parsed from the given file, like [`from_path`], but
+/// retrying up to `retries` more times if opening the file fails.
+///
+/// Under extreme memory pressure, the kernel can briefly fail to open a `maps` file
+/// that would otherwise be readable. This makes up to `retries + 1` attempts in
+/// total, sleeping for `delay` between them, and returns the first success or the
+/// last error. The sleeps block the calling thread.
+pub fn from_path_with_retry<P: AsRef<Path>>(
+    path: P,
+    retries: u32,
+    delay: Duration,
+) -> Result<Maps<BufReader<File>>, Error> {
+    let path = path.as_ref();
+
+    let mut result = from_path(path);
+    for _ in 0..retries {
+        if result.is_ok() {
+            break;
+        }
+
+        thread::sleep(delay);
+        result = from_path(path);
+    }
+
+    result
+}
+
+/// Returns every map that parses successfully from the given file, along with
+/// statistics about the lines consumed.
+///
+/// The file is parsed leniently (see [`ParseOptions::lenient`]), so lines that fail
+/// to parse are counted in [`ParseStats::skipped`] rather than returned. A failure
+/// to open or read the file is still returned as an error.
+pub fn from_path_with_stats<P: AsRef<Path>>(path: P) -> Result<(Vec<Map>, ParseStats), Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut maps = Maps::with_options(reader, ParseOptions::lenient());
+
+    let valid = maps.by_ref().collect::<Result<Vec<_>, _>>()?;
+    Ok((valid, maps.stats()))
+}
+
+/// Returns every map that parses successfully from the given file, along with
+/// the (1-based) line number and error for every line that doesn't.
+///
+/// This is best-effort parsing with full diagnostics: malformed lines are
+/// skipped as in [`ParseOptions::lenient`], but recorded rather than discarded.
+/// A failure to open or read the file is still returned as an error.
+pub fn from_path_collecting<P: AsRef<Path>>(
+    path: P,
+) -> Result<(Vec<Map>, Vec<SkippedLine>), Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut maps = Maps::with_options(reader, ParseOptions::lenient().record_skipped(true));
+
+    let valid = maps.by_ref().collect::<Result<Vec<_>, _>>()?;
+    Ok((valid, maps.skipped))
+}
+
+/// Returns an iterable `Maps` parsed from the given file, buffered with
+/// the given `capacity`.
+///
+/// [`from_path`] uses the standard library's default buffer capacity (currently 8 KiB),
+/// which is plenty for most processes. For processes with very large maps
+/// files (many thousands of maps), a larger capacity like 64 KiB reduces
+/// the number of `read(2)` calls needed to consume the file.
+pub fn from_path_with_capacity<P: AsRef<Path>>(
+    path: P,
+    capacity: usize,
+) -> Result<Maps<BufReader<File>>, Error> {
+    let reader = {
+        let f = File::open(path)?;
+        BufReader::with_capacity(capacity, f)
+    };
+
+    Ok(Maps::new(reader))
+}
+
+/// Returns an iterable `Maps` parsed from the given string.
+pub fn from_str(maps_data: &str) -> Maps<&[u8]> {
+    Maps::new(maps_data.as_bytes())
+}
+
+/// Returns an iterable `Maps` parsed from the given bytes.
+///
+/// Unlike [`from_str`], the data doesn't have to be valid UTF-8: only the numeric
+/// fields have to be ASCII, and pathnames that aren't valid UTF-8 are preserved
+/// by [`Map::pathname_bytes`].
+pub fn from_bytes(maps_data: &[u8]) -> Maps<&[u8]> {
+    Maps::new(maps_data)
+}
+
+/// Returns an iterable `MapsWithContext` parsed from the given `reader`, which
+/// contains the maps of the given pid.
+///
+/// This is useful when the maps don't come from `/proc` directly (e.g. when
+/// they were captured elsewhere), but their source pid is still known.
+pub fn from_reader_for_pid<T: BufRead>(reader: T, pid: impl Into<Pid>) -> MapsWithContext<T> {
+    MapsWithContext {
+        pid: pid.into(),
+        maps: Maps::new(reader),
+    }
+}
+
+/// Returns an iterable `Maps` parsed from the given [Lines].
+pub fn from_lines<T>(maps_lines: Lines<T>) -> MapsLines<T> {
     MapsLines::new(maps_lines)
 }
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
+/// Returns whether `maps` is sorted by address, as the kernel emits them.
+///
+/// Maps are sorted if each begins no earlier than the one before it; overlaps
+/// and duplicates are checked separately (see [`find_duplicate_ranges`]).
+pub fn is_sorted_by_address(maps: &[Map]) -> bool {
+    validate_sorted(maps).is_ok()
+}
+
+/// Checks that `maps` is sorted by address, returning [`Error::Unsorted`] for
+/// the first out-of-order pair of maps otherwise.
+///
+/// Several lookups (e.g. [`snapshot::MapsSnapshot::resolve`]) binary search their
+/// maps, and so silently misbehave on unsorted input; this lets callers check first.
+pub fn validate_sorted(maps: &[Map]) -> Result<(), Error> {
+    match maps
+        .windows(2)
+        .position(|pair| pair[1].address_range.begin < pair[0].address_range.begin)
+    {
+        Some(i) => Err(Error::Unsorted {
+            index: i + 1,
+            previous: maps[i].address_range.clone(),
+            found: maps[i + 1].address_range.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// An invariant that a map violates; see [`validate_maps`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Violation {
+    /// The map ends before it begins.
+    InvertedRange,
+    /// The map is empty, i.e. it begins and ends at the same address.
+    EmptyRange,
+    /// The map begins before the map preceding it.
+    Unsorted {
+        /// The range of the preceding map.
+        previous: AddressRange,
+    },
+    /// The map overlaps an earlier map.
+    Overlap {
+        /// The range of the earlier map.
+        other: AddressRange,
+    },
+    /// The map has no inode, but has a device other than `00:00`.
+    DeviceWithoutInode,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Violation::InvertedRange => write!(f, "range ends before it begins"),
+            Violation::EmptyRange => write!(f, "range is empty"),
+            Violation::Unsorted { previous } => {
+                write!(f, "begins before the preceding map at {}", previous)
+            }
+            Violation::Overlap { other } => write!(f, "overlaps the map at {}", other),
+            Violation::DeviceWithoutInode => write!(f, "has a device but no inode"),
+        }
+    }
+}
+
+/// A map that violates an invariant, as returned by [`validate_maps`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ValidationError {
+    /// The index of the offending map.
+    pub index: usize,
+    /// The offending map.
+    pub map: Map,
+    /// The violated invariant.
+    pub violation: Violation,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "map {} ({}): {}",
+            self.index, self.map.address_range, self.violation
+        )
+    }
+}
+
+/// Checks `maps` for internal consistency, returning every violation in order.
+///
+/// Maps produced by the kernel are sorted by address, don't overlap, aren't
+/// empty or inverted, and only have a device if they have an inode. A violation
+/// usually means a kernel bug, a malformed or injected maps file, or maps that
+/// were merged from several sources. A map can violate several invariants at once.
+///
+/// Overlaps are reported for the later of the two maps (in address order), and
+/// aren't checked for empty or inverted maps.
+pub fn validate_maps(maps: &[Map]) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    let mut error = |index: usize, violation| {
+        errors.push(ValidationError {
+            index,
+            map: maps[index].clone(),
+            violation,
+        })
+    };
+
+    // NOTE(ww): We sweep over the maps in address order, so that overlaps are
+    // found in unsorted input too. Each map that overlaps anything overlaps the
+    // map before it (in address order) that extends furthest.
+    let mut by_address = (0..maps.len())
+        .filter(|i| maps[*i].address_range.begin < maps[*i].address_range.end)
+        .collect::<Vec<_>>();
+    by_address.sort_by_key(|i| maps[*i].address_range.begin);
+
+    let mut overlaps = vec![None; maps.len()];
+    let mut furthest: Option<&Map> = None;
+    for index in by_address {
+        let range = &maps[index].address_range;
+        if let Some(other) = furthest {
+            if range.begin < other.address_range.end {
+                overlaps[index] = Some(other.address_range.clone());
+            }
+        }
+        if furthest.is_none_or(|other| other.address_range.end < range.end) {
+            furthest = Some(&maps[index]);
+        }
+    }
+
+    for (index, (map, overlap)) in maps.iter().zip(overlaps).enumerate() {
+        let range = &map.address_range;
+
+        if range.end < range.begin {
+            error(index, Violation::InvertedRange);
+        } else if range.end == range.begin {
+            error(index, Violation::EmptyRange);
+        }
+
+        if let Some(previous) = index.checked_sub(1).map(|i| &maps[i]) {
+            if range.begin < previous.address_range.begin {
+                error(
+                    index,
+                    Violation::Unsorted {
+                        previous: previous.address_range.clone(),
+                    },
+                );
+            }
+        }
+
+        if let Some(other) = overlap {
+            error(index, Violation::Overlap { other });
+        }
+
+        if map.inode == 0 && (map.device.major, map.device.minor) != (0, 0) {
+            error(index, Violation::DeviceWithoutInode);
+        }
+    }
+
+    errors
+}
+
+/// Returns the `[heap]` map if it's executable, which is a strong indicator of
+/// exploitation (or of a very old binary that requested `READ_IMPLIES_EXEC`).
+///
+/// Returns `None` if the heap isn't executable, or if there's no `[heap]` at all.
+pub fn executable_heap(maps: impl IntoIterator<Item = Map>) -> Option<Map> {
+    maps.into_iter()
+        .find(|map| map.pathname == Pathname::Heap && map.permissions.executable)
+}
+
+/// Returns every address range that appears more than once in `maps`, in
+/// ascending order.
+///
+/// Each duplicated range is only returned once, no matter how many times it appears.
+/// Unlike checking for overlaps, this only detects exact duplicates, which
+/// usually indicate malformed or incorrectly merged input.
+pub fn find_duplicate_ranges(maps: &[Map]) -> Vec<AddressRange> {
+    let mut ranges = maps
+        .iter()
+        .map(|map| (map.address_range.begin, map.address_range.end))
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+
+    let mut duplicates: Vec<AddressRange> = vec![];
+    for pair in ranges.windows(2) {
+        let (begin, end) = pair[0];
+        if pair[0] == pair[1]
+            && duplicates
+                .last()
+                .is_none_or(|last| last.begin != begin || last.end != end)
+        {
+            duplicates.push(AddressRange { begin, end });
+        }
+    }
+
+    duplicates
+}
+
+/// Returns the maps in `child` that it inherited from `parent` across a `fork`,
+/// in `child`'s order.
+///
+/// A child map is inherited if some parent map has the same address range,
+/// permissions, and backing (offset, device, inode, and pathname). Anything else
+/// was mapped, remapped, or `mprotect`ed by the child after the fork (or by the
+/// parent, if it's still running).
+pub fn inherited_from_parent(parent: &[Map], child: &[Map]) -> Vec<Map> {
+    let mut by_range = HashMap::<(u64, u64), Vec<&Map>>::new();
+    for map in parent {
+        by_range
+            .entry((map.address_range.begin, map.address_range.end))
+            .or_default()
+            .push(map);
+    }
+
+    child
+        .iter()
+        .filter(|map| {
+            by_range
+                .get(&(map.address_range.begin, map.address_range.end))
+                .is_some_and(|candidates| {
+                    candidates.iter().any(|other| {
+                        other.permissions == map.permissions
+                            && other.offset == map.offset
+                            && other.device == map.device
+                            && other.inode == map.inode
+                            && other.pathname == map.pathname
+                    })
+                })
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+
+    use glob::glob;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_map() {
+        let map =
+            Map::parse("5608dd391000-5608dd3be000 r--p 00000000 08:11 6572575 /bin/bash").unwrap();
+
+        assert_eq!(map.address_range.begin, 0x5608dd391000);
+        assert_eq!(map.address_range.end, 0x5608dd3be000);
+
+        assert!(map.permissions.readable);
+        assert!(!map.permissions.writable);
+        assert!(!map.permissions.executable);
+        assert!(!map.permissions.shared);
+        assert!(map.permissions.private);
+
+        assert_eq!(map.offset, 0);
+
+        assert_eq!(map.device.major, 8);
+        assert_eq!(map.device.minor, 17);
+
+        assert_eq!(map.inode, 6572575);
+
+        assert_eq!(map.pathname, Pathname::Path("/bin/bash".into()));
+    }
+
+    #[test]
+    fn test_debug_hex() {
+        let map =
+            Map::parse("7f1c2e400000-7f1c2e5c8000 r-xp 00022000 fd:01 6572575 /bin/bash").unwrap();
+
+        assert_eq!(
+            format!("{:?}", map.address_range),
+            "AddressRange { begin: 0x7f1c2e400000, end: 0x7f1c2e5c8000 }"
+        );
+        assert_eq!(
+            format!("{:?}", map.device),
+            "Device { major: 0xfd, minor: 0x1 }"
+        );
+        assert_eq!(
+            format!("{:?}", map),
+            "Map { \
+             address_range: AddressRange { begin: 0x7f1c2e400000, end: 0x7f1c2e5c8000 }, \
+             permissions: Permissions { readable: true, writable: false, executable: true, shared: false, private: true }, \
+             offset: 0x22000, \
+             device: Device { major: 0xfd, minor: 0x1 }, \
+             inode: 6572575, \
+             pathname: Path(\"/bin/bash\"), \
+             deleted: false, \
+             extra: None, \
+             raw_line: None, \
+             source_offset: None, \
+             pathname_bytes: None \
+             }"
+        );
+
+        assert_eq!(
+            format!("{:#?}", map.address_range),
+            "AddressRange {\n    begin: 0x7f1c2e400000,\n    end: 0x7f1c2e5c8000,\n}"
+        );
+    }
+
+    #[test]
+    fn test_parse_arm_pseudo_paths() {
+        let vectors =
+            Map::parse("ffff0000-ffff1000 r-xp 00000000 00:00 0          [vectors]").unwrap();
+        assert_eq!(vectors.pathname, Pathname::Vectors);
+
+        let sigpage =
+            Map::parse("b6f04000-b6f05000 r-xp 00000000 00:00 0          [sigpage]").unwrap();
+        assert_eq!(sigpage.pathname, Pathname::Sigpage);
+    }
+
+    #[test]
+    fn test_pathname_display() {
+        for line in
+            fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/arm.maps"))
+                .unwrap()
+                .lines()
+        {
+            let map = Map::parse(line).unwrap();
+            assert_eq!(
+                map.pathname.to_string(),
+                line.get(49..).unwrap_or_default().trim_end()
+            );
+        }
+
+        assert_eq!(
+            Pathname::OtherPseudo("[anon:scudo]".into()).to_string(),
+            "[anon:scudo]"
+        );
+    }
+
+    #[test]
+    fn test_from_reader_for_pid() {
+        let first = "00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n\
+                     00401000-00402000 rw-p 00001000 08:01 1234 /bin/true\n";
+        let second = "00400000-00403000 r-xp 00000000 08:01 5678 /bin/false\n";
+
+        let tagged = from_reader_for_pid(first.as_bytes(), 100)
+            .chain(from_reader_for_pid(second.as_bytes(), 200))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            tagged
+                .iter()
+                .map(|(pid, map)| (pid.as_raw(), map.address_range.end))
+                .collect::<Vec<_>>(),
+            vec![(100, 0x401000), (100, 0x402000), (200, 0x403000)]
+        );
+
+        let mut maps = from_reader_for_pid(first.as_bytes(), 100);
+        assert_eq!(maps.pid(), Pid::from(100));
+        assert_eq!(maps.by_ref().count(), 2);
+        assert_eq!(maps.maps().stats().parsed, 2);
+    }
+
+    #[test]
+    fn test_validate_sorted() {
+        let mut maps =
+            from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        assert!(is_sorted_by_address(&maps));
+        assert!(validate_sorted(&maps).is_ok());
+        assert!(is_sorted_by_address(&[]));
+
+        maps.swap(4, 7);
+        assert!(!is_sorted_by_address(&maps));
+        match validate_sorted(&maps) {
+            Err(Error::Unsorted {
+                index,
+                previous,
+                found,
+            }) => {
+                assert_eq!(index, 5);
+                assert_eq!(previous, maps[4].address_range);
+                assert_eq!(found, maps[5].address_range);
+            }
+            _ => panic!("expected an Unsorted error"),
+        }
+    }
+
+    #[test]
+    fn test_error_into_io_error() {
+        let kind = |err: Error| io::Error::from(err).kind();
+
+        let inner = io::Error::new(io::ErrorKind::TimedOut, "slow");
+        let err = io::Error::from(Error::Io(inner));
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "slow");
+
+        assert_eq!(
+            kind(Error::NamespaceInaccessible {
+                path: "/proc/1/root/proc/2/maps".into(),
+                source: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+            }),
+            io::ErrorKind::PermissionDenied
+        );
+
+        for err in [
+            Map::parse("garbage").unwrap_err(),
+            "ffffffffffffffffff:0".parse::<Device>().unwrap_err(),
+            Error::ConversionError(u8::try_from(256u32).unwrap_err()),
+            Error::MissingCounter("VmRSS".into()),
+            Error::Unsorted {
+                index: 1,
+                previous: AddressRange { begin: 2, end: 3 },
+                found: AddressRange { begin: 0, end: 1 },
+            },
+            Error::TooLarge {
+                what: "map size",
+                value: u64::MAX,
+            },
+            Error::MalformedRegion("?".into()),
+            Error::EmptyLine,
+        ] {
+            assert_eq!(kind(err), io::ErrorKind::InvalidData);
+        }
+
+        for err in [
+            Error::NotFileBacked,
+            Error::NotModuleBase,
+            Error::InvalidPattern("rwxq".into()),
+            Error::InvalidPid(-1),
+            Error::InvalidRange { begin: 2, end: 1 },
+        ] {
+            assert_eq!(kind(err), io::ErrorKind::InvalidInput);
+        }
+
+        assert_eq!(
+            kind(Error::DeletedBackingFile("/tmp/gone".into())),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(Error::Hidden(Pid::from(1))),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            kind(Error::BackingFileChanged {
+                expected: (Device { major: 8, minor: 1 }, 1),
+                found: (Device { major: 8, minor: 1 }, 2),
+            }),
+            io::ErrorKind::Other
+        );
+        assert_eq!(
+            kind(Error::CallbackPanicked("oops".into())),
+            io::ErrorKind::Other
+        );
+        assert_eq!(
+            kind(Error::CommandFailed {
+                command: "procstat",
+                status: std::os::unix::process::ExitStatusExt::from_raw(256),
+                stderr: "procstat: No such process".into(),
+            }),
+            io::ErrorKind::Other
+        );
+
+        // The original error is kept as the inner error.
+        let err = io::Error::from(Error::NotFileBacked);
+        assert_eq!(err.to_string(), Error::NotFileBacked.to_string());
+        assert_eq!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<Error>()
+                .unwrap()
+                .as_ref(),
+            &Error::NotFileBacked
+        );
+    }
+
+    #[cfg(any(feature = "freebsd", feature = "macos"))]
+    #[test]
+    fn test_run_command() {
+        use std::process::Command;
+
+        assert_eq!(
+            run_command("sh", Command::new("sh").args(["-c", "echo ok"])).unwrap(),
+            "ok\n"
+        );
+
+        match run_command(
+            "sh",
+            Command::new("sh").args(["-c", "echo gone >&2; exit 3"]),
+        ) {
+            Err(Error::CommandFailed {
+                command,
+                status,
+                stderr,
+            }) => {
+                assert_eq!(command, "sh");
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "gone");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(matches!(
+            run_command("nonexistent", &mut Command::new("/nonexistent/procstat")),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_error_eq() {
+        assert_eq!(
+            Error::from(io::Error::new(io::ErrorKind::NotFound, "gone")),
+            Error::Io(io::Error::new(io::ErrorKind::NotFound, "gone"))
+        );
+        assert_ne!(
+            Error::from(io::Error::new(io::ErrorKind::NotFound, "gone")),
+            Error::from(io::Error::new(io::ErrorKind::NotFound, "missing"))
+        );
+        assert_eq!(Error::NotFileBacked, Error::NotFileBacked);
+        assert_ne!(Error::NotFileBacked, Error::NotModuleBase);
+
+        // Different variants with the same message aren't equal.
+        assert_ne!(
+            Error::InvalidPattern("x".into()),
+            Error::DeletedBackingFile("x".into())
+        );
+
+        assert_eq!(
+            "08".parse::<Device>().unwrap_err(),
+            "08".parse::<Device>().unwrap_err()
+        );
+        assert_ne!(
+            "08".parse::<Device>().unwrap_err(),
+            "08:".parse::<Device>().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_map_predicates() {
+        let predicates = |line: &str| {
+            let map = Map::parse(line).unwrap();
+            (
+                map.is_file_backed(),
+                map.is_anonymous(),
+                map.is_executable(),
+            )
+        };
+
+        assert_eq!(
+            predicates("00400000-00401000 r-xp 00000000 08:01 1 /bin/cat"),
+            (true, false, true)
+        );
+        assert_eq!(
+            predicates("00601000-00602000 rw-p 00001000 08:01 1 /bin/cat"),
+            (true, false, false)
+        );
+        assert_eq!(
+            predicates("7f0000000000-7f0000001000 rw-p 00000000 00:00 0 "),
+            (false, true, false)
+        );
+        assert_eq!(
+            predicates("7f0000001000-7f0000002000 rwxp 00000000 00:00 0 "),
+            (false, true, true)
+        );
+        assert_eq!(
+            predicates("7f0000002000-7f0000003000 rw-s 00000000 00:01 17 /memfd:x (deleted)"),
+            (true, false, false)
+        );
+        assert_eq!(
+            predicates("01000000-01021000 rw-p 00000000 00:00 0 [heap]"),
+            (false, false, false)
+        );
+        assert_eq!(
+            predicates("7ffd8e3f9000-7ffd8e3fb000 r-xp 00000000 00:00 0 [vdso]"),
+            (false, false, true)
+        );
+    }
+
+    #[test]
+    fn test_executable_heap() {
+        let heap = |perms| {
+            from_str(&format!(
+                "00400000-00401000 r-xp 00000000 08:01 1 /bin/cat\n\
+                 01000000-01021000 {} 00000000 00:00 0 [heap]\n",
+                perms
+            ))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+        };
+
+        assert_eq!(executable_heap(heap("rw-p")), None);
+        assert_eq!(
+            executable_heap(heap("rwxp")).unwrap().address_range.begin,
+            0x1000000
+        );
+        assert!(executable_heap(heap("r-xp")).is_some());
+
+        let golden = from_path("test_data/golden.maps").unwrap();
+        assert_eq!(executable_heap(golden.map(Result::unwrap)), None);
+        assert_eq!(executable_heap(vec![]), None);
+    }
+
+    #[test]
+    fn test_find_duplicate_ranges() {
+        let golden = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(find_duplicate_ranges(&golden).is_empty());
+
+        let mut maps = golden.clone();
+        maps.push(golden[3].clone());
+        maps.push(golden[3].clone());
+        maps.insert(0, golden[10].clone());
+        assert_eq!(
+            find_duplicate_ranges(&maps),
+            vec![
+                golden[3].address_range.clone(),
+                golden[10].address_range.clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_pid_namespace() {
+        // Our own root is (usually) in our own pid namespace, so we can find ourselves in it.
+        let pid = std::process::id() as pid_t;
+        if let Ok(maps) = from_pid_namespace(pid, pid) {
+            let maps = maps.collect::<Result<Vec<_>, _>>().unwrap();
+            assert!(!maps.is_empty());
+        }
+
+        match from_pid_namespace(pid, pid_t::MAX) {
+            Err(Error::NamespaceInaccessible { path, source }) => {
+                assert!(path.ends_with(format!("proc/{}/maps", pid_t::MAX)));
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            _ => panic!("expected a NamespaceInaccessible error"),
+        }
+    }
+
+    #[test]
+    fn test_deduplicate_by_address() {
+        let input = "00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n\
+                     00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n\
+                     00401000-00402000 rw-p 00001000 08:01 1234 /bin/true\n\
+                     bogus\n\
+                     00401000-00402000 rw-p 00000000 00:00 0 \n\
+                     00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n";
+
+        let maps = Maps::new(input.as_bytes())
+            .deduplicate_by_address()
+            .collect::<Vec<_>>();
+        assert_eq!(maps.len(), 4);
+        assert_eq!(maps[0].as_ref().unwrap().address_range.begin, 0x400000);
+        assert_eq!(maps[1].as_ref().unwrap().address_range.begin, 0x401000);
+        assert!(maps[2].is_err());
+        // Only consecutive duplicates are dropped.
+        assert_eq!(maps[3].as_ref().unwrap().address_range.begin, 0x400000);
+    }
+
+    #[test]
+    fn test_validate() {
+        for name in ["golden.maps", "threads.maps", "arm.maps"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_data")
+                .join(name);
+            assert_eq!(from_path(path).unwrap().validate().unwrap(), vec![]);
+        }
+
+        let errors = from_str(
+            "1000-3000 rw-p 00000000 00:00 0 \n\
+             2000-4000 rw-p 00000000 00:00 0 \n\
+             5000-5000 rw-p 00000000 00:00 0 \n\
+             7000-6000 rw-p 00000000 08:01 0 \n\
+             1800-1900 r--p 00000000 00:00 0 \n",
+        )
+        .validate()
+        .unwrap();
+
+        let range = |begin, end| AddressRange { begin, end };
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.index, e.violation.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    1,
+                    Violation::Overlap {
+                        other: range(0x1000, 0x3000)
+                    }
+                ),
+                (2, Violation::EmptyRange),
+                (3, Violation::InvertedRange),
+                (3, Violation::DeviceWithoutInode),
+                (
+                    4,
+                    Violation::Unsorted {
+                        previous: range(0x7000, 0x6000)
+                    }
+                ),
+                (
+                    4,
+                    Violation::Overlap {
+                        other: range(0x1000, 0x3000)
+                    }
+                ),
+            ]
+        );
+        assert_eq!(errors[1].map.address_range, range(0x5000, 0x5000));
+        assert_eq!(
+            errors[0].to_string(),
+            "map 1 (00002000-00004000): overlaps the map at 00001000-00003000"
+        );
+
+        assert!(matches!(
+            from_str("1000-2000 rw-p\n").validate(),
+            Err(Error::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_missing_trailing_space() {
+        let map = Map::parse("7f3c8e61b000-7f3c8e628000 rw-p 00000000 00:00 0").unwrap();
+        assert_eq!(map.pathname, Pathname::Mmap);
+        assert_eq!(map.inode, 0);
+
+        let raw = RawMap::parse("7f3c8e61b000-7f3c8e628000 rw-p 00000000 00:00 0").unwrap();
+        assert_eq!(raw.inode, "0");
+        assert_eq!(raw.pathname, "");
+
+        // The inode still has to end the line or be followed by whitespace.
+        assert!(Map::parse("7f3c8e61b000-7f3c8e628000 rw-p 00000000 00:00 0x").is_err());
+    }
+
+    #[test]
+    fn test_parse_android_extra() {
+        let android = ParseOptions::new().android_annotations(true);
+
+        let map = Map::parse_with(
+            "7a3c1b1000-7a3c2f5000 r-xp 000bb000 fd:00 1612     /system/lib64/libc.so@1234",
+            &android,
+        )
+        .unwrap();
+        assert_eq!(map.pathname, Pathname::Path("/system/lib64/libc.so".into()));
+        assert_eq!(map.extra(), Some("1234"));
+
+        // By default, a trailing annotation is part of the path.
+        let map = Map::parse("00400000-00401000 rw-p 00000000 08:01 5678 /tmp/x@2").unwrap();
+        assert_eq!(map.pathname, Pathname::Path("/tmp/x@2".into()));
+        assert_eq!(map.extra(), None);
+        assert_eq!(
+            RawMap::parse(map.to_maps_line().as_str()).unwrap().pathname,
+            "/tmp/x@2"
+        );
+
+        let maps = Maps::with_options(
+            &b"00400000-00401000 rw-p 00000000 08:01 5678 /tmp/x@2\n"[..],
+            android.clone(),
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(maps[0].pathname, Pathname::Path("/tmp/x".into()));
+        assert_eq!(maps[0].extra(), Some("2"));
+
+        // `@` in the middle of a path isn't an annotation.
+        let map = Map::parse_with(
+            "6f5e4000-6f7d8000 rw-p 00000000 fd:05 3670     /data/dalvik-cache/arm64/system@framework@boot.art",
+            &android,
+        )
+        .unwrap();
+        assert_eq!(
+            map.pathname,
+            Pathname::Path("/data/dalvik-cache/arm64/system@framework@boot.art".into())
+        );
+        assert_eq!(map.extra(), None);
+
+        let map = Map::parse_with(
+            "12c00000-2ac00000 rw-p 00000000 00:00 0     [anon:dalvik-main space (region space)]",
+            &android,
+        )
+        .unwrap();
+        assert_eq!(
+            map.pathname,
+            Pathname::OtherPseudo("[anon:dalvik-main space (region space)]".into())
+        );
+        assert_eq!(map.extra(), None);
+    }
+
+    #[test]
+    fn test_record_source_offsets() {
+        let input = "7f1c2e400000-7f1c2e5c8000 r-xp 00022000 fd:01 6572575    /bin/bash\n\
+                     garbage\n\
+                     7f1c2e600000-7f1c2e700000 rw-p 00000000 00:00 0 \n\
+                     7f1c2e700000-7f1c2e800000 rw-p 00000000 00:00 0 [heap]";
+
+        let mut maps = Maps::with_options(
+            io::Cursor::new(input),
+            ParseOptions::lenient().record_source_offsets(true),
+        );
+        let offsets = maps
+            .by_ref()
+            .map(|map| map.unwrap().source_offset().unwrap())
+            .collect::<Vec<_>>();
+
+        // Each offset is the previous one plus the length of every line in
+        // between, newlines included; skipped lines still take up space.
+        let lines = input.split_inclusive('\n').collect::<Vec<_>>();
+        assert_eq!(
+            offsets,
+            vec![
+                0,
+                (lines[0].len() + lines[1].len()) as u64,
+                (lines[0].len() + lines[1].len() + lines[2].len()) as u64,
+            ]
+        );
+        for offset in offsets.iter() {
+            assert!(input[*offset as usize..].starts_with("7f1c2e"));
+        }
+
+        maps.reset().unwrap();
+        assert_eq!(maps.next().unwrap().unwrap().source_offset(), Some(0));
+
+        // Offsets aren't recorded by default.
+        assert!(from_str(input).all(|map| map.map_or(true, |m| m.source_offset().is_none())));
+    }
+
+    #[test]
+    fn test_keep_raw_lines() {
+        let input = "7f1c2e400000-7f1c2e5c8000 r-xp 00022000 fd:01 6572575    /bin/bash\n\
+                     7f1c2e600000-7f1c2e700000 rw-p 00000000 00:00 0 \n\
+                     7f1c2e700000-7f1c2e800000 rw-p 00000000 08:01 1234                       /tmp/two  spaces \n";
+
+        let maps = Maps::with_options(input.as_bytes(), ParseOptions::new().keep_raw_lines(true))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(maps.len(), 3);
+        for (map, line) in maps.iter().zip(input.lines()) {
+            assert_eq!(map.raw_line(), Some(line));
+        }
+        assert_eq!(maps[2].pathname, Pathname::Path("/tmp/two  spaces ".into()));
+
+        // Raw lines aren't kept by default.
+        assert!(from_str(input).all(|map| map.unwrap().raw_line().is_none()));
+    }
+
+    #[test]
+    fn test_raw_map_parse() {
+        let golden = fs::read_to_string("test_data/golden.maps").unwrap();
+        let raw = RawMap::parse(golden.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(
+            raw,
+            RawMap {
+                address_begin: "5610d526f000",
+                address_end: "5610d5274000",
+                permissions: "r-xp",
+                offset: "00002000",
+                dev_major: "08",
+                dev_minor: "11",
+                inode: "6553896",
+                pathname: "/bin/cat",
+                extra: None,
+            }
+        );
+
+        let android = fs::read_to_string("test_data/android.maps").unwrap();
+        let line = android.lines().nth(6).unwrap();
+        let raw = RawMap::parse_with(line, &ParseOptions::new().android_annotations(true)).unwrap();
+        assert_eq!(raw.address_begin, "7a3c0f5000");
+        assert_eq!(raw.dev_major, "fd");
+        assert_eq!(raw.pathname, "/system/lib64/libc.so");
+        assert_eq!(raw.extra, Some("1234"));
+
+        let raw = RawMap::parse(line).unwrap();
+        assert_eq!(raw.pathname, "/system/lib64/libc.so@1234");
+        assert_eq!(raw.extra, None);
+
+        // Fields can be separated by runs of spaces, and the pathname keeps
+        // any spaces after its first character.
+        let raw = RawMap::parse("0000AB-0000ff   rw-s  0000000a    08:1F   42  /tmp/two  spaces ")
+            .unwrap();
+        assert_eq!((raw.address_begin, raw.address_end), ("0000AB", "0000ff"));
+        assert_eq!(raw.permissions, "rw-s");
+        assert_eq!(raw.offset, "0000000a");
+        assert_eq!((raw.dev_major, raw.dev_minor), ("08", "1F"));
+        assert_eq!(raw.inode, "42");
+        assert_eq!(raw.pathname, "/tmp/two  spaces ");
+
+        let raw = RawMap::parse("7f1c2e600000-7f1c2e700000 rw-p 00000000 00:00 0 ").unwrap();
+        assert_eq!(raw.pathname, "");
+
+        // Only splitting is done, so out-of-range values are accepted.
+        let raw = RawMap::parse("fffffffffffffffff-0 rw-p 0 00:00 0 ").unwrap();
+        assert_eq!(raw.address_begin, "fffffffffffffffff");
+        assert!(Map::parse("fffffffffffffffff-0 rw-p 0 00:00 0 ").is_err());
+
+        assert!(RawMap::parse("7f1c2e600000-7f1c2e700000 rw-x 00000000 00:00 0").is_err());
+        assert!(RawMap::parse("7f1c2e600000-7f1c2e700000\trw-p 00000000 00:00 0").is_err());
+    }
+
+    #[test]
+    fn test_custom_pseudo_path() {
+        let input = "7ffe9e9f0000-7ffe9e9f2000 r--p 00000000 00:00 0          [mything]\n\
+                     7ffe9e9f2000-7ffe9e9f4000 r-xp 00000000 00:00 0          [otherthing]\n";
+
+        let options = ParseOptions::new().pseudo_path("[mything]", Pathname::Vvar);
+        let maps = Maps::with_options(input.as_bytes(), options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(maps[0].pathname, Pathname::Vvar);
+        assert_eq!(
+            maps[1].pathname,
+            Pathname::OtherPseudo("[otherthing]".into())
+        );
+
+        // Without the registration, it's just another pseudo-path.
+        let maps = Maps::new(input.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(maps[0].pathname, Pathname::OtherPseudo("[mything]".into()));
+    }
+
+    #[test]
+    fn test_classify_pseudo_path() {
+        let input = "7ffe9e9f0000-7ffe9e9f2000 r--p 00000000 00:00 0          [foo]\n\
+                     7ffe9e9f2000-7ffe9e9f4000 r-xp 00000000 00:00 0          [bar]\n\
+                     7ffe9e9f4000-7ffe9e9f6000 rw-p 00000000 00:00 0          [heap]\n";
+
+        let options = ParseOptions::new().classify_pseudo_path(|pathname| match pathname {
+            "[foo]" => Some(Pathname::OtherPseudo("foo".into())),
+            "[heap]" => Some(Pathname::Stack),
+            _ => None,
+        });
+        let maps = Maps::with_options(input.as_bytes(), options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(maps[0].pathname, Pathname::OtherPseudo("foo".into()));
+        // The callback returned None, so we fall back.
+        assert_eq!(maps[1].pathname, Pathname::OtherPseudo("[bar]".into()));
+        // Known pseudo-paths never reach the callback.
+        assert_eq!(maps[2].pathname, Pathname::Heap);
+    }
+
+    #[test]
+    fn test_pathname_label() {
+        assert_eq!(Pathname::Stack.label(), "[stack]");
+        assert_eq!(Pathname::Mmap.label(), "");
+        assert!(matches!(Pathname::Heap.label(), Cow::Borrowed("[heap]")));
+
+        let path = Pathname::Path("/usr/lib/libc.so.6".into());
+        assert!(matches!(path.label(), Cow::Borrowed("/usr/lib/libc.so.6")));
+    }
+
+    #[test]
+    fn test_reference_inputs() {
+        let test_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data");
+
+        for maps_input in glob(test_data.join("*.maps").to_str().unwrap()).unwrap() {
+            let maps_input = maps_input.unwrap();
+            let reference_output = maps_input.with_extension("json");
+
+            // The Android fixture's pathnames carry annotations.
+            let options = ParseOptions::new()
+                .android_annotations(maps_input.file_stem() == Some("android".as_ref()));
+            let maps =
+                Maps::with_options(BufReader::new(File::open(&maps_input).unwrap()), options)
+                    .collect::<Vec<_>>();
+            let expected_maps: Vec<Map> =
+                serde_json::from_str(&fs::read_to_string(reference_output).unwrap()).unwrap();
+
+            assert_eq!(maps.len(), expected_maps.len());
+            for (map, emap) in maps.iter().zip(expected_maps.iter()) {
+                assert_eq!(map.as_ref().unwrap(), emap);
+            }
+        }
+
+        // TODO(ww): Add some invalid reference inputs.
+    }
+
+    #[test]
+    fn test_device_from_dev_t() {
+        assert_eq!(
+            Device::from_dev_t(0x0811),
+            Device {
+                major: 8,
+                minor: 17
+            }
+        );
+        assert_eq!(
+            Device::from_dev_t(0x0000_f000_ffff_ffff),
+            Device {
+                major: 0xffff,
+                minor: 0xfffff,
+            }
+        );
+    }
+
+    #[test]
+    fn test_device_dev_t_round_trip() {
+        let boundaries = [
+            0,
+            1,
+            0xff,
+            0x100,
+            0xfff,
+            0x1000,
+            0xfffff,
+            0x100000,
+            0xffff_ffff,
+        ];
+
+        for &major in boundaries.iter() {
+            for &minor in boundaries.iter() {
+                let device = Device { major, minor };
+                assert_eq!(Device::from_dev_t(device.to_dev_t()), device);
+            }
+        }
+
+        for &dev in [0, 0x0811, 0xfff_ffff_ffff_ffff, u64::MAX].iter() {
+            assert_eq!(Device::from_dev_t(dev).to_dev_t(), dev);
+        }
+
+        // The legacy 8/8 encoding is a subset of the modern one.
+        assert_eq!(
+            Device {
+                major: 8,
+                minor: 17
+            }
+            .to_dev_t(),
+            0x0811
+        );
+    }
 
-    use glob::glob;
+    #[test]
+    fn test_device_matches_metadata() {
+        let exe = std::env::current_exe().unwrap();
+        let metadata = fs::metadata(&exe).unwrap();
 
-    use super::*;
+        // NOTE(ww): On stacked filesystems (e.g. overlayfs), the maps can report
+        // a different device than stat does, so we look for a map that agrees
+        // with the metadata on both rather than assuming the first one does.
+        let pid = std::process::id() as pid_t;
+        let maps = main_executable_maps(pid).unwrap();
+        let map = maps
+            .iter()
+            .find(|m| m.inode == metadata.ino() && m.device.matches_metadata(&metadata));
+        assert!(
+            map.is_some(),
+            "no map of {} has device {} and inode {}: {:#?}",
+            exe.display(),
+            Device::from_dev_t(metadata.dev()),
+            metadata.ino(),
+            maps
+        );
+        let map = map.unwrap();
+
+        let other = Device {
+            major: map.device.major + 1,
+            minor: map.device.minor,
+        };
+        assert!(!other.matches_metadata(&metadata));
+    }
 
     #[test]
-    fn test_parse_map() {
+    fn test_resolve_in_namespace() {
+        let proc_root =
+            std::env::temp_dir().join(format!("rsprocmaps-proc-{}", std::process::id()));
+        let lib = proc_root.join("4242/root/usr/lib/libfoo.so");
+        fs::create_dir_all(lib.parent().unwrap()).unwrap();
+        fs::write(&lib, b"libfoo").unwrap();
+
+        let resolved = resolve_in_proc_root(
+            &proc_root,
+            Pid::from(4242),
+            &Pathname::Path("/usr/lib/libfoo.so".into()),
+        )
+        .unwrap();
+        assert_eq!(resolved, lib);
+        assert_eq!(fs::read(&resolved).unwrap(), b"libfoo");
+
+        for pathname in [
+            Pathname::Mmap,
+            Pathname::Stack,
+            Pathname::OtherPseudo("[anon:foo]".into()),
+            Pathname::Path("anon_inode:[perf_event]".into()),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                resolve_in_proc_root(&proc_root, Pid::from(4242), pathname),
+                None
+            );
+        }
+
+        assert_eq!(
+            resolve_in_namespace(1, &Pathname::Path("/bin/sh".into())),
+            Some(PathBuf::from("/proc/1/root/bin/sh"))
+        );
+
+        fs::remove_dir_all(&proc_root).unwrap();
+    }
+
+    #[test]
+    fn test_checked_usize() {
+        let map = Map::parse("100000000-300000000 rw-p 00000000 00:00 0 ").unwrap();
+        assert_eq!(map.size_usize().unwrap(), 0x200000000);
+
+        // Pretend to be on a 32-bit target.
+        let max = u32::MAX as u64;
+        assert_eq!(
+            checked_usize("map size", 0xffff_ffff, max).unwrap(),
+            0xffff_ffff
+        );
+        assert_eq!(
+            checked_usize("map size", 0x200000000, max).unwrap_err(),
+            Error::TooLarge {
+                what: "map size",
+                value: 0x200000000
+            }
+        );
+        assert_eq!(
+            checked_usize("map size", 0x200000000, max)
+                .unwrap_err()
+                .to_string(),
+            "map size (8589934592) is too large for this platform"
+        );
+
+        assert_eq!(to_usize("offset", u64::MAX).is_ok(), usize::BITS == 64);
+    }
+
+    #[test]
+    fn test_device_resolve() {
+        let root = std::env::temp_dir().join(format!("rsprocmaps-devices-{}", std::process::id()));
+        let (sys_root, dev_root) = (root.join("sys"), root.join("dev"));
+        fs::create_dir_all(sys_root.join("dev/block/8:1")).unwrap();
+        fs::create_dir_all(sys_root.join("dev/block/8:2")).unwrap();
+        fs::create_dir_all(&dev_root).unwrap();
+        fs::write(
+            sys_root.join("dev/block/8:1/uevent"),
+            "MAJOR=8\nMINOR=1\nDEVNAME=sda1\nDEVTYPE=partition\n",
+        )
+        .unwrap();
+        fs::write(
+            sys_root.join("dev/block/8:2/uevent"),
+            "MAJOR=8\nMINOR=2\nDEVNAME=sda2\nDEVTYPE=partition\n",
+        )
+        .unwrap();
+        fs::write(dev_root.join("sda1"), "").unwrap();
+
+        let device = |major, minor| Device { major, minor };
+        assert_eq!(
+            device(8, 1).resolve_in(&sys_root, &dev_root),
+            Some(dev_root.join("sda1"))
+        );
+
+        // sysfs knows about sda2, but it has no node (and nothing turns up in a scan).
+        assert_eq!(device(8, 2).resolve_in(&sys_root, &dev_root), None);
+        assert_eq!(device(8, 3).resolve_in(&sys_root, &dev_root), None);
+        assert_eq!(device(0, 1).resolve_in(&sys_root, &dev_root), None);
+
+        fs::remove_dir_all(&root).unwrap();
+
+        // Any real block device should resolve to a node with the same number,
+        // even if only via the scan.
+        let block_device = fs::read_dir("/dev").unwrap().find_map(|entry| {
+            let metadata = fs::symlink_metadata(entry.unwrap().path()).unwrap();
+            Some(metadata.rdev()).filter(|_| metadata.file_type().is_block_device())
+        });
+        if let Some(rdev) = block_device {
+            let resolved = Device::from_dev_t(rdev).resolve().unwrap();
+            assert_eq!(fs::metadata(resolved).unwrap().rdev(), rdev);
+        }
+
+        let anonymous = Map::parse("1000-2000 rw-p 00000000 00:00 0 ").unwrap();
+        assert_eq!(anonymous.device_path(), None);
+    }
+
+    #[test]
+    fn test_open_backing_file() {
+        let path = std::env::temp_dir().join(format!("rsprocmaps-backing-{}", std::process::id()));
+        fs::write(&path, b"original").unwrap();
+
+        // NOTE(ww): We can't mmap without unsafe, so we build the map that
+        // mapping the file would have produced instead.
+        let metadata = fs::metadata(&path).unwrap();
+        let map = Map {
+            device: Device::from_dev_t(metadata.dev()),
+            inode: metadata.ino(),
+            pathname: Pathname::Path(path.to_str().unwrap().into()),
+            ..Default::default()
+        };
+
+        assert!(map.open_backing_file().is_ok());
+
+        // Replace the file out from under the map.
+        let replacement = path.with_extension("new");
+        fs::write(&replacement, b"replacement").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+
+        match map.open_backing_file() {
+            Err(Error::BackingFileChanged { expected, found }) => {
+                assert_eq!(expected, (map.device.clone(), map.inode));
+                assert_ne!(found.1, map.inode);
+            }
+            _ => panic!("expected a BackingFileChanged error"),
+        }
+
+        fs::remove_file(&path).unwrap();
+
+        let deleted = Map {
+            pathname: Pathname::Path(format!("{} (deleted)", path.to_str().unwrap())),
+            ..map.clone()
+        };
+        assert!(matches!(
+            deleted.open_backing_file(),
+            Err(Error::DeletedBackingFile(_))
+        ));
+
+        assert!(matches!(
+            Map::default().open_backing_file(),
+            Err(Error::NotFileBacked)
+        ));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_is_kernel_space() {
+        let user = AddressRange {
+            begin: 0x7fffe35c4000,
+            end: 0x7fffe35e5000,
+        };
+        assert!(!user.is_kernel_space());
+
+        let kernel = AddressRange {
+            begin: 0xffff_8000_0000_0000,
+            end: 0xffff_8000_0000_1000,
+        };
+        assert!(kernel.is_kernel_space());
+
+        // The vsyscall page lives in the upper half, even though it's user-accessible.
+        let vsyscall = AddressRange {
+            begin: 0xffffffffff600000,
+            end: 0xffffffffff601000,
+        };
+        assert!(vsyscall.is_kernel_space());
+    }
+
+    #[test]
+    fn test_permissions_satisfies() {
+        let rwxp = Map::parse("0-1 rwxp 0 0:0 0 ").unwrap().permissions;
+        let readonly = Map::parse("0-1 r--p 0 0:0 0 ").unwrap().permissions;
+
+        let rx = Permissions {
+            readable: true,
+            executable: true,
+            ..Default::default()
+        };
+        let rw = Permissions {
+            readable: true,
+            writable: true,
+            ..Default::default()
+        };
+        let shared = Permissions {
+            shared: true,
+            ..Default::default()
+        };
+
+        assert!(rwxp.satisfies(&rx));
+        assert!(rwxp.satisfies(&rw));
+        assert!(!readonly.satisfies(&rw));
+        assert!(!readonly.satisfies(&rx));
+        assert!(readonly.satisfies(&Default::default()));
+        assert!(!rwxp.satisfies(&shared));
+        assert!(rwxp.satisfies(&rwxp));
+    }
+
+    #[test]
+    fn test_main_executable_maps() {
+        let pid = std::process::id() as pid_t;
+        let exe = std::env::current_exe().unwrap();
+
+        let maps = main_executable_maps(pid).unwrap();
+        assert!(!maps.is_empty());
+        assert!(maps.iter().all(|m| m.is_main_executable(&exe)));
+        assert!(maps.iter().any(|m| m.permissions.executable));
+        assert!(maps
+            .iter()
+            .any(|m| m.pathname == Pathname::Path(exe.to_str().unwrap().into())));
+
+        let base = main_executable_base(pid).unwrap().unwrap();
+        assert_eq!(
+            base,
+            maps.iter().map(|m| m.address_range.begin).min().unwrap()
+        );
+
+        // Nothing else, like libc or the stack, is part of the main executable.
+        let others = from_pid(pid)
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|m| !maps.contains(m))
+            .collect::<Vec<_>>();
+        assert!(others.iter().all(|m| !m.is_main_executable(&exe)));
+    }
+
+    #[test]
+    fn test_flat_map_result() {
+        let input = "00400000-00402000 r-xp 00000000 08:01 1234 /bin/true\n\
+                     bogus\n\
+                     00402000-00403000 rw-p 00001000 08:01 1234 /bin/true\n";
+
+        let pages = Maps::new(input.as_bytes())
+            .flat_map_result(|map| (map.address_range.begin..map.address_range.end).step_by(0x1000))
+            .collect::<Vec<_>>();
+        assert_eq!(pages.len(), 4);
+        assert_eq!(*pages[0].as_ref().unwrap(), 0x400000);
+        assert_eq!(*pages[1].as_ref().unwrap(), 0x401000);
+        assert!(pages[2].is_err());
+        assert_eq!(*pages[3].as_ref().unwrap(), 0x402000);
+
+        // Maps that produce nothing are skipped entirely.
+        let none = Maps::new(input.as_bytes())
+            .flat_map_result(|_| Vec::<u64>::new())
+            .collect::<Vec<_>>();
+        assert_eq!(none.len(), 1);
+        assert!(none[0].is_err());
+    }
+
+    #[test]
+    fn test_fold_result() {
+        let total = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-4000 r-xp 00001000 08:11 1 /bin/cat\n",
+        )
+        .fold_result(0, |acc, map| acc + map.address_range.len())
+        .unwrap();
+        assert_eq!(total, 0x3000);
+
+        let mut seen = 0;
+        let result = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             garbage\n\
+             2000-4000 r-xp 00001000 08:11 1 /bin/cat\n",
+        )
+        .fold_result((), |_, _| seen += 1);
+        assert!(matches!(result, Err(Error::ParseError(_))));
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn test_permissions_bits() {
+        for mask in [
+            "---p", "r--p", "rw-p", "r-xp", "rwxp", "---s", "rw-s", "rwxs",
+        ]
+        .iter()
+        {
+            let permissions = Map::parse(&format!("0-1 {} 0 0:0 0 ", mask))
+                .unwrap()
+                .permissions;
+
+            assert_eq!(permissions.to_string(), *mask);
+            assert_eq!(
+                Permissions::from_bits(permissions.bits()),
+                Some(permissions)
+            );
+            assert!(permissions.matches_pattern(mask).unwrap());
+        }
+
+        assert_eq!(Permissions::RX_PRIVATE.bits(), 0b0101);
+        assert_eq!(Permissions::from_bits(0b1011).unwrap().to_string(), "rw-s");
+        assert_eq!(Permissions::from_bits(0x10), None);
+        assert_eq!(Permissions::from_bits(0xff), None);
+
+        assert_eq!(Permissions::NONE.to_string(), "---p");
+        assert_eq!(Permissions::R.to_string(), "r--p");
+        assert_eq!(Permissions::RW_PRIVATE.to_string(), "rw-p");
+        assert_eq!(Permissions::RX_PRIVATE.to_string(), "r-xp");
+    }
+
+    #[test]
+    fn test_address_range_from_str() {
+        assert_eq!(
+            "7f1c2e400000-7f1c2e5c8000".parse::<AddressRange>().unwrap(),
+            AddressRange {
+                begin: 0x7f1c2e400000,
+                end: 0x7f1c2e5c8000,
+            }
+        );
+        assert_eq!(
+            "0-1".parse::<AddressRange>().unwrap(),
+            AddressRange { begin: 0, end: 1 }
+        );
+
+        for bad in [
+            "",
+            "7f1c2e400000",
+            "7f1c2e400000-",
+            "-7f1c2e5c8000",
+            "7f1c2e400000 7f1c2e5c8000",
+            "7f1c2e400000-7f1c2e5c8000 ",
+            "0x1000-0x2000",
+            "g-1",
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(bad.parse::<AddressRange>(), Err(Error::ParseError(_))),
+                "{:?}",
+                bad
+            );
+        }
+
+        assert!(matches!(
+            "10000000000000000-0".parse::<AddressRange>(),
+            Err(Error::WidthError(_))
+        ));
+    }
+
+    #[test]
+    fn test_permissions_default() {
+        let null = Permissions::default();
+        assert_eq!(
+            null,
+            Permissions {
+                readable: false,
+                writable: false,
+                executable: false,
+                shared: false,
+                private: false,
+            }
+        );
+        assert_eq!(null.to_string(), "----");
+        assert_ne!(null, Permissions::NONE);
+        assert_eq!(Permissions::NONE.to_string(), "---p");
+        assert_eq!(Map::default().permissions, null);
+        assert_eq!("----".parse::<Permissions>().unwrap(), null);
+        assert_eq!(Permissions::from_bits(null.bits()), Some(Permissions::NONE));
+
+        // Default maps (and built maps that don't set permissions) round-trip
+        // through the maps format.
+        let map = Map::default();
+        assert_eq!(Map::parse(&map.to_maps_line()).unwrap(), map);
+
+        let map = Map::builder()
+            .address_range(AddressRange {
+                begin: 0x1000,
+                end: 0x2000,
+            })
+            .build();
+        assert_eq!(
+            map.to_maps_line(),
+            "00001000-00002000 ---- 00000000 00:00 0 "
+        );
+        assert_eq!(Map::parse(&map.to_maps_line()).unwrap(), map);
+    }
+
+    #[test]
+    fn test_permissions_from_str() {
+        for mask in ["---p", "r--p", "rw-p", "r-xp", "rwxs", "---s", "rwx-"].iter() {
+            let permissions = mask.parse::<Permissions>().unwrap();
+
+            assert_eq!(permissions.to_string(), *mask);
+            assert_eq!(
+                permissions,
+                Map::parse(&format!("0-1 {} 0 0:0 0 ", mask))
+                    .unwrap()
+                    .permissions
+            );
+        }
+
+        for bad in ["", "r-x", "r-xpp", "rwxq", "xwrp", "R-XP", " r-xp"].iter() {
+            assert!(
+                matches!(bad.parse::<Permissions>(), Err(Error::ParseError(_))),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_device_from_str() {
+        assert_eq!(
+            "08:11".parse::<Device>().unwrap(),
+            Device {
+                major: 8,
+                minor: 0x11
+            }
+        );
+        assert_eq!(
+            "fd:00".parse::<Device>().unwrap(),
+            Device {
+                major: 0xfd,
+                minor: 0
+            }
+        );
+
+        for bad in ["", "08", "08:", ":11", "08-11", "08:11:00", "zz:00"].iter() {
+            assert!(
+                matches!(bad.parse::<Device>(), Err(Error::ParseError(_))),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_pathname_classify() {
+        assert_eq!(Pathname::classify(""), Pathname::Mmap);
+        assert_eq!(Pathname::classify("[stack]"), Pathname::Stack);
+        assert_eq!(Pathname::classify("[vectors]"), Pathname::Vectors);
+        assert_eq!(
+            Pathname::classify("[anon:scudo]"),
+            Pathname::OtherPseudo("[anon:scudo]".into())
+        );
+        assert_eq!(
+            Pathname::classify("/usr/lib/libc.so.6"),
+            Pathname::Path("/usr/lib/libc.so.6".into())
+        );
+
+        // Only fully bracketed names look like pseudo-paths.
+        assert_eq!(
+            Pathname::classify("[stack"),
+            Pathname::Path("[stack".into())
+        );
+        assert_eq!(
+            Pathname::classify("/tmp/[stack]"),
+            Pathname::Path("/tmp/[stack]".into())
+        );
+    }
+
+    #[test]
+    fn test_permissions_combinators() {
+        let rw = Permissions::RW_PRIVATE;
+        let rx = Permissions::RX_PRIVATE;
+        let shared = Permissions::from_bits(Permissions::SHARED_BIT).unwrap();
+
+        assert_eq!(rw.union(&rx).to_string(), "rwxp");
+        assert_eq!(rw | rx, rw.union(&rx));
+        assert_eq!(rw.intersection(&rx), Permissions::R);
+        assert_eq!(rw & rx, Permissions::R);
+        assert_eq!(rw.difference(&rx).to_string(), "-w-p");
+        assert_eq!((rw | shared).to_string(), "rw-s");
+
+        assert_eq!((!Permissions::NONE).to_string(), "rwxs");
+        assert_eq!((!rw).to_string(), "--xs");
+        assert_eq!(!!rx, rx);
+
+        // "Required" and "forbidden" bits, as policy code would express them.
+        let candidate = Permissions::from_bits(0b0111).unwrap();
+        let forbidden =
+            Permissions::from_bits(Permissions::WRITE_BIT | Permissions::EXECUTE_BIT).unwrap();
+        assert!(candidate.satisfies(&Permissions::R));
+        assert_ne!((candidate & forbidden).bits(), 0);
+        assert_eq!((rx & forbidden).bits(), Permissions::EXECUTE_BIT);
+    }
+
+    #[test]
+    fn test_permissions_matches_pattern() {
+        let rxp = Map::parse("0-1 r-xp 0 0:0 0 ").unwrap().permissions;
+        let rws = Map::parse("0-1 rw-s 0 0:0 0 ").unwrap().permissions;
+
+        assert!(rxp.matches_pattern("r-x?").unwrap());
+        assert!(rxp.matches_pattern("r-xp").unwrap());
+        assert!(!rxp.matches_pattern("r-xs").unwrap());
+        assert!(!rxp.matches_pattern("rw??").unwrap());
+        assert!(rxp.matches_pattern("????").unwrap());
+
+        assert!(rws.matches_pattern("rw??").unwrap());
+        assert!(!rws.matches_pattern("r-x?").unwrap());
+        assert!(rws.matches_pattern("??-s").unwrap());
+
+        for invalid in &["", "r-x", "r-x?p", "x-r?", "r-x-", "rwx\u{e9}"] {
+            assert!(matches!(
+                rxp.matches_pattern(invalid),
+                Err(Error::InvalidPattern(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let mut maps = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             garbage\n\
+             2000-4000 r-xp 00001000 08:11 1 /bin/cat\n\
+             2000-4000 r-xq 00001000 08:11 1 /bin/cat\n",
+        );
+        assert_eq!(maps.stats(), Default::default());
+
+        maps.next();
+        assert_eq!(maps.stats().parsed, 1);
+
+        maps.by_ref().for_each(drop);
+        assert_eq!(
+            maps.stats(),
+            ParseStats {
+                parsed: 2,
+                failed: 2,
+                skipped: 0,
+            }
+        );
+
+        let (valid, stats) = from_path_with_stats(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"),
+        )
+        .unwrap();
+        assert_eq!(valid.len(), 25);
+        assert_eq!(stats.parsed, 25);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.skipped, 0);
+    }
+
+    #[test]
+    fn test_parse_stats_lenient() {
+        let input = "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+                     garbage\n\
+                     2000-4000 r-xp 00001000 08:11 1 /bin/cat\n\
+                     2000-4000 r-xq 00001000 08:11 1 /bin/cat\n\
+                     4000-5000 rw-p 00000000 00:00 0 \n";
+
+        let mut maps = Maps::with_options(input.as_bytes(), ParseOptions::lenient());
+        assert_eq!(maps.by_ref().count(), 3);
+        assert_eq!(
+            maps.stats(),
+            ParseStats {
+                parsed: 3,
+                failed: 0,
+                skipped: 2,
+            }
+        );
+
+        let path = std::env::temp_dir().join(format!("rsprocmaps-stats-{}", std::process::id()));
+        fs::write(&path, input).unwrap();
+        let result = from_path_with_stats(&path);
+        fs::remove_file(&path).unwrap();
+
+        let (valid, stats) = result.unwrap();
+        assert_eq!(valid.len(), 3);
+        assert_eq!(stats, maps.stats());
+
+        // A read error is returned, rather than retried forever.
+        assert!(matches!(
+            from_path_with_stats(env!("CARGO_MANIFEST_DIR")),
+            Err(Error::Io(e)) if e.raw_os_error() == Some(libc::EISDIR)
+        ));
+    }
+
+    #[test]
+    fn test_collect_by_executable_regions() {
+        let groups = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-3000 r-xp 00001000 08:11 1 /bin/cat\n\
+             3000-4000 r-xp 00002000 08:11 1 /bin/cat\n\
+             4000-5000 rwxp 00000000 00:00 0 \n\
+             5000-6000 r-xp 00000000 08:11 2 /lib/libc.so\n\
+             6000-7000 r-xp 00000000 08:11 3 /lib/ld.so\n",
+        )
+        .collect_by_executable_regions()
+        .unwrap();
+
+        assert_eq!(
+            groups
+                .iter()
+                .map(|(path, maps)| (path.to_str().unwrap(), maps.len()))
+                .collect::<Vec<_>>(),
+            vec![("/bin/cat", 2), ("/lib/libc.so", 1), ("/lib/ld.so", 1)]
+        );
+        assert_eq!(groups[0].1[0].address_range.begin, 0x2000);
+
+        let groups = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect_by_executable_regions()
+            .unwrap();
+        assert_eq!(groups.len(), 3);
+
+        assert!(from_str("garbage\n")
+            .collect_by_executable_regions()
+            .is_err());
+    }
+
+    #[test]
+    fn test_lenient() {
+        let input = "garbage\n\
+                     1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+                     2000-4000 r-xq 00001000 08:11 1 /bin/cat\n\
+                     4000-5000 rw-p 00000000 00:00 0 \n\
+                     \n";
+
+        let mut maps = Maps::with_options(input.as_bytes(), ParseOptions::lenient());
+        let valid = maps.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            valid
+                .iter()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x4000]
+        );
+        assert_eq!(
+            maps.stats(),
+            ParseStats {
+                parsed: 2,
+                failed: 0,
+                skipped: 3,
+            }
+        );
+        assert!(maps.skipped().is_empty());
+
+        let mut maps = Maps::with_options(
+            input.as_bytes(),
+            ParseOptions::lenient().record_skipped(true),
+        );
+        maps.by_ref().for_each(drop);
+        assert_eq!(
+            maps.skipped()
+                .iter()
+                .map(|(line, _)| *line)
+                .collect::<Vec<_>>(),
+            vec![1, 3, 5]
+        );
+
+        // Strict mode is still the default.
+        assert!(from_str(input).any(|m| m.is_err()));
+    }
+
+    #[test]
+    fn test_from_path_collecting() {
+        let path =
+            std::env::temp_dir().join(format!("rsprocmaps-collecting-{}", std::process::id()));
+        fs::write(
+            &path,
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             garbage\n\
+             2000-4000 r-xp 00001000 08:11 1 /bin/cat\n\
+             4000-5000 rw-p 00000000 00:00 0 \n\
+             5000-6000 rw-p 00000000 00:00 99999999999999999999 \n",
+        )
+        .unwrap();
+
+        let (valid, errors) = from_path_collecting(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            valid
+                .iter()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x2000, 0x4000]
+        );
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 2);
+        assert!(matches!(errors[0].1, Error::ParseError(_)));
+        assert_eq!(errors[1].0, 5);
+        assert!(matches!(errors[1].1, Error::WidthError(_)));
+
+        assert!(from_path_collecting("/nonexistent/maps").is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut maps =
+            from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps")).unwrap();
+
+        let first = maps
+            .by_ref()
+            .take(10)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        maps.reset().unwrap();
+        assert_eq!(maps.stats(), Default::default());
+
+        let all = maps.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(all.len(), 25);
+        assert_eq!(&all[..10], &first[..]);
+
+        let mut maps = Maps::new(std::io::Cursor::new(
+            b"1000-2000 r--p 00000000 08:11 1 /bin/cat\n".to_vec(),
+        ));
+        assert_eq!(maps.by_ref().count(), 1);
+        maps.reset().unwrap();
+        assert_eq!(maps.count(), 1);
+    }
+
+    #[test]
+    fn test_from_path_with_capacity() {
+        let maps_input = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps");
+
+        let expected = from_path(&maps_input)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        for capacity in &[1, 64, 64 * 1024] {
+            let maps = from_path_with_capacity(&maps_input, *capacity)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(maps, expected);
+        }
+    }
+
+    #[test]
+    fn test_from_path_with_retry() {
+        let maps_input = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps");
+        let delay = Duration::from_millis(20);
+
+        let maps = from_path_with_retry(&maps_input, 3, delay)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            maps,
+            from_path(&maps_input)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        );
+
+        // A missing file fails every attempt, sleeping between each.
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            from_path_with_retry(maps_input.with_extension("missing"), 2, delay),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        assert!(start.elapsed() >= delay * 2);
+    }
+
+    #[test]
+    fn test_inherited_from_parent() {
+        let fixture = |name: &str| {
+            from_path(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("test_data")
+                    .join(name),
+            )
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+        };
+        let parent = fixture("golden.maps");
+        let child = fixture("fork-child.maps");
+
+        // The child mapped one anonymous region after the fork.
+        let inherited = inherited_from_parent(&parent, &child);
+        assert_eq!(inherited, parent);
+        assert_eq!(inherited.len(), child.len() - 1);
+
+        // The same range with different permissions isn't inherited.
+        let mut mprotected = child.clone();
+        mprotected[0].permissions = "rw-p".parse().unwrap();
+        assert_eq!(inherited_from_parent(&parent, &mprotected), &parent[1..]);
+
+        assert_eq!(inherited_from_parent(&[], &child), vec![]);
+    }
+
+    #[test]
+    fn test_pid() {
+        assert_eq!(Pid::from(42).as_raw(), 42);
+        assert_eq!(Pid::from(42u32).as_raw(), 42);
+        assert_eq!(Pid::from(0).as_raw(), 0);
+        assert_eq!(Pid::new(42).unwrap(), Pid::from(42));
+        assert_eq!(
+            Pid::from(std::process::id()).to_string(),
+            std::process::id().to_string()
+        );
+
+        assert_eq!(Pid::new(-1).unwrap_err(), Error::InvalidPid(-1));
+
+        // Converting an invalid pid doesn't fail, but using it does.
+        assert_eq!(Pid::from(-1).as_raw(), -1);
+        assert!(matches!(from_pid(-1), Err(Error::InvalidPid(-1))));
+        assert!(matches!(from_pid(u32::MAX), Err(Error::InvalidPid(-1))));
+        assert!(matches!(
+            from_pid_namespace(1, -2),
+            Err(Error::InvalidPid(-2))
+        ));
+        assert!(matches!(
+            main_executable_maps(-1),
+            Err(Error::InvalidPid(-1))
+        ));
+        assert!(matches!(smaps::from_pid(-1), Err(Error::InvalidPid(-1))));
+        assert_eq!(memory::read(-1, 0, 1).unwrap_err(), Error::InvalidPid(-1));
+        assert_eq!(
+            resolve_in_namespace(-1, &Pathname::Path("/bin/cat".into())),
+            None
+        );
+
+        // Existing pid_t callers keep compiling.
+        let pid: pid_t = 4242;
+        assert_eq!(
+            resolve_in_namespace(pid, &Pathname::Path("/bin/cat".into())),
+            Some(PathBuf::from("/proc/4242/root/bin/cat"))
+        );
+    }
+
+    #[cfg(feature = "nix")]
+    #[test]
+    fn test_pid_from_nix() {
+        let pid = nix::unistd::Pid::this();
+        assert_eq!(Pid::from(pid).as_raw(), pid.as_raw());
+        assert_eq!(Pid::from(pid), Pid::from(std::process::id()));
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut input = b"00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n".to_vec();
+        input.extend_from_slice(
+            b"00401000-00402000 rw-p 00001000 08:01 5678 /tmp/caf\xe9 \xff@42\n",
+        );
+        input.extend_from_slice(b"00402000-00403000 rw-p 00000000 00:00 0 \n");
+
+        let maps = from_bytes(&input).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(maps.len(), 3);
+
+        assert_eq!(maps[0].pathname, Pathname::Path("/bin/true".into()));
+        assert_eq!(maps[0].pathname_bytes(), Some(&b"/bin/true"[..]));
+
+        assert_eq!(maps[1].inode, 5678);
+        assert_eq!(
+            maps[1].pathname,
+            Pathname::Path("/tmp/caf\u{fffd} \u{fffd}@42".into())
+        );
+        assert_eq!(maps[1].pathname_bytes(), Some(&b"/tmp/caf\xe9 \xff@42"[..]));
+        assert_eq!(maps[1].extra(), None);
+
+        // An Android annotation isn't part of the pathname's bytes.
+        let android = Maps::with_options(&input[..], ParseOptions::new().android_annotations(true))
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            android.pathname,
+            Pathname::Path("/tmp/caf\u{fffd} \u{fffd}".into())
+        );
+        assert_eq!(android.pathname_bytes(), Some(&b"/tmp/caf\xe9 \xff"[..]));
+        assert_eq!(android.extra(), Some("42"));
+
+        assert_eq!(maps[2].pathname, Pathname::Mmap);
+        assert_eq!(maps[2].pathname_bytes(), None);
+
+        // Invalid UTF-8 is still rejected in the fields before the pathname.
+        assert!(
+            from_bytes(b"00400000-00401000 r-xp 00000000 08:\xff1 1234 /bin/true\n")
+                .next()
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_map_try_from_bytes() {
         let map =
-            Map::parse("5608dd391000-5608dd3be000 r--p 00000000 08:11 6572575 /bin/bash").unwrap();
+            Map::try_from(&b"00400000-00401000 r-xp 00001000 08:01 1234 /bin/true\n"[..]).unwrap();
+        assert_eq!(
+            map.address_range,
+            AddressRange {
+                begin: 0x400000,
+                end: 0x401000
+            }
+        );
+        assert_eq!(map.offset, 0x1000);
+        assert_eq!(map.inode, 1234);
+        assert_eq!(map.pathname, Pathname::Path("/bin/true".into()));
 
-        assert_eq!(map.address_range.begin, 0x5608dd391000);
-        assert_eq!(map.address_range.end, 0x5608dd3be000);
+        let map =
+            Map::try_from(&b"00400000-00401000 r-xp 00000000 08:01 1234 /tmp/\xff"[..]).unwrap();
+        assert_eq!(map.pathname_bytes(), Some(&b"/tmp/\xff"[..]));
 
-        assert!(map.permissions.readable);
-        assert!(!map.permissions.writable);
-        assert!(!map.permissions.executable);
-        assert!(!map.permissions.shared);
-        assert!(map.permissions.private);
+        for bad in [
+            &b"00400000-0040100g r-xp 00000000 08:01 1234 /bin/true"[..],
+            &b"00400000-00401000 r-xp 00000000 08:01 12a4 /bin/true"[..],
+            &b"00400000-00401000 r-xp 00000000 08:01 99999999999999999999 /bin/true"[..],
+            &b"00400000-00401000 r-xp"[..],
+        ]
+        .iter()
+        {
+            assert!(Map::try_from(*bad).is_err());
+        }
+    }
 
-        assert_eq!(map.offset, 0);
+    #[test]
+    fn test_map_builder() {
+        let parsed =
+            Map::parse("00400000-00452000 r-xp 00001000 08:02 173521 /usr/bin/dbus-daemon")
+                .unwrap();
+        let built = Map::builder()
+            .address_range(AddressRange {
+                begin: 0x400000,
+                end: 0x452000,
+            })
+            .permissions(Permissions::RX_PRIVATE)
+            .offset(0x1000)
+            .device(Device { major: 8, minor: 2 })
+            .inode(173521)
+            .pathname(Pathname::Path("/usr/bin/dbus-daemon".into()))
+            .build();
+        assert_eq!(built, parsed);
 
-        assert_eq!(map.device.major, 8);
-        assert_eq!(map.device.minor, 17);
+        assert_eq!(Map::builder().build(), Map::default());
 
-        assert_eq!(map.inode, 6572575);
+        let mut snapshot = Maps::with_options(
+            "7f0b6d600000-7f0b6d700000 rw-s 00000000 00:01 5120 /memfd:wl (deleted)\n\
+             7a3c000000-7a3c001000 r--p 00000000 fd:00 42 /system/lib64/libc.so@1234\n"
+                .as_bytes(),
+            ParseOptions::new().android_annotations(true),
+        )
+        .collect::<Result<snapshot::MapsSnapshot, _>>()
+        .unwrap();
+        snapshot.canonicalize();
 
-        assert_eq!(map.pathname, Pathname::Path("/bin/bash".into()));
+        let memfd = Map::builder()
+            .address_range(AddressRange {
+                begin: 0x7f0b6d600000,
+                end: 0x7f0b6d700000,
+            })
+            .permissions("rw-s".parse().unwrap())
+            .device(Device { major: 0, minor: 1 })
+            .inode(5120)
+            .pathname(Pathname::Path("/memfd:wl".into()))
+            .deleted(true)
+            .build();
+        let libc = Map::builder()
+            .address_range(AddressRange {
+                begin: 0x7a3c000000,
+                end: 0x7a3c001000,
+            })
+            .permissions("r--p".parse().unwrap())
+            .device(Device {
+                major: 0xfd,
+                minor: 0,
+            })
+            .inode(42)
+            .pathname(Pathname::Path("/system/lib64/libc.so".into()))
+            .extra(Some("1234".into()))
+            .build();
+        assert_eq!(snapshot.maps(), &[libc, memfd]);
     }
 
     #[test]
-    fn test_reference_inputs() {
-        let test_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data");
+    fn test_to_maps_line() {
+        for (name, kernel) in [
+            ("golden.maps", KernelWidth::SixtyFourBit),
+            ("threads.maps", KernelWidth::SixtyFourBit),
+            ("x86-compat.maps", KernelWidth::SixtyFourBit),
+            ("arm.maps", KernelWidth::ThirtyTwoBit),
+        ] {
+            let input = fs::read_to_string(Path::new("test_data").join(name)).unwrap();
+            let output = from_str(&input)
+                .map(|map| map.unwrap().to_maps_line_for(kernel) + "\n")
+                .collect::<String>();
 
-        for maps_input in glob(test_data.join("*.maps").to_str().unwrap()).unwrap() {
-            let maps_input = maps_input.unwrap();
-            let reference_output = maps_input.with_extension("json");
+            assert_eq!(output, input, "{} doesn't round-trip", name);
+        }
 
-            let maps = from_path(&maps_input).unwrap().collect::<Vec<_>>();
-            let expected_maps: Vec<Map> =
-                serde_json::from_str(&fs::read_to_string(reference_output).unwrap()).unwrap();
+        // 32-bit addresses keep their width, everywhere.
+        let map = Map::parse("08048000-08049000 r-xp 00000000 fe:00 1234 /tmp/maps32").unwrap();
+        assert_eq!(map.address_range.to_string(), "08048000-08049000");
+        assert!(map
+            .to_maps_line()
+            .starts_with("08048000-08049000 r-xp 00000000 fe:00 1234 "));
+        assert!(map.to_maps_line().ends_with(" /tmp/maps32"));
 
-            assert_eq!(maps.len(), expected_maps.len());
-            for (map, emap) in maps.iter().zip(expected_maps.iter()) {
-                assert_eq!(map.as_ref().unwrap(), emap);
+        let mut map = Map::parse_with(
+            "7a3c0f5000-7a3c1b1000 r--p 00000000 fd:00 1612 /system/lib64/libc.so@1234",
+            &ParseOptions::new().android_annotations(true),
+        )
+        .unwrap();
+        map.deleted = true;
+        assert_eq!(
+            map.to_maps_line(),
+            "7a3c0f5000-7a3c1b1000 r--p 00000000 fd:00 1612                           \
+             /system/lib64/libc.so (deleted)@1234"
+        );
+    }
+
+    #[test]
+    fn test_empty_line() {
+        assert_eq!(Map::parse("").unwrap_err(), Error::EmptyLine);
+        assert_eq!(Map::parse(" \t ").unwrap_err(), Error::EmptyLine);
+        assert_eq!(RawMap::parse("  ").unwrap_err(), Error::EmptyLine);
+        assert_eq!(Map::try_from(&b"\n"[..]).unwrap_err(), Error::EmptyLine);
+
+        let mut maps = from_str(
+            "00400000-00401000 r-xp 00000000 08:01 1234 /bin/true\n\
+             \n\
+             \x20\x20\n",
+        );
+        assert!(maps.next().unwrap().is_ok());
+        assert!(matches!(maps.next(), Some(Err(Error::EmptyLine))));
+        assert!(matches!(maps.next(), Some(Err(Error::EmptyLine))));
+        assert!(maps.next().is_none());
+    }
+
+    #[test]
+    fn test_map_into_json_value() {
+        let map = Map::parse("00400000-00401000 r-xp 00001000 08:01 1234 /bin/true").unwrap();
+
+        let value = serde_json::Value::try_from(&map).unwrap();
+        assert_eq!(value["address_range"]["begin"], 0x400000);
+        assert_eq!(value["offset"], 0x1000);
+        assert_eq!(value["pathname"]["Path"], "/bin/true");
+        assert_eq!(value["permissions"]["executable"], true);
+
+        assert_eq!(serde_json::Value::from(map.clone()), value);
+        assert_eq!(serde_json::from_value::<Map>(value).unwrap(), map);
+    }
+
+    #[test]
+    fn test_address_range_new() {
+        assert_eq!(
+            AddressRange::new(0x1000, 0x2000).unwrap(),
+            AddressRange {
+                begin: 0x1000,
+                end: 0x2000
+            }
+        );
+        assert_eq!(
+            AddressRange::new(0x1000, 0x1000).unwrap(),
+            AddressRange {
+                begin: 0x1000,
+                end: 0x1000
             }
+        );
+        assert_eq!(
+            AddressRange::new(0x2000, 0x1000).unwrap_err(),
+            Error::InvalidRange {
+                begin: 0x2000,
+                end: 0x1000
+            }
+        );
+
+        let inverted = AddressRange::new_unchecked(0x2000, 0x1000);
+        assert_eq!((inverted.begin, inverted.end), (0x2000, 0x1000));
+        assert_eq!(inverted.len(), 0);
+        assert!(inverted.is_empty());
+        assert_eq!(AddressRange::new(0x1000, 0x3000).unwrap().len(), 0x2000);
+        assert!(AddressRange::new(0x1000, 0x1000).unwrap().is_empty());
+
+        // The parser accepts inverted ranges, so their sizes mustn't underflow.
+        let map = Map::parse("2000-1000 rw-p 00000000 00:00 0 ").unwrap();
+        assert_eq!(map.size_usize().unwrap(), 0);
+        assert_eq!(
+            category::category_totals(&[map])[&category::MemoryCategory::Anonymous],
+            0
+        );
+    }
+
+    #[test]
+    fn test_address_range_split_at() {
+        let range = AddressRange::new(0x1000, 0x4000).unwrap();
+
+        assert_eq!(
+            range.split_at(0x2000),
+            Some((
+                AddressRange::new(0x1000, 0x2000).unwrap(),
+                AddressRange::new(0x2000, 0x4000).unwrap()
+            ))
+        );
+        assert_eq!(
+            range.split_at(0x3fff),
+            Some((
+                AddressRange::new(0x1000, 0x3fff).unwrap(),
+                AddressRange::new(0x3fff, 0x4000).unwrap()
+            ))
+        );
+
+        for addr in [0, 0x1000, 0x4000, 0x5000] {
+            assert_eq!(range.split_at(addr), None);
         }
+        assert_eq!(
+            AddressRange::new(0x1000, 0x1000).unwrap().split_at(0x1000),
+            None
+        );
+    }
 
-        // TODO(ww): Add some invalid reference inputs.
+    #[test]
+    fn test_address_range_iter_pages() {
+        let range = AddressRange::new(0x1000, 0x4000).unwrap();
+        assert_eq!(
+            range.iter_pages(0x1000).collect::<Vec<_>>(),
+            vec![0x1000, 0x2000, 0x3000]
+        );
+        assert_eq!(range.iter_pages(0x2000).collect::<Vec<_>>(), vec![0x2000]);
+
+        // Unaligned ranges start at the next page boundary.
+        assert_eq!(
+            AddressRange::new(0x1800, 0x3800)
+                .unwrap()
+                .iter_pages(0x1000)
+                .collect::<Vec<_>>(),
+            vec![0x2000, 0x3000]
+        );
+
+        assert_eq!(range.iter_pages(0).count(), 0);
+        assert_eq!(
+            AddressRange::new(0x1000, 0x1000)
+                .unwrap()
+                .iter_pages(0x1000)
+                .count(),
+            0
+        );
+        assert_eq!(
+            AddressRange::new(u64::MAX - 0x1fff, u64::MAX)
+                .unwrap()
+                .iter_pages(0x1000)
+                .collect::<Vec<_>>(),
+            vec![u64::MAX - 0x1fff, u64::MAX - 0xfff]
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!(version(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(version().split('.').count(), 3);
     }
 }
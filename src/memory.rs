@@ -0,0 +1,72 @@
+//! Reading the contents of a process's memory via `/proc/<pid>/mem`.
+//!
+//! Reading another process's memory requires `PTRACE_MODE_ATTACH` access to it,
+//! i.e. the same UID (subject to the Yama `ptrace_scope` setting) or `CAP_SYS_PTRACE`.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::{Map, Pid};
+
+/// The most that [`read`] allocates up front; larger reads grow the buffer as
+/// data actually comes back.
+const MAX_INITIAL_CAPACITY: usize = 64 * 1024;
+
+/// Reads `len` bytes from the given pid's memory, starting at `addr`.
+///
+/// Fewer than `len` bytes are returned if the read runs off the end of
+/// mapped memory partway through.
+pub fn read(pid: impl Into<Pid>, addr: u64, len: usize) -> Result<Vec<u8>, Error> {
+    let mut mem = File::open(
+        Path::new("/proc")
+            .join(pid.into().validate()?.to_string())
+            .join("mem"),
+    )?;
+    mem.seek(SeekFrom::Start(addr))?;
+
+    // NOTE(ww): `len` can be the size of a huge (and mostly unbacked) map, so we
+    // don't trust it for the allocation.
+    let mut buf = Vec::with_capacity(len.min(MAX_INITIAL_CAPACITY));
+    mem.take(len as u64).read_to_end(&mut buf)?;
+
+    Ok(buf)
+}
+
+impl Map {
+    /// Reads up to `len` bytes from the start of this map in the given pid's memory.
+    ///
+    /// The read never extends past the end of this map.
+    pub fn read_bytes(&self, pid: impl Into<Pid>, len: usize) -> Result<Vec<u8>, Error> {
+        // NOTE(ww): A map too large for a usize is still larger than any read.
+        let size = self.size_usize().unwrap_or(usize::MAX);
+        read(pid, self.address_range.begin, len.min(size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_pid;
+
+    #[test]
+    fn test_read_self() {
+        let pid = Pid::from(std::process::id());
+        let needle = b"rsprocmaps memory test";
+
+        let addr = needle.as_ptr() as u64;
+        assert_eq!(read(pid, addr, needle.len()).unwrap(), needle);
+
+        let map = from_pid(pid)
+            .unwrap()
+            .filter_map(Result::ok)
+            .find(|m| m.address_range.begin <= addr && addr < m.address_range.end)
+            .unwrap();
+        let size = map.size_usize().unwrap();
+        assert_eq!(map.read_bytes(pid, size + 4096).unwrap().len(), size);
+
+        // Huge lengths are fine, since they're not allocated up front.
+        assert_eq!(map.read_bytes(pid, usize::MAX).unwrap().len(), size);
+    }
+}
@@ -0,0 +1,302 @@
+//! Grouping maps into loaded modules (executables and shared libraries).
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::elf::{self, MAX_NOTE_SIZE};
+use crate::error::Error;
+use crate::memory;
+use crate::{to_usize, AddressRange, Map, Pathname, Pid};
+
+/// Represents a loaded module, i.e. all of the maps backed by a single file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Module {
+    /// The path of the module's backing file.
+    pub path: PathBuf,
+    /// The module's maps (segments), sorted by address.
+    pub maps: Vec<Map>,
+}
+
+impl Module {
+    /// Returns the module's base address, i.e. the lowest address that it's mapped at.
+    pub fn base(&self) -> u64 {
+        self.maps
+            .iter()
+            .map(|map| map.address_range.begin)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Returns the map at the start of the module's file (offset 0), which
+    /// contains its headers.
+    pub fn base_map(&self) -> Option<&Map> {
+        self.maps.iter().find(|map| map.offset == 0)
+    }
+
+    /// Extracts the module's GNU build-id (`NT_GNU_BUILD_ID`) from the given pid's memory.
+    ///
+    /// This reads the module's ELF and program headers, then scans each of its
+    /// `PT_NOTE` segments. Every read is bounded, so a corrupt or malicious
+    /// header can't cause a huge allocation. Returns `None` if the module
+    /// isn't a recognizable ELF image or doesn't have a build-id.
+    pub fn build_id(&self, pid: impl Into<Pid>) -> Result<Option<Vec<u8>>, Error> {
+        let pid = pid.into();
+        let base_map = self.base_map().ok_or(Error::NotModuleBase)?;
+        let base = base_map.address_range.begin;
+
+        let (layout, phdrs) = match elf::read_program_headers(pid, base_map)? {
+            Some(headers) => headers,
+            None => return Ok(None),
+        };
+        let bias = match layout.load_bias(&phdrs, base) {
+            Some(bias) => bias,
+            None => return Ok(None),
+        };
+
+        for note in layout.notes(&phdrs) {
+            let addr = match bias.checked_add(note.vaddr) {
+                Some(addr) => addr,
+                None => continue,
+            };
+
+            let len = to_usize("note size", note.memsz.min(MAX_NOTE_SIZE))?;
+            let notes = memory::read(pid, addr, len)?;
+            if let Some(build_id) = layout.find_build_id(&notes, note.align) {
+                return Ok(Some(build_id));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Computes the module's load bias from its headers in the given pid's memory,
+    /// i.e. the difference between its runtime and link-time addresses.
+    ///
+    /// This is the `l_addr` (or `dlpi_addr`) that the dynamic loader reports for
+    /// the module. Returns `None` if the module isn't a recognizable ELF image.
+    pub fn load_bias(&self, pid: impl Into<Pid>) -> Result<Option<u64>, Error> {
+        let base_map = self.base_map().ok_or(Error::NotModuleBase)?;
+
+        Ok(elf::read_program_headers(pid.into(), base_map)?
+            .and_then(|(layout, phdrs)| layout.load_bias(&phdrs, base_map.address_range.begin)))
+    }
+}
+
+/// Groups the given maps into modules.
+///
+/// Every map with a file path belongs to the module for that path; anonymous
+/// and pseudo-path maps don't belong to any module. Modules are returned in
+/// order of their base addresses.
+pub fn modules(maps: &[Map]) -> Vec<Module> {
+    let mut modules: Vec<Module> = vec![];
+
+    for map in maps {
+        let path = match &map.pathname {
+            Pathname::Path(path) => path,
+            _ => continue,
+        };
+
+        match modules
+            .iter_mut()
+            .find(|m| m.path.as_os_str() == path.as_str())
+        {
+            Some(module) => module.maps.push(map.clone()),
+            None => modules.push(Module {
+                path: path.into(),
+                maps: vec![map.clone()],
+            }),
+        }
+    }
+
+    for module in modules.iter_mut() {
+        module.maps.sort_by_key(|map| map.address_range.begin);
+    }
+    modules.sort_by_key(|module| module.base());
+
+    modules
+}
+
+/// Returns the path and full runtime extent of the module containing `addr`, i.e.
+/// the range from the lowest begin to the highest end of every map backed by the
+/// same file as the map containing `addr`.
+///
+/// The extent may include gaps between the module's segments, as well as any
+/// maps in those gaps. Returns `None` if `addr` isn't in a file-backed map.
+pub fn module_extent(maps: &[Map], addr: u64) -> Option<(PathBuf, AddressRange)> {
+    let path = maps
+        .iter()
+        .find(|map| map.contains_address(addr))
+        .and_then(|map| match &map.pathname {
+            Pathname::Path(path) => Some(path),
+            _ => None,
+        })?;
+
+    let segments = maps
+        .iter()
+        .filter(|map| matches!(&map.pathname, Pathname::Path(p) if p == path));
+    let extent = segments.fold(None, |extent: Option<AddressRange>, map| {
+        Some(match extent {
+            Some(extent) => AddressRange {
+                begin: extent.begin.min(map.address_range.begin),
+                end: extent.end.max(map.address_range.end),
+            },
+            None => map.address_range.clone(),
+        })
+    })?;
+
+    Some((path.into(), extent))
+}
+
+/// Returns the maps backed by `path`, sorted by their offsets within the file.
+///
+/// This is the order of the module's segments in its ELF layout, which
+/// usually (but not necessarily) matches their order in memory. Maps at the
+/// same offset (e.g. a segment split by `mprotect` into RELRO and data) stay
+/// in address order.
+pub fn module_segments_by_offset(maps: &[Map], path: &Path) -> Vec<Map> {
+    let mut segments = maps
+        .iter()
+        .filter(|map| matches!(&map.pathname, Pathname::Path(p) if Path::new(p) == path))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    segments.sort_by_key(|map| (map.offset, map.address_range.begin));
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::{from_path, from_pid};
+
+    #[test]
+    fn test_modules() {
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let modules = modules(&maps);
+        assert_eq!(
+            modules.iter().map(|m| m.path.clone()).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("/bin/cat"),
+                PathBuf::from("/usr/lib/locale/locale-archive"),
+                PathBuf::from("/lib/x86_64-linux-gnu/libc-2.31.so"),
+                PathBuf::from("/lib/x86_64-linux-gnu/ld-2.31.so"),
+            ]
+        );
+
+        let libc = &modules[2];
+        assert_eq!(libc.maps.len(), 6);
+        assert_eq!(libc.base(), 0x7fee89308000);
+        assert_eq!(libc.base_map().unwrap().address_range.begin, 0x7fee89308000);
+    }
+
+    #[test]
+    fn test_module_extent() {
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // An address in libc's data segment gives the whole of libc.
+        let libc = (
+            PathBuf::from("/lib/x86_64-linux-gnu/libc-2.31.so"),
+            AddressRange {
+                begin: 0x7fee89308000,
+                end: 0x7fee894f6000,
+            },
+        );
+        assert_eq!(module_extent(&maps, 0x7fee894f4000), Some(libc.clone()));
+        assert_eq!(module_extent(&maps, 0x7fee89308000), Some(libc.clone()));
+        assert_eq!(module_extent(&maps, 0x7fee894f5fff), Some(libc));
+
+        // Anonymous, pseudo-path, and unmapped addresses aren't in a module.
+        let heap = maps.iter().find(|m| m.pathname == Pathname::Heap).unwrap();
+        assert_eq!(module_extent(&maps, heap.address_range.begin), None);
+        assert_eq!(module_extent(&maps, 0), None);
+    }
+
+    #[test]
+    fn test_module_segments_by_offset() {
+        let maps = from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let libc =
+            module_segments_by_offset(&maps, Path::new("/lib/x86_64-linux-gnu/libc-2.31.so"));
+        assert_eq!(
+            libc.iter().map(|m| m.offset).collect::<Vec<_>>(),
+            vec![0, 0x25000, 0x19d000, 0x1e7000, 0x1e7000, 0x1ea000]
+        );
+        // The ---p gap and RELRO share an offset, and stay in address order.
+        assert!(!libc[3].permissions.readable);
+        assert!(libc[4].permissions.readable);
+
+        // Segments mapped out of file order are put back in file order.
+        let maps = crate::from_str(
+            "7f0000003000-7f0000004000 rw-p 00002000 08:01 42 /lib/libodd.so\n\
+             7f0000004000-7f0000005000 r--p 00000000 08:01 42 /lib/libodd.so\n\
+             7f0000005000-7f0000006000 rw-p 00000000 00:00 0 \n\
+             7f0000006000-7f0000007000 r-xp 00001000 08:01 42 /lib/libodd.so\n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let odd = module_segments_by_offset(&maps, Path::new("/lib/libodd.so"));
+        assert_eq!(
+            odd.iter()
+                .map(|m| (m.offset, m.address_range.begin))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0x7f0000004000),
+                (0x1000, 0x7f0000006000),
+                (0x2000, 0x7f0000003000)
+            ]
+        );
+
+        assert!(module_segments_by_offset(&maps, Path::new("/lib/libc.so.6")).is_empty());
+    }
+
+    /// Extracts a 20-byte (SHA-1) GNU build-id from an ELF file on disk, by
+    /// searching for its note's raw header rather than parsing any ELF structures.
+    fn build_id_from_raw_note(path: &Path) -> Option<Vec<u8>> {
+        let contents = fs::read(path).unwrap();
+
+        // NOTE(ww): A note's header is its name size, descriptor size, and
+        // type (NT_GNU_BUILD_ID is 3), in the file's (here, native) byte order.
+        let mut header = vec![];
+        for field in [4u32, 20, 3] {
+            header.extend_from_slice(&field.to_ne_bytes());
+        }
+        header.extend_from_slice(b"GNU\0");
+
+        let start = contents
+            .windows(header.len())
+            .position(|window| window == header.as_slice())?
+            + header.len();
+        contents.get(start..start + 20).map(<[u8]>::to_vec)
+    }
+
+    #[test]
+    fn test_build_id_self() {
+        let pid = Pid::from(std::process::id());
+        let exe = std::env::current_exe().unwrap();
+
+        let maps = from_pid(pid)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let module = modules(&maps).into_iter().find(|m| m.path == exe).unwrap();
+
+        let build_id = module.build_id(pid).unwrap();
+        assert!(build_id.is_some(), "no build-id for {}", exe.display());
+        assert_eq!(build_id.as_ref().map(Vec::len), Some(20));
+        assert_eq!(build_id, build_id_from_raw_note(&exe));
+    }
+}
@@ -0,0 +1,277 @@
+//! Identifying the filesystems that file-backed maps live on.
+//!
+//! Where a mapped file lives matters for performance and security: a file on
+//! tmpfs is effectively RAM, one on overlayfs usually comes from a container
+//! image, and page faults on one from NFS go over the network. This module
+//! matches maps against the mounts in `/proc/<pid>/mountinfo` to find out.
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::mountinfo::{self, MountEntry};
+use crate::{Device, Map, Pathname, Pid, DELETED_SUFFIX};
+
+/// Filesystem types whose pages are fetched over the network.
+const NETWORK_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "9p",
+    "ceph",
+    "afs",
+    "glusterfs",
+    "fuse.sshfs",
+    "fuse.glusterfs",
+];
+
+/// Broad kinds of filesystem, as classified by their types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FsKind {
+    /// `tmpfs` (or the older `shm`), i.e. memory.
+    Tmpfs,
+    /// `overlay`, as used by container runtimes for image layers.
+    Overlay,
+    /// A network filesystem, e.g. NFS, CIFS, or 9p.
+    Network,
+    /// Any other filesystem, e.g. a local disk.
+    Other,
+}
+
+impl FsKind {
+    /// Classifies a filesystem type, as it appears in `mountinfo` (e.g. `nfs4`).
+    pub fn classify(fs_type: &str) -> FsKind {
+        match fs_type {
+            "tmpfs" | "shm" => FsKind::Tmpfs,
+            "overlay" => FsKind::Overlay,
+            fs_type if NETWORK_FS_TYPES.contains(&fs_type) => FsKind::Network,
+            _ => FsKind::Other,
+        }
+    }
+}
+
+/// A file-backed map, annotated with the mount that its file lives on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FsContext {
+    /// The map.
+    pub map: Map,
+    /// The mount's filesystem type, e.g. `ext4` or `tmpfs`.
+    pub fs_type: String,
+    /// The broad kind of the mount's filesystem.
+    pub kind: FsKind,
+    /// The mount's source, e.g. `/dev/sda1` or `server:/export`.
+    pub source: String,
+    /// Where the filesystem is mounted.
+    pub mount_point: PathBuf,
+}
+
+/// Annotates each of `maps` that's file-backed with the mount its file lives on,
+/// according to this process's `mountinfo`.
+///
+/// See [`fs_context_for_pid`].
+pub fn fs_context(maps: &[Map]) -> Result<Vec<FsContext>, Error> {
+    fs_context_for_pid(maps, std::process::id())
+}
+
+/// Annotates each of `maps` that's file-backed with the mount its file lives on,
+/// according to the given pid's `mountinfo`.
+///
+/// Maps are matched to mounts by device number, preferring the mount whose mount
+/// point is the longest prefix of the map's path when several share a device (e.g.
+/// bind mounts). Some filesystems (notably btrfs, whose subvolumes each get an
+/// anonymous device) report devices that don't appear in `mountinfo`; for those,
+/// the mount is the longest prefix of the path alone, provided the path still
+/// refers to the mapped file. Maps that can't be matched either way (e.g. memfds
+/// and deleted files) are omitted, as are anonymous and pseudo-path maps.
+pub fn fs_context_for_pid(maps: &[Map], pid: impl Into<Pid>) -> Result<Vec<FsContext>, Error> {
+    let mounts = mountinfo::from_pid(pid.into())?;
+
+    Ok(contexts(maps, &mounts, |map, path| {
+        fs::metadata(path).is_ok_and(|meta| {
+            Device::from_dev_t(meta.dev()) == map.device && meta.ino() == map.inode
+        })
+    }))
+}
+
+/// Matches each file-backed map to a mount, calling `is_mapped_file` to check
+/// whether a path still refers to a map's file before matching by path alone.
+fn contexts<F>(maps: &[Map], mounts: &[MountEntry], is_mapped_file: F) -> Vec<FsContext>
+where
+    F: Fn(&Map, &Path) -> bool,
+{
+    maps.iter()
+        .filter_map(|map| {
+            let path = match &map.pathname {
+                Pathname::Path(path) => Path::new(path),
+                _ => return None,
+            };
+
+            let on_device = mounts
+                .iter()
+                .filter(|mount| mount.device == map.device)
+                .collect::<Vec<_>>();
+            let mount = match longest_prefix(&on_device, path).or(on_device.last().copied()) {
+                Some(mount) => mount,
+                None if is_mapped_file(map, path) => {
+                    longest_prefix(&mounts.iter().collect::<Vec<_>>(), path)?
+                }
+                None => return None,
+            };
+
+            Some(FsContext {
+                map: map.clone(),
+                fs_type: mount.fs_type.clone(),
+                kind: FsKind::classify(&mount.fs_type),
+                source: mount.source.clone(),
+                mount_point: mount.mount_point.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the mount whose mount point is the longest prefix of `path`, preferring
+/// later mounts (which shadow earlier ones) on ties.
+fn longest_prefix<'a>(mounts: &[&'a MountEntry], path: &Path) -> Option<&'a MountEntry> {
+    // NOTE(ww): The path of a deleted file has a suffix, which can't be part of a
+    // mount point but can break a prefix match on the last component.
+    let path = path
+        .to_str()
+        .and_then(|path| path.strip_suffix(DELETED_SUFFIX))
+        .map_or(path, Path::new);
+
+    mounts
+        .iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::from_str;
+
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw,errors=remount-ro
+26 22 0:24 / /dev/shm rw,nosuid,nodev shared:4 - tmpfs tmpfs rw,inode64
+35 22 0:31 /@home /home rw,relatime shared:18 - btrfs /dev/sda1 rw,subvolid=257
+41 22 0:40 / /mnt/nfs rw,relatime - nfs4 server:/export rw,vers=4.2
+44 22 0:45 / /var/lib/docker/overlay2/abc/merged rw,relatime - overlay overlay rw
+45 22 259:2 /srv /opt/srv rw,relatime - ext4 /dev/nvme0n1p2 rw
+";
+
+    fn maps() -> Vec<Map> {
+        from_str(
+            "00400000-00401000 r-xp 00000000 103:02 11 /usr/bin/cat\n\
+             00600000-00601000 r--p 00000000 103:02 12 /opt/srv/data.bin\n\
+             7f0000000000-7f0000001000 rw-s 00000000 00:18 13 /dev/shm/ring\n\
+             7f0000001000-7f0000002000 r-xp 00000000 00:28 14 /mnt/nfs/lib/libremote.so\n\
+             7f0000002000-7f0000003000 r-xp 00000000 00:2d 15 /var/lib/docker/overlay2/abc/merged/lib/libc.so.6\n\
+             7f0000003000-7f0000004000 r--p 00000000 00:35 16 /home/user/notes.txt\n\
+             7f0000004000-7f0000005000 rw-s 00000000 00:01 17 /memfd:buffer (deleted)\n\
+             7f0000005000-7f0000006000 rw-p 00000000 00:00 0 \n\
+             7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0 [stack]\n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fs_kind_classify() {
+        assert_eq!(FsKind::classify("tmpfs"), FsKind::Tmpfs);
+        assert_eq!(FsKind::classify("overlay"), FsKind::Overlay);
+        assert_eq!(FsKind::classify("nfs4"), FsKind::Network);
+        assert_eq!(FsKind::classify("fuse.sshfs"), FsKind::Network);
+        assert_eq!(FsKind::classify("ext4"), FsKind::Other);
+        assert_eq!(FsKind::classify("fuse.portal"), FsKind::Other);
+    }
+
+    #[test]
+    fn test_contexts() {
+        let mounts = mountinfo::parse(MOUNTINFO).unwrap();
+        let maps = maps();
+
+        // Only the btrfs file still exists (as far as this test is concerned).
+        let contexts = contexts(&maps, &mounts, |map, _| map.inode == 16);
+        assert_eq!(
+            contexts
+                .iter()
+                .map(|c| (
+                    c.map.inode,
+                    c.fs_type.as_str(),
+                    c.kind,
+                    c.mount_point.to_str().unwrap()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (11, "ext4", FsKind::Other, "/"),
+                // A bind mount of the same device is preferred by its mount point.
+                (12, "ext4", FsKind::Other, "/opt/srv"),
+                (13, "tmpfs", FsKind::Tmpfs, "/dev/shm"),
+                (14, "nfs4", FsKind::Network, "/mnt/nfs"),
+                (
+                    15,
+                    "overlay",
+                    FsKind::Overlay,
+                    "/var/lib/docker/overlay2/abc/merged"
+                ),
+                // btrfs's anonymous device doesn't match, so the path does.
+                (16, "btrfs", FsKind::Other, "/home"),
+            ]
+        );
+        assert_eq!(contexts[3].source, "server:/export");
+    }
+
+    #[test]
+    fn test_fs_context_tmpfs() {
+        let mounts = mountinfo::from_pid(Pid::from(std::process::id())).unwrap();
+        if !mounts
+            .iter()
+            .any(|m| m.mount_point == Path::new("/dev/shm") && m.fs_type == "tmpfs")
+        {
+            return;
+        }
+
+        let path = Path::new("/dev/shm").join(format!("rsprocmaps-{}", std::process::id()));
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(&[0; 4096])
+            .unwrap();
+        let meta = fs::metadata(&path).unwrap();
+
+        // NOTE(ww): We can't mmap the file ourselves without unsafe, so we build
+        // the map that mmap would produce.
+        let map = Map::builder()
+            .device(Device::from_dev_t(meta.dev()))
+            .inode(meta.ino())
+            .pathname(Pathname::Path(path.to_str().unwrap().into()))
+            .build();
+        let contexts = fs_context(&[map]);
+        fs::remove_file(&path).unwrap();
+
+        let contexts = contexts.unwrap();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].kind, FsKind::Tmpfs);
+        assert_eq!(contexts[0].mount_point, Path::new("/dev/shm"));
+    }
+
+    #[test]
+    fn test_fs_context_self() {
+        let maps = crate::from_pid(std::process::id())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let exe = std::env::current_exe().unwrap();
+
+        let contexts = fs_context(&maps).unwrap();
+        assert!(contexts
+            .iter()
+            .any(|c| c.map.pathname == Pathname::Path(exe.to_str().unwrap().into())));
+    }
+}
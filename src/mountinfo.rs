@@ -0,0 +1,140 @@
+//! Parsing `/proc/<pid>/mountinfo`, as documented in `proc(5)`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::snapshot::decode_escapes;
+use crate::{Device, Pid};
+
+/// A single mount, i.e. a line of a `mountinfo` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MountEntry {
+    /// The device that files on this mount report in `st_dev`.
+    pub(crate) device: Device,
+    /// Where the mount is, relative to the process's root directory.
+    pub(crate) mount_point: PathBuf,
+    /// The filesystem type, e.g. `ext4` or `fuse.sshfs`.
+    pub(crate) fs_type: String,
+    /// The filesystem-specific mount source, e.g. `/dev/sda1` or `server:/export`.
+    pub(crate) source: String,
+}
+
+/// Reads and parses the given pid's `mountinfo`.
+pub(crate) fn from_pid(pid: Pid) -> Result<Vec<MountEntry>, Error> {
+    parse(&fs::read_to_string(
+        Path::new("/proc")
+            .join(pid.validate()?.to_string())
+            .join("mountinfo"),
+    )?)
+}
+
+/// Parses the contents of a `mountinfo` file, skipping blank lines.
+pub(crate) fn parse(input: &str) -> Result<Vec<MountEntry>, Error> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<MountEntry, Error> {
+    let malformed = || Error::MalformedMountInfo(line.into());
+
+    // 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+    // NOTE(ww): There are zero or more optional fields between the mount options
+    // and the "-" separator, so we split on the separator first.
+    let (head, tail) = line.split_once(" - ").ok_or_else(malformed)?;
+
+    let mut head = head.split(' ');
+    let mut field = || head.next().filter(|f| !f.is_empty()).ok_or_else(malformed);
+    let _mount_id = field()?.parse::<u64>().map_err(|_| malformed())?;
+    let _parent_id = field()?.parse::<u64>().map_err(|_| malformed())?;
+    let (major, minor) = field()?.split_once(':').ok_or_else(malformed)?;
+    let device = Device {
+        major: major.parse().map_err(|_| malformed())?,
+        minor: minor.parse().map_err(|_| malformed())?,
+    };
+    let _root = field()?;
+    let mount_point = decode_escapes(field()?).into();
+    let _options = field()?;
+
+    let mut tail = tail.split(' ');
+    let fs_type = tail
+        .next()
+        .filter(|f| !f.is_empty())
+        .ok_or_else(malformed)?
+        .into();
+    let source = decode_escapes(tail.next().ok_or_else(malformed)?);
+
+    Ok(MountEntry {
+        device,
+        mount_point,
+        fs_type,
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw,errors=remount-ro
+26 22 0:24 / /dev/shm rw,nosuid,nodev shared:4 - tmpfs tmpfs rw,inode64
+35 22 0:31 /@home /home rw,relatime shared:18 master:3 - btrfs /dev/sda1 rw,space_cache=v2,subvolid=257
+41 22 0:40 / /mnt/my\\040share rw,relatime - nfs4 server:/export/my\\040share rw,vers=4.2
+
+44 22 0:45 / /var/lib/docker/overlay2/abc/merged rw,relatime - overlay overlay rw,lowerdir=/l
+";
+
+    #[test]
+    fn test_parse() {
+        let mounts = parse(MOUNTINFO).unwrap();
+        assert_eq!(mounts.len(), 5);
+
+        assert_eq!(
+            mounts[0],
+            MountEntry {
+                device: Device {
+                    major: 259,
+                    minor: 2
+                },
+                mount_point: "/".into(),
+                fs_type: "ext4".into(),
+                source: "/dev/nvme0n1p2".into(),
+            }
+        );
+
+        // Any number of optional fields can precede the separator.
+        assert_eq!(mounts[1].fs_type, "tmpfs");
+        assert_eq!(mounts[2].fs_type, "btrfs");
+
+        // Paths and sources are unescaped.
+        assert_eq!(mounts[3].mount_point, PathBuf::from("/mnt/my share"));
+        assert_eq!(mounts[3].source, "server:/export/my share");
+
+        assert_eq!(mounts[4].fs_type, "overlay");
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        for bad in [
+            "22 1 259:2 / / rw,relatime shared:1 ext4 /dev/nvme0n1p2 rw",
+            "22 1 259-2 / / rw,relatime - ext4 /dev/nvme0n1p2 rw",
+            "22 x 259:2 / / rw,relatime - ext4 /dev/nvme0n1p2 rw",
+            "22 1 259:2 / - ext4 /dev/nvme0n1p2 rw",
+            "22 1 259:2 / / rw -",
+        ] {
+            assert!(matches!(parse(bad), Err(Error::MalformedMountInfo(_))));
+        }
+
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_pid_self() {
+        let mounts = from_pid(Pid::from(std::process::id())).unwrap();
+        assert!(mounts.iter().any(|m| m.mount_point == Path::new("/")));
+    }
+}
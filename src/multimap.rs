@@ -0,0 +1,231 @@
+//! Detecting files that are mapped more than once at the same offsets.
+//!
+//! ELF loaders map a file several times, privately and mostly at different offsets
+//! (one map per segment). Sharing the *same* part of a file between two maps is
+//! unusual: JIT engines do it to get a writable view and an executable view of
+//! their code cache without ever making a single map both writable and executable,
+//! and some exploit techniques do it for the same reason.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Device, Map, Pathname, Permissions};
+
+/// What a [`MultiMapping`]'s views permit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MultiMappingKind {
+    /// The same part of the file is mapped more than once, but no view is
+    /// writable while an overlapping one is executable.
+    Aliased,
+    /// One view is writable and an overlapping view is executable, so code can
+    /// be written through the one and run through the other.
+    DoubleMappedWX,
+}
+
+/// A file with overlapping views, as returned by [`multi_mappings`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MultiMapping {
+    /// The device that the file lives on.
+    pub device: Device,
+    /// The file's inode.
+    pub inode: u64,
+    /// The file's pathname, as it appears in its first view.
+    pub pathname: Pathname,
+    /// Every view that overlaps another view of the file, in address order.
+    pub views: Vec<Map>,
+    /// What the overlapping views permit.
+    pub kind: MultiMappingKind,
+}
+
+impl MultiMapping {
+    /// Returns the permissions of each view, in the same order as [`MultiMapping::views`].
+    pub fn permissions(&self) -> Vec<Permissions> {
+        self.views.iter().map(|view| view.permissions).collect()
+    }
+}
+
+/// Returns every file (including memfds and other shared memory) with a part that's
+/// mapped more than once, in order of each file's first overlapping view.
+///
+/// Maps are grouped into files by their (device, inode), and views overlap if
+/// their ranges of file offsets do. At least one of two overlapping views must be
+/// shared: ELF loaders routinely map the file page at each segment boundary
+/// privately more than once, which is harmless.
+///
+/// A private view isn't isolated from the file until it's written to, since
+/// only then does copy-on-write give it its own copy of a page. Until that
+/// happens, it sees every write made through a shared view of the same file, so
+/// a read-only (or executable) private view can still alias a shared one. Writable
+/// private views are ignored, since their writes never reach another view and
+/// can't be assumed to have left them aliased.
+pub fn multi_mappings(maps: &[Map]) -> Vec<MultiMapping> {
+    let mut files = BTreeMap::<(u64, u64, u64), Vec<&Map>>::new();
+    for map in maps {
+        if map.inode == 0
+            || !matches!(map.pathname, Pathname::Path(_))
+            || (map.permissions.private && map.permissions.writable)
+        {
+            continue;
+        }
+
+        files
+            .entry((map.device.major, map.device.minor, map.inode))
+            .or_default()
+            .push(map);
+    }
+
+    let mut multi = files
+        .into_values()
+        .filter_map(|mut views| {
+            views.sort_by_key(|view| view.address_range.begin);
+
+            let mut overlapping = vec![false; views.len()];
+            let mut wx = false;
+            for (i, a) in views.iter().enumerate() {
+                for (j, b) in views.iter().enumerate().skip(i + 1) {
+                    if !(a.permissions.shared || b.permissions.shared) || !file_overlap(a, b) {
+                        continue;
+                    }
+
+                    overlapping[i] = true;
+                    overlapping[j] = true;
+                    wx |= writes_into(a, b) || writes_into(b, a);
+                }
+            }
+
+            let views = views
+                .into_iter()
+                .zip(overlapping)
+                .filter(|(_, overlapping)| *overlapping)
+                .map(|(view, _)| view.clone())
+                .collect::<Vec<_>>();
+            let first = views.first()?;
+
+            Some(MultiMapping {
+                device: first.device.clone(),
+                inode: first.inode,
+                pathname: first.pathname.clone(),
+                kind: if wx {
+                    MultiMappingKind::DoubleMappedWX
+                } else {
+                    MultiMappingKind::Aliased
+                },
+                views,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    multi.sort_by_key(|m| m.views[0].address_range.begin);
+    multi
+}
+
+/// Returns whether two maps of the same file cover any of the same file offsets.
+fn file_overlap(a: &Map, b: &Map) -> bool {
+    let len = |map: &Map| map.address_range.len();
+
+    a.offset < b.offset.saturating_add(len(b)) && b.offset < a.offset.saturating_add(len(a))
+}
+
+/// Returns whether writes through `writer` show up as code in `executor`.
+///
+/// Only shared views are writers here, since writable private views are never
+/// grouped with the others.
+fn writes_into(writer: &Map, executor: &Map) -> bool {
+    writer.permissions.writable && executor.permissions.executable
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::from_path;
+
+    fn fixture(name: &str) -> Vec<Map> {
+        from_path(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_data")
+                .join(name),
+        )
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_multi_mappings_jit() {
+        let multi = multi_mappings(&fixture("jit.maps"));
+
+        // The code cache is double-mapped RW and RX; the stubs are mapped RW
+        // (shared) and RX (private, but never written, so still aliased); the
+        // profile is mapped twice read-only; the data memfd and ELF segments are
+        // at distinct offsets.
+        assert_eq!(
+            multi
+                .iter()
+                .map(|m| (m.pathname.to_string(), m.inode, m.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "/memfd:jit-cache (deleted)".into(),
+                    2071,
+                    MultiMappingKind::DoubleMappedWX
+                ),
+                (
+                    "/memfd:jit-stubs (deleted)".into(),
+                    2073,
+                    MultiMappingKind::DoubleMappedWX
+                ),
+                ("/dev/shm/jit-profile".into(), 9, MultiMappingKind::Aliased),
+            ]
+        );
+
+        let cache = &multi[0];
+        assert_eq!(cache.device, Device { major: 0, minor: 1 });
+        assert_eq!(
+            cache.permissions(),
+            vec![
+                "rw-s".parse::<Permissions>().unwrap(),
+                "r-xs".parse::<Permissions>().unwrap(),
+            ]
+        );
+        assert_eq!(
+            multi[1].permissions(),
+            vec![
+                "rw-s".parse::<Permissions>().unwrap(),
+                "r-xp".parse::<Permissions>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_mappings_elf() {
+        for name in ["golden.maps", "threads.maps", "arm.maps", "x86-compat.maps"] {
+            assert_eq!(multi_mappings(&fixture(name)), vec![], "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_multi_mappings_partial() {
+        let maps = crate::from_str(
+            "7f0000000000-7f0000002000 rw-s 00000000 08:01 42 /tmp/code\n\
+             7f0000002000-7f0000004000 r-xs 00001000 08:01 42 /tmp/code\n\
+             7f0000004000-7f0000005000 r--s 00003000 08:01 42 /tmp/code\n\
+             7f0000005000-7f0000006000 rw-p 00000000 08:01 43 /tmp/private\n\
+             7f0000006000-7f0000007000 r-xp 00000000 08:01 43 /tmp/private\n\
+             7f0000007000-7f0000008000 r--p 00000000 08:01 44 /tmp/elf\n\
+             7f0000008000-7f0000009000 r-xp 00000000 08:01 44 /tmp/elf\n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        // Partially overlapping views count, but the view at 0x3000 doesn't
+        // overlap anything, a writable private view's writes never reach another
+        // view, and private views only alias shared ones.
+        let multi = multi_mappings(&maps);
+        assert_eq!(multi.len(), 1);
+        assert_eq!(multi[0].kind, MultiMappingKind::DoubleMappedWX);
+        assert_eq!(multi[0].views, &maps[..2]);
+    }
+}
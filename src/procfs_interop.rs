@@ -0,0 +1,243 @@
+//! Conversions between `Map` and the `procfs` crate's `MemoryMap`.
+//!
+//! The two crates model a few things differently:
+//!
+//! * Deleted files: `procfs` leaves the kernel's `" (deleted)"` suffix in the path,
+//!   as does our parser. [`MapsSnapshot::canonicalize`](crate::snapshot::MapsSnapshot::canonicalize)
+//!   moves it into [`Map::deleted`] instead, so converting a map with `deleted` set
+//!   to `procfs` puts the suffix back. Converting from `procfs` never sets `deleted`.
+//! * Anonymous regions: `procfs`'s `Anonymous` is our `Mmap`.
+//! * SysV shared memory: `procfs` decodes `/SYSV<key> (deleted)` into `Vsys(key)`;
+//!   we leave it as a path, with the key in hex.
+//! * Other pseudo-paths: `procfs` strips the brackets from `Other` (and parses thread
+//!   stacks into `TStack`), while our `OtherPseudo` keeps them. `Vectors`, `Sigpage`,
+//!   and `[rollup]` only have dedicated variants in one crate or the other.
+//! * Non-UTF-8 paths: `procfs` uses `PathBuf`, so converting from `procfs` is lossy.
+//! * `smaps` data: `procfs` carries it in `MemoryMap::extension`, which is left empty.
+
+use std::convert::{TryFrom, TryInto};
+use std::path::PathBuf;
+
+use procfs::process::{MMPermissions, MMapExtension, MMapPath, MemoryMap};
+
+use crate::error::Error;
+use crate::{AddressRange, Device, Map, Pathname, Permissions, DELETED_SUFFIX};
+
+impl From<MMapPath> for Pathname {
+    fn from(path: MMapPath) -> Pathname {
+        match path {
+            MMapPath::Path(path) => Pathname::Path(path.to_string_lossy().into_owned()),
+            MMapPath::Heap => Pathname::Heap,
+            MMapPath::Stack => Pathname::Stack,
+            MMapPath::TStack(tid) => Pathname::OtherPseudo(format!("[stack:{}]", tid)),
+            MMapPath::Vdso => Pathname::Vdso,
+            MMapPath::Vvar => Pathname::Vvar,
+            MMapPath::Vsyscall => Pathname::Vsyscall,
+            MMapPath::Rollup => Pathname::OtherPseudo("[rollup]".into()),
+            MMapPath::Anonymous => Pathname::Mmap,
+            MMapPath::Vsys(key) => {
+                Pathname::Path(format!("/SYSV{:08x}{}", key as u32, DELETED_SUFFIX))
+            }
+            MMapPath::Other(other) => match other.as_str() {
+                "vectors" => Pathname::Vectors,
+                "sigpage" => Pathname::Sigpage,
+                _ => Pathname::OtherPseudo(format!("[{}]", other)),
+            },
+        }
+    }
+}
+
+impl From<&Pathname> for MMapPath {
+    fn from(pathname: &Pathname) -> MMapPath {
+        match pathname {
+            Pathname::Stack => MMapPath::Stack,
+            Pathname::Vdso => MMapPath::Vdso,
+            Pathname::Vvar => MMapPath::Vvar,
+            Pathname::Vsyscall => MMapPath::Vsyscall,
+            Pathname::Heap => MMapPath::Heap,
+            Pathname::Vectors => MMapPath::Other("vectors".into()),
+            Pathname::Sigpage => MMapPath::Other("sigpage".into()),
+            Pathname::Mmap => MMapPath::Anonymous,
+            Pathname::OtherPseudo(pseudo) => {
+                let inner = pseudo
+                    .strip_prefix('[')
+                    .and_then(|p| p.strip_suffix(']'))
+                    .unwrap_or(pseudo);
+                match inner.strip_prefix("stack:").map(str::parse) {
+                    Some(Ok(tid)) => MMapPath::TStack(tid),
+                    _ if inner == "rollup" => MMapPath::Rollup,
+                    _ => MMapPath::Other(inner.into()),
+                }
+            }
+            Pathname::Path(path) => {
+                // NOTE(ww): Mirror procfs's own parsing here, so that a round trip
+                // through it is lossless.
+                match path
+                    .strip_prefix("/SYSV")
+                    .and_then(|key| key.get(..8))
+                    .map(|key| u32::from_str_radix(key, 16))
+                {
+                    Some(Ok(key)) => MMapPath::Vsys(key as i32),
+                    _ => MMapPath::Path(PathBuf::from(path)),
+                }
+            }
+        }
+    }
+}
+
+impl From<MemoryMap> for Map {
+    fn from(map: MemoryMap) -> Map {
+        Map {
+            address_range: AddressRange {
+                begin: map.address.0,
+                end: map.address.1,
+            },
+            permissions: Permissions {
+                readable: map.perms.contains(MMPermissions::READ),
+                writable: map.perms.contains(MMPermissions::WRITE),
+                executable: map.perms.contains(MMPermissions::EXECUTE),
+                shared: map.perms.contains(MMPermissions::SHARED),
+                private: map.perms.contains(MMPermissions::PRIVATE),
+            },
+            offset: map.offset,
+            device: Device {
+                major: map.dev.0 as u64,
+                minor: map.dev.1 as u64,
+            },
+            inode: map.inode,
+            pathname: map.pathname.into(),
+            deleted: false,
+            extra: None,
+            raw_line: None,
+            source_offset: None,
+            pathname_bytes: None,
+        }
+    }
+}
+
+impl TryFrom<&Map> for MemoryMap {
+    type Error = Error;
+
+    /// Converts a map into a `procfs` `MemoryMap`, failing only if its device
+    /// numbers don't fit in `procfs`'s (signed) representation.
+    fn try_from(map: &Map) -> Result<MemoryMap, Error> {
+        let mut perms = MMPermissions::NONE;
+        perms.set(MMPermissions::READ, map.permissions.readable);
+        perms.set(MMPermissions::WRITE, map.permissions.writable);
+        perms.set(MMPermissions::EXECUTE, map.permissions.executable);
+        perms.set(MMPermissions::SHARED, map.permissions.shared);
+        perms.set(MMPermissions::PRIVATE, map.permissions.private);
+
+        let pathname = match &map.pathname {
+            Pathname::Path(path) if map.deleted() => {
+                MMapPath::Path(PathBuf::from(format!("{}{}", path, DELETED_SUFFIX)))
+            }
+            pathname => pathname.into(),
+        };
+
+        Ok(MemoryMap {
+            address: (map.address_range.begin, map.address_range.end),
+            perms,
+            offset: map.offset,
+            dev: (map.device.major.try_into()?, map.device.minor.try_into()?),
+            inode: map.inode,
+            pathname,
+            extension: MMapExtension::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn round_trip(line: &str) -> (Map, MemoryMap) {
+        let map = from_str(line).next().unwrap().unwrap();
+        let converted = MemoryMap::try_from(&map).unwrap();
+        assert_eq!(Map::from(converted.clone()), map);
+
+        (map, converted)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (_, converted) =
+            round_trip("7fee89308000-7fee8932d000 r-xp 00022000 08:11 6572575 /usr/lib/libc.so.6");
+        assert_eq!(converted.address, (0x7fee89308000, 0x7fee8932d000));
+        assert_eq!(
+            converted.perms,
+            MMPermissions::READ | MMPermissions::EXECUTE | MMPermissions::PRIVATE
+        );
+        assert_eq!(converted.offset, 0x22000);
+        assert_eq!(converted.dev, (8, 17));
+        assert_eq!(
+            converted.pathname,
+            MMapPath::Path("/usr/lib/libc.so.6".into())
+        );
+
+        let (_, converted) = round_trip("7fee89500000-7fee89504000 rw-p 00000000 00:00 0 ");
+        assert_eq!(converted.pathname, MMapPath::Anonymous);
+
+        let (_, converted) = round_trip("7ffd5a1c0000-7ffd5a1e1000 rw-p 00000000 00:00 0 [stack]");
+        assert_eq!(converted.pathname, MMapPath::Stack);
+
+        let (map, converted) = round_trip(
+            "7f0b6d400000-7f0b6d500000 rw-s 00000000 00:01 32769 /SYSV0000162e (deleted)",
+        );
+        assert!(map.permissions.shared);
+        assert_eq!(converted.pathname, MMapPath::Vsys(0x162e));
+
+        let (map, converted) = round_trip("ffff0000-ffff1000 r-xp 00000000 00:00 0 [vectors]");
+        assert_eq!(map.pathname, Pathname::Vectors);
+        assert_eq!(converted.pathname, MMapPath::Other("vectors".into()));
+
+        let (_, converted) =
+            round_trip("7f1e62bff000-7f1e633ff000 rw-p 00000000 00:00 0 [stack:1234]");
+        assert_eq!(converted.pathname, MMapPath::TStack(1234));
+
+        let (_, converted) =
+            round_trip("7f1e62bff000-7f1e633ff000 rw-p 00000000 00:00 0 [anon:scudo]");
+        assert_eq!(converted.pathname, MMapPath::Other("anon:scudo".into()));
+    }
+
+    #[test]
+    fn test_deleted() {
+        let mut map =
+            from_str("7f0b6d600000-7f0b6d700000 r-xp 00000000 08:01 4242 /tmp/foo (deleted)")
+                .next()
+                .unwrap()
+                .unwrap();
+
+        // procfs leaves the suffix in the path, like we do.
+        let converted = MemoryMap::try_from(&map).unwrap();
+        assert_eq!(
+            converted.pathname,
+            MMapPath::Path("/tmp/foo (deleted)".into())
+        );
+
+        // Canonicalized maps get their suffix back.
+        map.pathname = Pathname::Path("/tmp/foo".into());
+        map.deleted = true;
+        let converted = MemoryMap::try_from(&map).unwrap();
+        assert_eq!(
+            converted.pathname,
+            MMapPath::Path("/tmp/foo (deleted)".into())
+        );
+        assert!(!Map::from(converted).deleted());
+    }
+
+    #[test]
+    fn test_device_out_of_range() {
+        let mut map = from_str("0-1000 r--p 00000000 08:01 1 /foo")
+            .next()
+            .unwrap()
+            .unwrap();
+        map.device.minor = u64::MAX;
+
+        assert!(matches!(
+            MemoryMap::try_from(&map),
+            Err(Error::ConversionError(_))
+        ));
+    }
+}
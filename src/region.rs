@@ -0,0 +1,71 @@
+//! A platform-agnostic view of memory regions.
+//!
+//! Every source of maps in this crate (`/proc/<pid>/maps` on Linux, `procstat` on
+//! FreeBSD, `vmmap` on macOS) can report an address range, permissions, and a
+//! pathname for each region, even where the rest of a [`Map`] has to be
+//! synthesized. Code written against [`MemoryRegion`] works with any of them.
+
+use crate::{AddressRange, Map, Pathname, Permissions};
+
+/// The properties of a memory region that every platform can report.
+pub trait MemoryRegion {
+    /// Returns the region's address range.
+    fn address_range(&self) -> &AddressRange;
+
+    /// Returns the region's permissions.
+    fn permissions(&self) -> &Permissions;
+
+    /// Returns the region's pathname.
+    fn pathname(&self) -> &Pathname;
+}
+
+impl MemoryRegion for Map {
+    fn address_range(&self) -> &AddressRange {
+        &self.address_range
+    }
+
+    fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    fn pathname(&self) -> &Pathname {
+        &self.pathname
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_executable(regions: &[&dyn MemoryRegion]) -> u64 {
+        regions
+            .iter()
+            .filter(|region| region.permissions().executable)
+            .map(|region| region.address_range().len())
+            .sum()
+    }
+
+    #[test]
+    fn test_memory_region_map() {
+        let map = Map {
+            address_range: AddressRange {
+                begin: 0x1000,
+                end: 0x3000,
+            },
+            permissions: Permissions::RX_PRIVATE,
+            pathname: Pathname::Path("/bin/cat".into()),
+            ..Default::default()
+        };
+
+        let region: &dyn MemoryRegion = &map;
+        assert_eq!(region.address_range(), &map.address_range);
+        assert_eq!(region.permissions(), &Permissions::RX_PRIVATE);
+        assert_eq!(region.pathname(), &Pathname::Path("/bin/cat".into()));
+
+        let stack = Map {
+            pathname: Pathname::Stack,
+            ..Default::default()
+        };
+        assert_eq!(total_executable(&[&map, &stack]), 0x2000);
+    }
+}
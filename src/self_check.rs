@@ -0,0 +1,267 @@
+//! Cross-checking the current process's maps against its dynamic loader.
+//!
+//! The dynamic loader's list of loaded modules (what `dl_iterate_phdr(3)` walks)
+//! and the modules visible in maps should agree. Executable maps that the loader
+//! doesn't know about suggest manual mapping or injection, while loader entries
+//! without matching maps suggest that something was unmapped behind its back.
+//!
+//! `dl_iterate_phdr` itself can't be called without `unsafe`, so this walks the
+//! same list that it does instead: the loader's `link_map` chain, found via the
+//! `DT_DEBUG` entry of the main executable's dynamic section and read through
+//! `/proc/self/mem`.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::elf::{self, DT_DEBUG};
+use crate::error::Error;
+use crate::memory;
+use crate::module::{self, Module};
+use crate::{from_pid, to_usize, Map, Pathname, Pid};
+
+/// The most loader entries we're willing to walk, in case the list is corrupt.
+const MAX_LOADER_ENTRIES: usize = 4096;
+
+/// The largest dynamic section we're willing to read.
+const MAX_DYNAMIC_SIZE: u64 = 64 * 1024;
+
+/// The longest module name we're willing to read.
+const MAX_NAME_SIZE: usize = 4096;
+
+/// A module, as seen by the dynamic loader.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LoaderEntry {
+    /// The module's name, as the loader recorded it. This is empty for the main
+    /// executable, and a soname (e.g. `linux-vdso.so.1`) for the vDSO.
+    pub name: String,
+    /// The module's load bias (`l_addr`, or `dlpi_addr`).
+    pub load_bias: u64,
+}
+
+/// The result of comparing the current process's maps against its dynamic loader.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LoaderComparison {
+    /// Every module known to the loader.
+    pub loader_entries: Vec<LoaderEntry>,
+    /// Executable, file-backed maps that don't belong to any module known to the loader.
+    pub unmatched_maps: Vec<Map>,
+    /// Loader entries that don't correspond to a module in the maps, or whose
+    /// module's headers give a different load bias.
+    pub unmatched_entries: Vec<LoaderEntry>,
+}
+
+impl LoaderComparison {
+    /// Returns whether the maps and the loader agree completely.
+    pub fn is_consistent(&self) -> bool {
+        self.unmatched_maps.is_empty() && self.unmatched_entries.is_empty()
+    }
+}
+
+/// Compares the current process's maps against its dynamic loader's modules.
+///
+/// The main executable and the vDSO are matched up specially, since the loader
+/// doesn't know them by path. A statically linked process has no loader entries,
+/// and so every executable, file-backed map is unmatched.
+pub fn compare_with_loader() -> Result<LoaderComparison, Error> {
+    let pid = Pid::from(std::process::id());
+    let exe = Path::new("/proc").join(pid.to_string()).join("exe");
+
+    let maps = from_pid(pid)?.collect::<Result<Vec<_>, _>>()?;
+    let mut modules = module::modules(&maps);
+    if let Some(vdso) = maps.iter().find(|m| m.pathname == Pathname::Vdso) {
+        modules.push(Module {
+            path: "[vdso]".into(),
+            maps: vec![vdso.clone()],
+        });
+    }
+
+    let exe_module = match modules
+        .iter()
+        .find(|m| m.maps.iter().any(|map| map.is_main_executable(&exe)))
+    {
+        Some(module) => module,
+        None => return Ok(Default::default()),
+    };
+
+    let loader_entries = loader_entries(pid, exe_module)?;
+
+    let mut matched = vec![false; modules.len()];
+    let mut unmatched_entries = vec![];
+    for entry in loader_entries.iter() {
+        let mut found = false;
+        for (i, module) in modules.iter().enumerate() {
+            if matched[i] || !module_matches(pid, module, entry, &exe)? {
+                continue;
+            }
+
+            matched[i] = true;
+            found = true;
+            break;
+        }
+
+        if !found {
+            unmatched_entries.push(entry.clone());
+        }
+    }
+
+    let unmatched_maps = modules
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+        .flat_map(|(module, _)| module.maps.iter())
+        .filter(|map| map.permissions.executable)
+        .cloned()
+        .collect();
+
+    Ok(LoaderComparison {
+        loader_entries,
+        unmatched_maps,
+        unmatched_entries,
+    })
+}
+
+/// Returns whether `module` is the module that the loader describes with `entry`.
+fn module_matches(
+    pid: Pid,
+    module: &Module,
+    entry: &LoaderEntry,
+    exe: &Path,
+) -> Result<bool, Error> {
+    let base_map = match module.base_map() {
+        Some(base_map) => base_map,
+        None => return Ok(false),
+    };
+
+    let same_module = if entry.name.is_empty() {
+        base_map.is_main_executable(exe)
+    } else if base_map.pathname == Pathname::Vdso {
+        // NOTE(ww): The loader names the vDSO after its soname, which varies by
+        // architecture, so we can only identify it by its load bias below.
+        !entry.name.starts_with('/')
+    } else {
+        base_map.is_main_executable(Path::new(&entry.name))
+    };
+
+    Ok(same_module && module.load_bias(pid)? == Some(entry.load_bias))
+}
+
+/// Walks the loader's `link_map` chain, starting from the `r_debug` structure
+/// that the loader advertises in the main executable's `DT_DEBUG` entry.
+fn loader_entries(pid: Pid, exe_module: &Module) -> Result<Vec<LoaderEntry>, Error> {
+    let base_map = exe_module.base_map().ok_or(Error::NotModuleBase)?;
+    let (layout, phdrs) = match elf::read_program_headers(pid, base_map)? {
+        Some(headers) => headers,
+        None => return Ok(vec![]),
+    };
+
+    // A static executable has no dynamic section, and no loader.
+    let (dynamic, bias) = match (
+        layout.dynamic(&phdrs),
+        layout.load_bias(&phdrs, base_map.address_range.begin),
+    ) {
+        (Some(dynamic), Some(bias)) => (dynamic, bias),
+        _ => return Ok(vec![]),
+    };
+    let dynamic = match bias.checked_add(dynamic.vaddr) {
+        Some(addr) => {
+            let len = to_usize("dynamic section size", dynamic.memsz.min(MAX_DYNAMIC_SIZE))?;
+            memory::read(pid, addr, len)?
+        }
+        None => return Ok(vec![]),
+    };
+
+    let word = layout.word_size();
+    let read_word = |addr: u64| -> Result<Option<u64>, Error> {
+        Ok(layout.word(&memory::read(pid, addr, word)?, 0))
+    };
+
+    // NOTE(ww): r_debug begins with an int (r_version), so r_map is at the
+    // next word-aligned offset.
+    let r_debug = match layout.find_dynamic(&dynamic, DT_DEBUG) {
+        Some(r_debug) if r_debug != 0 => r_debug,
+        _ => return Ok(vec![]),
+    };
+    let mut link_map = read_word(r_debug + word as u64)?.unwrap_or_default();
+
+    let mut entries = vec![];
+    while link_map != 0 && entries.len() < MAX_LOADER_ENTRIES {
+        // struct link_map { l_addr; l_name; l_ld; l_next; l_prev; ... }
+        let fields = memory::read(pid, link_map, word * 4)?;
+        let (l_addr, l_name, l_next) = match (
+            layout.word(&fields, 0),
+            layout.word(&fields, word),
+            layout.word(&fields, word * 3),
+        ) {
+            (Some(l_addr), Some(l_name), Some(l_next)) => (l_addr, l_name, l_next),
+            _ => break,
+        };
+
+        entries.push(LoaderEntry {
+            name: read_c_string(pid, l_name)?,
+            load_bias: l_addr,
+        });
+        link_map = l_next;
+    }
+
+    Ok(entries)
+}
+
+/// Reads a NUL-terminated string from the given pid's memory.
+fn read_c_string(pid: Pid, addr: u64) -> Result<String, Error> {
+    if addr == 0 {
+        return Ok(String::new());
+    }
+
+    // NOTE(ww): Reads are chunked at aligned boundaries, so that a string
+    // ending just before an unmapped page doesn't cause a failed read.
+    const CHUNK: u64 = 256;
+
+    let mut bytes = vec![];
+    let mut cursor = addr;
+    while bytes.len() < MAX_NAME_SIZE {
+        let len = CHUNK - cursor % CHUNK;
+        let chunk = memory::read(pid, cursor, to_usize("string chunk size", len)?)?;
+
+        match chunk.iter().position(|b| *b == 0) {
+            Some(nul) => {
+                bytes.extend_from_slice(&chunk[..nul]);
+                break;
+            }
+            None if chunk.is_empty() => break,
+            None => bytes.extend_from_slice(&chunk),
+        }
+        cursor += len;
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_with_loader() {
+        let comparison = compare_with_loader().unwrap();
+
+        assert!(comparison.is_consistent(), "{:#?}", comparison);
+
+        // The main executable is always first, and has no name.
+        assert!(!comparison.loader_entries.is_empty());
+        assert_eq!(comparison.loader_entries[0].name, "");
+        assert!(serde_json::to_string(&comparison).is_ok());
+    }
+
+    #[test]
+    fn test_read_c_string() {
+        let pid = Pid::from(std::process::id());
+        let string = b"rsprocmaps c string\0";
+
+        assert_eq!(
+            read_c_string(pid, string.as_ptr() as u64).unwrap(),
+            "rsprocmaps c string"
+        );
+        assert_eq!(read_c_string(pid, 0).unwrap(), "");
+    }
+}
@@ -0,0 +1,251 @@
+//! Inventories of the shared memory mapped by a process.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{from_pid, Device, Map, Pathname, Pid, DELETED_SUFFIX};
+
+/// The kinds of shared memory object that a map can be backed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum ShmKind {
+    /// A POSIX shared memory object, i.e. a file under `/dev/shm`.
+    DevShm,
+    /// An anonymous file created by `memfd_create(2)`.
+    Memfd,
+    /// A System V shared memory segment (`shmget(2)`).
+    SysV,
+    /// A shared anonymous mapping (`MAP_SHARED | MAP_ANONYMOUS`).
+    AnonShared,
+    /// A shared anonymous mapping backed by huge pages (`MAP_HUGETLB`).
+    HugetlbShm,
+}
+
+impl ShmKind {
+    /// Classifies a shared map, returning its kind and identifier (e.g. the
+    /// object's name or SysV key), if it has one.
+    pub(crate) fn classify(map: &Map) -> Option<(ShmKind, Option<String>)> {
+        if !map.permissions.shared {
+            return None;
+        }
+
+        let path = match &map.pathname {
+            Pathname::Path(path) => path.strip_suffix(DELETED_SUFFIX).unwrap_or(path),
+            // NOTE(ww): Shared anonymous maps normally show up as "/dev/zero (deleted)",
+            // but we accept pathless shared maps too.
+            Pathname::Mmap => return Some((ShmKind::AnonShared, None)),
+            _ => return None,
+        };
+
+        if let Some(name) = path.strip_prefix("/dev/shm/") {
+            Some((ShmKind::DevShm, Some(name.into())))
+        } else if let Some(name) = path.strip_prefix("/memfd:") {
+            Some((ShmKind::Memfd, Some(name.into())))
+        } else if let Some(key) = path.strip_prefix("/SYSV") {
+            Some((ShmKind::SysV, Some(key.into())))
+        } else if path == "/dev/zero" {
+            Some((ShmKind::AnonShared, None))
+        } else if map.is_hugetlb() {
+            Some((ShmKind::HugetlbShm, None))
+        } else {
+            None
+        }
+    }
+}
+
+/// A shared memory object, and every map of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedRegion<'a> {
+    /// The kind of shared memory object.
+    pub kind: ShmKind,
+    /// The object's identifier: its name for `DevShm` and `Memfd`, its key
+    /// (in hex) for `SysV`, and nothing for anonymous objects.
+    pub identifier: Option<String>,
+    /// The device that the object lives on.
+    pub device: Device,
+    /// The object's inode, which (together with `device`) identifies it.
+    pub inode: u64,
+    /// Every map of the object, in address order.
+    pub maps: Vec<&'a Map>,
+    /// The total size of every map of the object, in bytes.
+    pub total_bytes: u64,
+}
+
+/// Returns every shared memory object mapped in `maps`.
+///
+/// Maps of the same object are grouped by their (device, inode); maps without
+/// an inode can't be matched up, so each one is its own region. Private maps of
+/// shared memory objects aren't shared, and so aren't included.
+pub fn shared_memory_inventory(maps: &[Map]) -> Vec<SharedRegion<'_>> {
+    let mut regions: Vec<SharedRegion<'_>> = vec![];
+
+    for map in maps {
+        let (kind, identifier) = match ShmKind::classify(map) {
+            Some(classified) => classified,
+            None => continue,
+        };
+        let size = map.address_range.len();
+
+        match regions.iter_mut().find(|r| {
+            map.inode != 0 && r.inode == map.inode && r.device == map.device && r.kind == kind
+        }) {
+            Some(region) => {
+                region.maps.push(map);
+                region.total_bytes += size;
+            }
+            None => regions.push(SharedRegion {
+                kind,
+                identifier,
+                device: map.device.clone(),
+                inode: map.inode,
+                maps: vec![map],
+                total_bytes: size,
+            }),
+        }
+    }
+
+    regions
+}
+
+/// A shared memory object mapped by two processes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SharedObject {
+    /// The kind of shared memory object.
+    pub kind: ShmKind,
+    /// The object's identifier; see [`SharedRegion::identifier`].
+    pub identifier: Option<String>,
+    /// The device that the object lives on.
+    pub device: Device,
+    /// The object's inode.
+    pub inode: u64,
+    /// The total size of the first process's maps of the object, in bytes.
+    pub bytes_a: u64,
+    /// The total size of the second process's maps of the object, in bytes.
+    pub bytes_b: u64,
+}
+
+/// Returns every shared memory object that's mapped by both of the given processes.
+///
+/// Objects are matched by their (device, inode), so objects without an inode
+/// (i.e. pathless shared anonymous maps) never match.
+pub fn shared_with(
+    pid_a: impl Into<Pid>,
+    pid_b: impl Into<Pid>,
+) -> Result<Vec<SharedObject>, Error> {
+    let maps_a = from_pid(pid_a)?.collect::<Result<Vec<_>, _>>()?;
+    let maps_b = from_pid(pid_b)?.collect::<Result<Vec<_>, _>>()?;
+
+    Ok(intersect(
+        &shared_memory_inventory(&maps_a),
+        &shared_memory_inventory(&maps_b),
+    ))
+}
+
+fn intersect(a: &[SharedRegion], b: &[SharedRegion]) -> Vec<SharedObject> {
+    let b = b
+        .iter()
+        .filter(|r| r.inode != 0)
+        .map(|r| ((r.device.major, r.device.minor, r.inode), r))
+        .collect::<BTreeMap<_, _>>();
+
+    a.iter()
+        .filter(|r| r.inode != 0)
+        .filter_map(|r| {
+            let other = b.get(&(r.device.major, r.device.minor, r.inode))?;
+            Some(SharedObject {
+                kind: r.kind,
+                identifier: r.identifier.clone(),
+                device: r.device.clone(),
+                inode: r.inode,
+                bytes_a: r.total_bytes,
+                bytes_b: other.total_bytes,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::from_path;
+
+    fn fixture() -> Vec<Map> {
+        from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/shm.maps"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_shared_memory_inventory() {
+        let maps = fixture();
+        let inventory = shared_memory_inventory(&maps);
+
+        assert_eq!(
+            inventory
+                .iter()
+                .map(|r| (r.kind, r.identifier.as_deref(), r.maps.len(), r.total_bytes))
+                .collect::<Vec<_>>(),
+            vec![
+                (ShmKind::HugetlbShm, None, 1, 0x200000),
+                (ShmKind::SysV, Some("0000162e"), 1, 0x100000),
+                (ShmKind::AnonShared, None, 1, 0x10000),
+                (ShmKind::Memfd, Some("wayland-shm"), 1, 0x100000),
+                (ShmKind::DevShm, Some("foo"), 2, 0x200000),
+                (ShmKind::DevShm, Some("bar"), 1, 0x1000),
+                (ShmKind::AnonShared, None, 1, 0x10000),
+            ]
+        );
+
+        // The private memfd map isn't shared memory.
+        assert!(inventory
+            .iter()
+            .all(|r| r.identifier.as_deref() != Some("jit-cache")));
+
+        let json = serde_json::to_value(&inventory[4]).unwrap();
+        assert_eq!(json["kind"], "DevShm");
+        assert_eq!(json["maps"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_intersect() {
+        let maps = fixture();
+        let inventory = shared_memory_inventory(&maps);
+
+        // Another process that maps just half of /dev/shm/foo.
+        let other = maps
+            .iter()
+            .filter(|m| matches!(&m.pathname, Pathname::Path(p) if p == "/dev/shm/foo"))
+            .skip(1)
+            .cloned()
+            .collect::<Vec<_>>();
+        let other_inventory = shared_memory_inventory(&other);
+
+        let shared = intersect(&inventory, &other_inventory);
+        assert_eq!(
+            shared
+                .iter()
+                .map(|o| (o.identifier.as_deref(), o.bytes_a, o.bytes_b))
+                .collect::<Vec<_>>(),
+            vec![(Some("foo"), 0x200000, 0x100000)]
+        );
+    }
+
+    #[test]
+    fn test_shared_with_self() {
+        let pid = Pid::from(std::process::id());
+        let maps = from_pid(pid)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let expected = shared_memory_inventory(&maps)
+            .into_iter()
+            .filter(|r| r.inode != 0)
+            .count();
+        assert_eq!(shared_with(pid, pid).unwrap().len(), expected);
+    }
+}
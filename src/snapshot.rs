@@ -0,0 +1,1388 @@
+//! Owned, in-memory collections of maps.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::BufRead;
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::module;
+use crate::smaps::{self, SmapEntry};
+use crate::{
+    from_path, is_sorted_by_address, AddressRange, Map, Maps, Pathname, Pid, DELETED_SUFFIX,
+};
+
+/// The largest guard region that [`MapsSnapshot::stack_guards`] will pair with a stack.
+///
+/// Thread stack guards are a page or a few pages (glibc and musl both default
+/// to a single page), while the `---p` reservations at the end of malloc arenas
+/// are usually many megabytes. Limiting the guard size keeps an arena's reservation
+/// from being mistaken for the guard of whatever is mapped above it.
+pub const MAX_STACK_GUARD_SIZE: u64 = 64 * 1024;
+
+/// Whether a snapshot's maps look like they came from a 32-bit or 64-bit process;
+/// see [`MapsSnapshot::address_space_hint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AddressSpaceHint {
+    /// The maps fit in a 4 GiB address space and include a stack, so they're
+    /// from a 32-bit process (possibly under a 64-bit kernel).
+    ThirtyTwoBit,
+    /// Some map ends above 4 GiB, or there's a `[vsyscall]` page.
+    SixtyFourBit,
+    /// There isn't enough to go on, e.g. because the snapshot is empty.
+    Unknown,
+}
+
+/// An address resolved against a snapshot, e.g. for handing off to a symbolizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedAddress<'a> {
+    /// The map containing the address.
+    pub map: &'a Map,
+    /// The path of the module (i.e. file) containing the address, if file-backed.
+    pub module_path: Option<&'a Path>,
+    /// The address's offset within the module's file, if file-backed (and if
+    /// the map's offset is small enough for it not to overflow).
+    pub file_offset: Option<u64>,
+    /// The address's offset from the module's base address, if file-backed.
+    pub module_relative: Option<u64>,
+}
+
+/// A map annotated with the pid of the process it came from; see [`MapsSnapshot::with_pid`].
+///
+/// A `ProcessMap` dereferences to its `Map`, so every `Map` method can be called
+/// on it directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessMap {
+    /// The pid of the process that the map came from.
+    pub pid: Pid,
+    /// The map itself.
+    pub map: Map,
+}
+
+impl Deref for ProcessMap {
+    type Target = Map;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl From<(Pid, Map)> for ProcessMap {
+    fn from((pid, map): (Pid, Map)) -> Self {
+        ProcessMap { pid, map }
+    }
+}
+
+/// A snapshot of a process's maps, i.e. every `Map` read from a single source.
+///
+/// A `MapsSnapshot` serializes as a plain sequence of maps, in the same
+/// format as the reference outputs in `test_data/`.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct MapsSnapshot {
+    maps: Vec<Map>,
+    // NOTE(ww): Each module's base address, for resolving addresses. This is
+    // built on first use and must be reset whenever the maps change.
+    #[serde(skip)]
+    module_bases: OnceLock<HashMap<PathBuf, u64>>,
+}
+
+impl std::fmt::Debug for MapsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MapsSnapshot")
+            .field("maps", &self.maps)
+            .finish()
+    }
+}
+
+impl PartialEq for MapsSnapshot {
+    fn eq(&self, other: &MapsSnapshot) -> bool {
+        self.maps == other.maps
+    }
+}
+
+impl Eq for MapsSnapshot {}
+
+impl MapsSnapshot {
+    /// Consumes `maps`, returning a snapshot of every map or the first parse error.
+    ///
+    /// This is equivalent to `maps.collect::<Result<MapsSnapshot, _>>()`.
+    pub fn from_maps<T: BufRead>(maps: Maps<T>) -> Result<MapsSnapshot, Error> {
+        maps.collect()
+    }
+
+    /// Returns the maps in this snapshot.
+    pub fn maps(&self) -> &[Map] {
+        &self.maps
+    }
+
+    /// Consumes this snapshot, returning its maps.
+    pub fn into_maps(self) -> Vec<Map> {
+        self.maps
+    }
+
+    /// Consumes this snapshot, annotating each of its maps with `pid`.
+    ///
+    /// This is useful for tracking where each map came from when merging maps
+    /// from several processes.
+    pub fn with_pid(self, pid: impl Into<Pid>) -> Vec<ProcessMap> {
+        let pid = pid.into();
+
+        self.maps
+            .into_iter()
+            .map(|map| ProcessMap { pid, map })
+            .collect()
+    }
+
+    /// Writes this snapshot to `path` in the maps format, as produced by a 64-bit
+    /// kernel (see [`Map::to_maps_line`]), replacing anything already there.
+    ///
+    /// A snapshot parsed from a 64-bit kernel's maps file is written back
+    /// byte-for-byte, so saved snapshots can be compared with (or read by
+    /// anything that reads) real maps files.
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut contents = String::new();
+        for map in &self.maps {
+            contents.push_str(&map.to_maps_line());
+            contents.push('\n');
+        }
+
+        Ok(fs::write(path, contents)?)
+    }
+
+    /// Reads a snapshot from a maps file at `path`, e.g. one written by
+    /// [`MapsSnapshot::save_to_path`].
+    ///
+    /// This is equivalent to `MapsSnapshot::from_maps(from_path(path)?)`.
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<MapsSnapshot, Error> {
+        MapsSnapshot::from_maps(from_path(path)?)
+    }
+
+    /// Serializes this snapshot to JSON.
+    ///
+    /// JSON is this crate's interchange format for maps: each map is an object
+    /// with the same fields as [`Map`], in the same layout as the reference
+    /// outputs in `test_data/`. See [`MapsSnapshot::to_canonical_json`]
+    /// for byte-for-byte reproducible output.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Deserializes a snapshot from JSON, in the format produced by [`MapsSnapshot::to_json`].
+    pub fn from_json(json: &str) -> Result<MapsSnapshot, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Infers whether this snapshot is from a 32-bit or 64-bit process.
+    ///
+    /// Any map ending above 4 GiB means a 64-bit process, as does the x86-64
+    /// `[vsyscall]` page, which is never mapped into 32-bit processes. Otherwise,
+    /// the stack is the tell: a 64-bit process's stack is always mapped far above
+    /// 4 GiB, so a stack below it means a 32-bit process. Snapshots with neither
+    /// (e.g. partial or synthetic ones) are `Unknown`.
+    pub fn address_space_hint(&self) -> AddressSpaceHint {
+        if self
+            .maps
+            .iter()
+            .any(|m| m.address_range.end > 1 << 32 || m.pathname == Pathname::Vsyscall)
+        {
+            AddressSpaceHint::SixtyFourBit
+        } else if self.maps.iter().any(|m| m.pathname == Pathname::Stack) {
+            AddressSpaceHint::ThirtyTwoBit
+        } else {
+            AddressSpaceHint::Unknown
+        }
+    }
+
+    /// Returns the `[vdso]` map, if there's exactly one.
+    pub fn vdso(&self) -> Option<&Map> {
+        self.unique(&Pathname::Vdso)
+    }
+
+    /// Returns the `[vvar]` map, if there's exactly one.
+    pub fn vvar(&self) -> Option<&Map> {
+        self.unique(&Pathname::Vvar)
+    }
+
+    /// Returns the `[vsyscall]` map, if there's exactly one.
+    ///
+    /// Only x86-64 has a vsyscall page, and only for 64-bit processes.
+    pub fn vsyscall(&self) -> Option<&Map> {
+        self.unique(&Pathname::Vsyscall)
+    }
+
+    /// Returns the `[heap]` map, if there's exactly one.
+    ///
+    /// `[heap]` is the region grown by `brk`, so this returns `None` for processes
+    /// whose allocator only uses `mmap`'d arenas (or hasn't allocated yet).
+    pub fn heap(&self) -> Option<&Map> {
+        self.unique(&Pathname::Heap)
+    }
+
+    /// Returns every stack in this snapshot, in address order.
+    ///
+    /// This includes the main thread's `[stack]`, the `[stack:<tid>]` maps that
+    /// older kernels (and Android) label thread stacks with, and thread stacks
+    /// detected by their guards; see [`MapsSnapshot::stack_guards`].
+    pub fn stacks(&self) -> Vec<&Map> {
+        let mut stacks = self
+            .maps
+            .iter()
+            .filter(|m| match &m.pathname {
+                Pathname::Stack => true,
+                Pathname::OtherPseudo(pseudo) => pseudo.starts_with("[stack:"),
+                _ => false,
+            })
+            .chain(self.stack_guards().into_iter().map(|(_, stack)| stack))
+            .collect::<Vec<_>>();
+
+        stacks.sort_by_key(|m| m.address_range.begin);
+        stacks.dedup_by_key(|m| m.address_range.begin);
+        stacks
+    }
+
+    fn unique(&self, pathname: &Pathname) -> Option<&Map> {
+        let mut matches = self.maps.iter().filter(|m| &m.pathname == pathname);
+
+        match (matches.next(), matches.next()) {
+            (Some(map), None) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Returns every guard region in this snapshot; see [`Map::is_guard`].
+    pub fn guard_regions(&self) -> Vec<&Map> {
+        self.maps.iter().filter(|m| m.is_guard()).collect()
+    }
+
+    /// Returns the anonymous maps in this snapshot; see [`Map::is_anonymous`].
+    pub fn anonymous_maps(&self) -> impl Iterator<Item = &Map> {
+        self.maps.iter().filter(|m| m.is_anonymous())
+    }
+
+    /// Returns the file-backed maps in this snapshot, i.e. those with a path
+    /// and a backing device.
+    pub fn file_backed_maps(&self) -> impl Iterator<Item = &Map> {
+        self.maps.iter().filter(|m| m.is_file_backed())
+    }
+
+    /// Returns each stack guard in this snapshot, paired with the stack that it protects.
+    ///
+    /// Stacks grow down, so their guards sit directly below them: glibc and musl
+    /// both allocate thread stacks as a single mapping whose lowest page(s) are
+    /// made inaccessible. This heuristic is deliberately conservative: a guard is
+    /// only paired when it's no larger than [`MAX_STACK_GUARD_SIZE`] and immediately
+    /// adjacent to either `[stack]` or an anonymous, private, read-write map above it.
+    /// The main thread's stack usually has no visible guard, since the kernel
+    /// enforces its guard gap without a map.
+    pub fn stack_guards(&self) -> Vec<(&Map, &Map)> {
+        self.maps
+            .windows(2)
+            .filter_map(|pair| {
+                let (guard, stack) = (&pair[0], &pair[1]);
+
+                let is_stack = stack.pathname == Pathname::Stack
+                    || (stack.pathname == Pathname::Mmap
+                        && stack.permissions.readable
+                        && stack.permissions.writable
+                        && !stack.permissions.executable
+                        && stack.permissions.private);
+
+                if guard.is_guard()
+                    && guard.address_range.len() <= MAX_STACK_GUARD_SIZE
+                    && guard.address_range.end == stack.address_range.begin
+                    && is_stack
+                {
+                    Some((guard, stack))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns whether any two maps in this snapshot overlap, with one writable and
+    /// the other executable.
+    ///
+    /// The kernel never emits overlapping maps, but snapshots assembled from other
+    /// sources (or merged from several reads) can contain them, and an overlap
+    /// between a writable and an executable region is a W^X violation in all but name.
+    /// This compares every pair of maps, which is quadratic but fine for the
+    /// sizes of typical maps.
+    pub fn executable_regions_overlap(&self) -> bool {
+        let overlap = |a: &Map, b: &Map| {
+            a.address_range.begin < b.address_range.end
+                && b.address_range.begin < a.address_range.end
+        };
+
+        self.maps.iter().enumerate().any(|(i, a)| {
+            self.maps[i + 1..].iter().any(|b| {
+                overlap(a, b)
+                    && ((a.permissions.writable && b.permissions.executable)
+                        || (a.permissions.executable && b.permissions.writable))
+            })
+        })
+    }
+
+    /// Resolves `addr` to the map containing it and, for file-backed maps, its
+    /// offsets within its module.
+    ///
+    /// Addresses in anonymous or pseudo-path maps resolve with no module information.
+    /// The snapshot must be sorted by address, as the kernel emits maps.
+    pub fn resolve(&self, addr: u64) -> Option<ResolvedAddress<'_>> {
+        let map = self.find(addr)?;
+
+        Some(self.resolve_in(map, addr))
+    }
+
+    /// Resolves each address in `addrs`, as with [`MapsSnapshot::resolve`].
+    ///
+    /// The results are in the same order as `addrs`. Internally, the addresses are
+    /// sorted and swept against the maps in a single pass, making this much
+    /// cheaper than repeated calls to `resolve` for large batches.
+    pub fn resolve_many(&self, addrs: &[u64]) -> Vec<Option<ResolvedAddress<'_>>> {
+        let mut order = (0..addrs.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| addrs[i]);
+
+        let mut resolved = vec![None; addrs.len()];
+        let mut maps = self.maps.iter().peekable();
+        for i in order {
+            let addr = addrs[i];
+            while maps.peek().is_some_and(|m| m.address_range.end <= addr) {
+                maps.next();
+            }
+
+            if let Some(map) = maps.peek().filter(|m| m.address_range.begin <= addr) {
+                resolved[i] = Some(self.resolve_in(map, addr));
+            }
+        }
+
+        resolved
+    }
+
+    /// Returns every (query range, map) pair in which the two share at least one
+    /// address, ordered by query range (by address, then by position in `ranges`)
+    /// and then by map address.
+    ///
+    /// A range that spans several maps yields a pair for each of them, and a range
+    /// that falls entirely in a hole (or is empty) yields nothing. Neither `ranges`
+    /// nor the snapshot needs to be sorted: both are sorted internally and then swept
+    /// together, making this much cheaper than checking every pair for large batches.
+    pub fn intersecting<'a>(
+        &'a self,
+        ranges: &'a [AddressRange],
+    ) -> impl Iterator<Item = (&'a AddressRange, &'a Map)> + 'a {
+        let mut queries = ranges
+            .iter()
+            .filter(|range| range.begin < range.end)
+            .collect::<Vec<_>>();
+        queries.sort_by_key(|range| range.begin);
+
+        let mut maps = self.maps.iter().collect::<Vec<_>>();
+        if !is_sorted_by_address(&self.maps) {
+            maps.sort_by_key(|m| m.address_range.begin);
+        }
+
+        // NOTE(ww): Queries are visited in order of their beginnings, so a map that
+        // ends at or before one query's beginning can't intersect any later query,
+        // and the sweep never needs to revisit it.
+        let mut first = 0;
+        queries.into_iter().flat_map(move |range| {
+            while maps
+                .get(first)
+                .is_some_and(|m| m.address_range.end <= range.begin)
+            {
+                first += 1;
+            }
+
+            maps[first..]
+                .iter()
+                .take_while(|m| m.address_range.begin < range.end)
+                .filter(|m| range.begin < m.address_range.end)
+                .map(|m| (range, *m))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Returns the total `Private_Dirty` memory of every map in this snapshot, in bytes,
+    /// by correlating it with the given `smaps` entries.
+    ///
+    /// Private dirty memory is the best single measure of how much memory
+    /// belongs to a process alone, since it excludes pages shared with others.
+    ///
+    /// `smaps` must be sorted by address, as the kernel emits it. Returns `None`
+    /// if any map lacks an `smaps` entry with the same address range (e.g. because
+    /// the process changed between reads) or the kernel didn't report `Private_Dirty`.
+    pub fn total_private_dirty(&self, smaps: &[SmapEntry]) -> Option<u64> {
+        self.maps
+            .iter()
+            .map(|map| {
+                map.overlapping_smaps_entry(smaps)
+                    .filter(|e| e.map.address_range == map.address_range)?
+                    .private_dirty
+            })
+            .sum::<Option<u64>>()
+            .map(|kb| kb * 1024)
+    }
+
+    /// Like [`MapsSnapshot::total_private_dirty`], but reads `smaps` for the given pid.
+    ///
+    /// Reading another process's `smaps` requires `PTRACE_MODE_READ` access to it,
+    /// i.e. the same UID (subject to the Yama `ptrace_scope` setting) or `CAP_SYS_PTRACE`.
+    pub fn total_private_dirty_for_pid(&self, pid: impl Into<Pid>) -> Result<Option<u64>, Error> {
+        let smaps = smaps::from_pid(pid)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(self.total_private_dirty(&smaps))
+    }
+
+    /// Returns whether any map in this snapshot contains `addr`.
+    ///
+    /// Like [`MapsSnapshot::resolve`], this expects the snapshot to be sorted by
+    /// address, and is a binary search.
+    pub fn has_address(&self, addr: u64) -> bool {
+        self.find(addr).is_some()
+    }
+
+    /// Returns whether any map in this snapshot contains each address in `addrs`,
+    /// as with [`MapsSnapshot::has_address`].
+    ///
+    /// The results are in the same order as `addrs`, and each address costs a
+    /// single binary search.
+    pub fn contains_all(&self, addrs: &[u64]) -> Vec<bool> {
+        addrs.iter().map(|&addr| self.has_address(addr)).collect()
+    }
+
+    /// Returns whether a single map in this snapshot contains the whole of `range`.
+    ///
+    /// Ranges that span several adjacent maps aren't contained, since they may
+    /// cross permission boundaries.
+    pub fn has_range(&self, range: &AddressRange) -> bool {
+        self.find(range.begin)
+            .is_some_and(|m| range.end <= m.address_range.end)
+    }
+
+    /// Returns the map containing `addr` or, if `addr` is in a hole, the nearest
+    /// map below it. Returns `None` if there are no maps at or below `addr`.
+    ///
+    /// Like [`MapsSnapshot::resolve`], this expects the snapshot to be sorted by address.
+    pub fn nearest_below(&self, addr: u64) -> Option<&Map> {
+        let idx = self.maps.partition_point(|m| m.address_range.begin <= addr);
+        idx.checked_sub(1).map(|idx| &self.maps[idx])
+    }
+
+    /// Returns the map containing `addr` or, if `addr` is in a hole, the nearest
+    /// map above it. Returns `None` if there are no maps at or above `addr`.
+    ///
+    /// Like [`MapsSnapshot::resolve`], this expects the snapshot to be sorted by address.
+    pub fn nearest_above(&self, addr: u64) -> Option<&Map> {
+        let idx = self.maps.partition_point(|m| m.address_range.end <= addr);
+        self.maps.get(idx)
+    }
+
+    /// Returns the index of the map containing `addr`, for direct access into
+    /// [`MapsSnapshot::maps`].
+    ///
+    /// This is a binary search when the snapshot is sorted by address, as the
+    /// kernel emits maps. Snapshots assembled in some other order fall back to a
+    /// linear scan, but only once the binary search has come up empty, so the
+    /// sortedness check costs nothing for addresses that are found.
+    pub fn position_of_address(&self, addr: u64) -> Option<usize> {
+        let idx = self.maps.partition_point(|m| m.address_range.end <= addr);
+
+        match self.maps.get(idx) {
+            Some(map) if map.contains_address(addr) => Some(idx),
+            _ if !is_sorted_by_address(&self.maps) => {
+                self.maps.iter().position(|m| m.contains_address(addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn find(&self, addr: u64) -> Option<&Map> {
+        let idx = self.maps.partition_point(|m| m.address_range.end <= addr);
+        self.maps.get(idx).filter(|m| m.address_range.begin <= addr)
+    }
+
+    /// Returns the base address of each module in this snapshot, by path.
+    fn module_bases(&self) -> &HashMap<PathBuf, u64> {
+        self.module_bases.get_or_init(|| {
+            module::modules(&self.maps)
+                .into_iter()
+                .map(|module| {
+                    let base = module.base();
+                    (module.path, base)
+                })
+                .collect()
+        })
+    }
+
+    /// Resolves `addr`, which must be within `map`.
+    ///
+    /// A file offset that would overflow (e.g. from a bogus parsed offset) is `None`.
+    fn resolve_in<'a>(&'a self, map: &'a Map, addr: u64) -> ResolvedAddress<'a> {
+        match &map.pathname {
+            Pathname::Path(path) => {
+                let path = Path::new(path);
+                let base = self.module_bases()[path];
+
+                ResolvedAddress {
+                    map,
+                    module_path: Some(path),
+                    file_offset: map.offset.checked_add(addr - map.address_range.begin),
+                    module_relative: Some(addr - base),
+                }
+            }
+            _ => ResolvedAddress {
+                map,
+                module_path: None,
+                file_offset: None,
+                module_relative: None,
+            },
+        }
+    }
+
+    /// Puts this snapshot into its canonical form.
+    ///
+    /// The canonical form is a stability guarantee: two snapshots of the
+    /// same maps are identical once canonicalized, regardless of the order
+    /// they were read in. In the canonical form:
+    ///
+    /// * Maps are sorted by address range (begin, then end), then by pathname.
+    /// * Escaped octal sequences in pathnames (e.g. `\012` for a newline) are decoded.
+    /// * The " (deleted)" suffix is stripped from pathnames and recorded
+    ///   in [`Map::deleted`] instead.
+    pub fn canonicalize(&mut self) {
+        self.module_bases = OnceLock::new();
+
+        for map in self.maps.iter_mut() {
+            if let Pathname::Path(path) = &mut map.pathname {
+                if let Some(stripped) = path.strip_suffix(DELETED_SUFFIX) {
+                    *path = stripped.into();
+                    map.deleted = true;
+                }
+                *path = decode_escapes(path);
+            }
+        }
+
+        self.maps.sort_by(|a, b| {
+            (a.address_range.begin, a.address_range.end, &a.pathname).cmp(&(
+                b.address_range.begin,
+                b.address_range.end,
+                &b.pathname,
+            ))
+        });
+    }
+
+    /// Returns this snapshot's canonical JSON representation.
+    ///
+    /// The output is deterministic: object keys are sorted, all numbers are
+    /// integers, and the output ends with a trailing newline. Callers
+    /// that want the canonical JSON of the canonical form should call
+    /// [`MapsSnapshot::canonicalize`] first.
+    pub fn to_canonical_json(&self) -> String {
+        // NOTE(ww): Going through Value gives us sorted keys by default; the
+        // explicit sort keeps them sorted even if something else in the
+        // dependency graph enables serde_json's `preserve_order` feature.
+        // Serializing our own types to a Value can't fail, so the unwrap is safe.
+        let mut value = serde_json::to_value(self).unwrap();
+        value.sort_all_objects();
+
+        let mut json = serde_json::to_string_pretty(&value).unwrap();
+        json.push('\n');
+        json
+    }
+}
+
+impl Deref for MapsSnapshot {
+    type Target = [Map];
+
+    fn deref(&self) -> &Self::Target {
+        &self.maps
+    }
+}
+
+impl From<Vec<Map>> for MapsSnapshot {
+    fn from(maps: Vec<Map>) -> Self {
+        MapsSnapshot {
+            maps,
+            ..Default::default()
+        }
+    }
+}
+
+impl<T: BufRead> TryFrom<Maps<T>> for MapsSnapshot {
+    type Error = Error;
+
+    fn try_from(maps: Maps<T>) -> Result<Self, Self::Error> {
+        MapsSnapshot::from_maps(maps)
+    }
+}
+
+impl FromIterator<Map> for MapsSnapshot {
+    fn from_iter<I: IntoIterator<Item = Map>>(iter: I) -> Self {
+        MapsSnapshot {
+            maps: iter.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Decodes the `\ooo` octal escapes that the kernel uses for special
+/// characters (like newlines) in pathnames.
+pub(crate) fn decode_escapes(path: &str) -> String {
+    let mut decoded = String::with_capacity(path.len());
+    let mut rest = path;
+
+    while let Some(idx) = rest.find('\\') {
+        decoded.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let escape = rest
+            .get(1..4)
+            .filter(|digits| digits.bytes().all(|b| (b'0'..=b'7').contains(&b)))
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        match escape {
+            Some(byte) if byte.is_ascii() => {
+                decoded.push(byte as char);
+                rest = &rest[4..];
+            }
+            _ => {
+                decoded.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::from_str;
+
+    fn fixture(name: &str) -> MapsSnapshot {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data")
+            .join(name);
+
+        from_path(path)
+            .unwrap()
+            .collect::<Result<MapsSnapshot, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_escapes() {
+        assert_eq!(decode_escapes("/tmp/foo\\012bar"), "/tmp/foo\nbar");
+        assert_eq!(decode_escapes("/tmp/foo\\bar"), "/tmp/foo\\bar");
+        assert_eq!(decode_escapes("/tmp/foo\\01"), "/tmp/foo\\01");
+        assert_eq!(decode_escapes("/tmp/foo"), "/tmp/foo");
+    }
+
+    #[test]
+    fn test_from_maps() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps");
+
+        let snapshot = MapsSnapshot::from_maps(from_path(&path).unwrap()).unwrap();
+        assert_eq!(snapshot.len(), 25);
+        assert_eq!(
+            MapsSnapshot::try_from(from_path(&path).unwrap()).unwrap(),
+            snapshot
+        );
+
+        let result = MapsSnapshot::try_from(from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             garbage\n",
+        ));
+        assert!(matches!(result, Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn test_anonymous_maps() {
+        let snapshot = fixture("golden.maps");
+
+        assert_eq!(
+            snapshot
+                .anonymous_maps()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x7fee894f6000, 0x7fee89504000, 0x7fee89557000]
+        );
+
+        // Pseudo-paths and maps with a device aren't anonymous.
+        let snapshot = from_str(
+            "1000-2000 rw-p 00000000 00:00 0 [heap]\n\
+             2000-3000 rw-s 00000000 00:05 1234 \n\
+             3000-4000 rw-p 00000000 00:00 0 \n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        assert_eq!(
+            snapshot
+                .anonymous_maps()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x3000]
+        );
+    }
+
+    #[test]
+    fn test_file_backed_maps() {
+        let snapshot = fixture("golden.maps");
+        assert_eq!(snapshot.file_backed_maps().count(), 17);
+        assert!(snapshot
+            .file_backed_maps()
+            .all(|m| matches!(m.pathname, Pathname::Path(_))));
+
+        // Anonymous maps, pseudo-paths, and paths without a device aren't file-backed.
+        let snapshot = from_str(
+            "1000-2000 r-xp 00000000 08:01 1234 /bin/cat\n\
+             2000-3000 rw-p 00000000 00:00 0 \n\
+             3000-4000 rw-p 00000000 00:00 0 [heap]\n\
+             4000-5000 rw-s 00000000 00:00 0 /SYSV00000000\n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        assert_eq!(
+            snapshot
+                .file_backed_maps()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>(),
+            vec![0x1000]
+        );
+    }
+
+    #[test]
+    fn test_address_space_hint() {
+        assert_eq!(
+            fixture("golden.maps").address_space_hint(),
+            AddressSpaceHint::SixtyFourBit
+        );
+        assert_eq!(
+            fixture("x86-compat.maps").address_space_hint(),
+            AddressSpaceHint::ThirtyTwoBit
+        );
+        assert_eq!(
+            fixture("arm.maps").address_space_hint(),
+            AddressSpaceHint::ThirtyTwoBit
+        );
+        assert_eq!(
+            MapsSnapshot::default().address_space_hint(),
+            AddressSpaceHint::Unknown
+        );
+
+        let snapshot = from_str(
+            "1000-2000 r-xp 00000000 08:01 1234 /bin/cat\n\
+             ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0 [vsyscall]\n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        assert_eq!(
+            snapshot.address_space_hint(),
+            AddressSpaceHint::SixtyFourBit
+        );
+
+        let snapshot = from_str("1000-2000 r-xp 00000000 08:01 1234 /bin/cat\n")
+            .collect::<Result<MapsSnapshot, _>>()
+            .unwrap();
+        assert_eq!(snapshot.address_space_hint(), AddressSpaceHint::Unknown);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("rsprocmaps-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for name in ["golden.maps", "threads.maps"] {
+            let input = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_data")
+                .join(name);
+            let output = dir.join(name);
+
+            let snapshot = MapsSnapshot::load_from_path(&input).unwrap();
+            snapshot.save_to_path(&output).unwrap();
+
+            // The saved file is identical to the kernel's, and loads back the same.
+            assert_eq!(
+                fs::read_to_string(&output).unwrap(),
+                fs::read_to_string(&input).unwrap()
+            );
+            assert_eq!(MapsSnapshot::load_from_path(&output).unwrap(), snapshot);
+        }
+
+        let empty = dir.join("empty.maps");
+        MapsSnapshot::default().save_to_path(&empty).unwrap();
+        assert_eq!(fs::read_to_string(&empty).unwrap(), "");
+        assert!(MapsSnapshot::load_from_path(&empty).unwrap().is_empty());
+
+        // Built maps keep their default permissions through a save and load.
+        let built = MapsSnapshot::from(vec![Map::builder()
+            .address_range(AddressRange {
+                begin: 0x1000,
+                end: 0x2000,
+            })
+            .build()]);
+        let built_path = dir.join("built.maps");
+        built.save_to_path(&built_path).unwrap();
+        assert_eq!(MapsSnapshot::load_from_path(&built_path).unwrap(), built);
+
+        assert!(matches!(
+            MapsSnapshot::load_from_path(dir.join("missing.maps")),
+            Err(Error::Io(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_pid() {
+        let golden = fixture("golden.maps");
+        let threads = fixture("threads.maps");
+        let (golden_len, threads_len) = (golden.len(), threads.len());
+
+        let mut merged = golden.clone().with_pid(100);
+        merged.extend(threads.with_pid(200));
+        assert_eq!(merged.len(), golden_len + threads_len);
+        assert!(merged[..golden_len].iter().all(|m| m.pid == Pid::from(100)));
+        assert!(merged[golden_len..].iter().all(|m| m.pid == Pid::from(200)));
+
+        // Map methods are available through Deref.
+        assert_eq!(merged[0].map, golden[0]);
+        assert_eq!(merged[0].category(), golden[0].category());
+        assert_eq!(
+            merged
+                .iter()
+                .filter(|m| m.pathname == Pathname::Heap)
+                .count(),
+            2
+        );
+
+        let map = golden[0].clone();
+        assert_eq!(
+            ProcessMap::from((Pid::from(1), map.clone())),
+            ProcessMap {
+                pid: Pid::from(1),
+                map
+            }
+        );
+    }
+
+    #[test]
+    fn test_special_regions() {
+        let begin = |map: Option<&Map>| map.map(|m| m.address_range.begin);
+
+        let golden = fixture("golden.maps");
+        assert_eq!(begin(golden.vdso()), Some(0x7fffe35f3000));
+        assert_eq!(begin(golden.vvar()), Some(0x7fffe35f0000));
+        assert_eq!(begin(golden.vsyscall()), Some(0xffffffffff600000));
+        assert_eq!(begin(golden.heap()), Some(0x5610d54a5000));
+
+        let arm = fixture("arm.maps");
+        assert!(arm.vdso().is_some());
+        assert!(arm.vsyscall().is_none());
+        assert_eq!(begin(arm.heap()), Some(0x0138e000));
+
+        // No brk heap, and [vvar_vclock] isn't [vvar].
+        let compat = fixture("x86-compat.maps");
+        assert!(compat.heap().is_none());
+        assert!(compat.vsyscall().is_none());
+        assert_eq!(begin(compat.vvar()), Some(0xf7fe3000));
+
+        // Duplicates aren't unique.
+        let split = from_str(
+            "1000-2000 rw-p 00000000 00:00 0 [heap]\n\
+             3000-4000 rw-p 00000000 00:00 0 [heap]\n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        assert!(split.heap().is_none());
+        assert!(MapsSnapshot::default().vdso().is_none());
+    }
+
+    #[test]
+    fn test_stacks() {
+        let begins = |snapshot: &MapsSnapshot| {
+            snapshot
+                .stacks()
+                .iter()
+                .map(|m| m.address_range.begin)
+                .collect::<Vec<_>>()
+        };
+
+        let golden = fixture("golden.maps");
+        assert_eq!(begins(&golden), vec![0x7fffe35c4000]);
+
+        // The main stack, plus the three thread stacks above their guards.
+        let threads = fixture("threads.maps");
+        let stacks = threads.stacks();
+        assert_eq!(stacks.len(), 4);
+        assert_eq!(stacks.last().unwrap().pathname, Pathname::Stack);
+        assert_eq!(
+            &begins(&threads)[..3],
+            &[0x7f1e62bff000, 0x7f1e63400000, 0x7f1e63c01000]
+        );
+
+        let unusual = fixture("unusual.maps");
+        assert!(unusual
+            .stacks()
+            .iter()
+            .any(|m| m.pathname == Pathname::OtherPseudo("[stack:999]".into())));
+
+        assert!(MapsSnapshot::default().stacks().is_empty());
+    }
+
+    #[test]
+    fn test_guard_regions() {
+        let snapshot = fixture("threads.maps");
+
+        // Two arena reservations and three thread stack guards. libc's
+        // `---p` alignment gap is file-backed, so it isn't a guard.
+        let guards = snapshot.guard_regions();
+        assert_eq!(guards.len(), 5);
+        assert!(guards.iter().all(|m| m.pathname == Pathname::Mmap));
+
+        let pairs = snapshot.stack_guards();
+        assert_eq!(
+            pairs
+                .iter()
+                .map(|(guard, stack)| (guard.address_range.begin, stack.address_range.begin))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x7f1e62bfe000, 0x7f1e62bff000),
+                (0x7f1e633ff000, 0x7f1e63400000),
+                (0x7f1e63c00000, 0x7f1e63c01000),
+            ]
+        );
+
+        assert!(fixture("golden.maps").guard_regions().is_empty());
+    }
+
+    #[test]
+    fn test_executable_regions_overlap() {
+        let snapshot = |maps: &str| MapsSnapshot::from_maps(from_str(maps)).unwrap();
+
+        assert!(!fixture("golden.maps").executable_regions_overlap());
+        assert!(!fixture("threads.maps").executable_regions_overlap());
+
+        assert!(snapshot(
+            "1000-3000 rw-p 00000000 00:00 0 \n\
+             4000-5000 r--p 00000000 00:00 0 \n\
+             2000-4000 r-xp 00000000 08:01 1 /tmp/foo\n"
+        )
+        .executable_regions_overlap());
+
+        // Adjacent isn't overlapping.
+        assert!(!snapshot(
+            "1000-2000 rw-p 00000000 00:00 0 \n\
+             2000-3000 r-xp 00000000 08:01 1 /tmp/foo\n"
+        )
+        .executable_regions_overlap());
+
+        // Overlapping, but neither W^X pairing.
+        assert!(!snapshot(
+            "1000-3000 r--p 00000000 00:00 0 \n\
+             2000-4000 r-xp 00000000 08:01 1 /tmp/foo\n\
+             2800-2900 r-xp 00000000 08:01 1 /tmp/bar\n"
+        )
+        .executable_regions_overlap());
+
+        // A single W+X map isn't a pair.
+        assert!(!snapshot("1000-3000 rwxp 00000000 00:00 0 \n").executable_regions_overlap());
+    }
+
+    #[test]
+    fn test_total_private_dirty() {
+        let smaps =
+            smaps::from_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.smaps"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+        let expected = smaps.iter().map(|e| e.private_dirty.unwrap()).sum::<u64>() * 1024;
+        assert!(expected > 0);
+
+        let snapshot = smaps
+            .iter()
+            .map(|e| e.map.clone())
+            .collect::<MapsSnapshot>();
+        assert_eq!(snapshot.total_private_dirty(&smaps), Some(expected));
+
+        // Maps that can't be correlated with smaps make the total unknowable.
+        assert_eq!(fixture("golden.maps").total_private_dirty(&smaps), None);
+    }
+
+    #[test]
+    fn test_total_private_dirty_for_pid() {
+        let pid = Pid::from(std::process::id());
+        let snapshot = crate::from_pid(pid)
+            .unwrap()
+            .collect::<Result<MapsSnapshot, _>>()
+            .unwrap();
+
+        // Our own maps can change between reads (e.g. as other tests run),
+        // so we can't expect the total to always be available.
+        if let Some(total) = snapshot.total_private_dirty_for_pid(pid).unwrap() {
+            assert!(total > 0);
+        }
+    }
+
+    #[test]
+    fn test_has_address() {
+        let snapshot = fixture("golden.maps");
+
+        assert!(snapshot.has_address(0x5610d526d000));
+        assert!(snapshot.has_address(0x5610d5278fff));
+        assert!(!snapshot.has_address(0x5610d5279000));
+        assert!(!snapshot.has_address(0));
+        assert!(snapshot.has_address(0xffffffffff600fff));
+        assert!(!snapshot.has_address(u64::MAX));
+
+        let range = |begin, end| AddressRange { begin, end };
+        assert!(snapshot.has_range(&range(0x7fee89308000, 0x7fee8932d000)));
+        assert!(snapshot.has_range(&range(0x7fee89309000, 0x7fee8930a000)));
+        // Spans two adjacent maps.
+        assert!(!snapshot.has_range(&range(0x7fee89308000, 0x7fee8932e000)));
+        // Runs off the end of a map into a hole.
+        assert!(!snapshot.has_range(&range(0x7fee894f6000, 0x7fee89500000)));
+        assert!(!snapshot.has_range(&range(0x1000, 0x2000)));
+    }
+
+    /// The quadratic reference for [`MapsSnapshot::intersecting`].
+    fn naive_intersecting<'a>(
+        snapshot: &'a MapsSnapshot,
+        ranges: &'a [AddressRange],
+    ) -> Vec<(&'a AddressRange, &'a Map)> {
+        let mut ranges = ranges.iter().collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.begin);
+
+        let mut maps = snapshot.iter().collect::<Vec<_>>();
+        maps.sort_by_key(|m| m.address_range.begin);
+
+        ranges
+            .into_iter()
+            .flat_map(|range| {
+                maps.iter()
+                    .filter(move |m| {
+                        range.begin.max(m.address_range.begin) < range.end.min(m.address_range.end)
+                    })
+                    .map(move |m| (range, *m))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_intersecting() {
+        let snapshot = fixture("golden.maps");
+        let range = |begin, end| AddressRange { begin, end };
+        let ranges = [
+            // Spans the end of libc's text and the start of its rodata.
+            range(0x7fee8932cff0, 0x7fee8932d010),
+            // In the hole below the first map.
+            range(0x1000, 0x2000),
+            range(0x5610d526d000, 0x5610d526d001),
+            range(0x5610d526d000, 0x5610d526d000),
+        ];
+
+        assert_eq!(
+            snapshot
+                .intersecting(&ranges)
+                .map(|(r, m)| (r.begin, m.address_range.begin))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x5610d526d000, 0x5610d526d000),
+                (0x7fee8932cff0, 0x7fee89308000),
+                (0x7fee8932cff0, 0x7fee8932d000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_intersecting_matches_naive() {
+        // NOTE(ww): A small xorshift generator, so that this test is deterministic
+        // without pulling in a dependency.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+
+        for _ in 0..200 {
+            let mut maps = vec![];
+            let mut addr = next(0x10);
+            for _ in 0..next(20) {
+                let begin = addr + next(4);
+                let end = begin + 1 + next(4);
+                maps.push(Map {
+                    address_range: AddressRange { begin, end },
+                    ..Default::default()
+                });
+                addr = end;
+            }
+            // Shuffle, so that unsorted snapshots are exercised too.
+            for i in (1..maps.len()).rev() {
+                maps.swap(i, next(i as u64 + 1) as usize);
+            }
+            let snapshot = MapsSnapshot::from(maps);
+
+            let ranges = (0..next(20))
+                .map(|_| {
+                    let begin = next(addr + 4);
+                    AddressRange {
+                        begin,
+                        end: begin + next(8),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                snapshot.intersecting(&ranges).collect::<Vec<_>>(),
+                naive_intersecting(&snapshot, &ranges)
+            );
+        }
+    }
+
+    #[test]
+    fn test_contains_all() {
+        let snapshot = fixture("golden.maps");
+
+        assert_eq!(
+            snapshot.contains_all(&[
+                0x5610d5278fff,
+                0,
+                0x7fee89309000,
+                0x5610d5279000,
+                0xffffffffff600000,
+                u64::MAX,
+            ]),
+            vec![true, false, true, false, true, false]
+        );
+        assert!(snapshot.contains_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_position_of_address() {
+        let snapshot = fixture("golden.maps");
+
+        for (i, map) in snapshot.iter().enumerate() {
+            assert_eq!(
+                snapshot.position_of_address(map.address_range.begin),
+                Some(i)
+            );
+            assert_eq!(
+                snapshot.position_of_address(map.address_range.end - 1),
+                Some(i)
+            );
+        }
+        assert_eq!(snapshot.position_of_address(0), None);
+        assert_eq!(snapshot.position_of_address(0x5610d5279000), None);
+
+        // Out of order, the binary search misses some maps, but the scan finds them.
+        let mut maps = snapshot.into_maps();
+        maps.reverse();
+        let reversed = MapsSnapshot::from(maps);
+        for (i, map) in reversed.iter().enumerate() {
+            assert_eq!(
+                reversed.position_of_address(map.address_range.begin),
+                Some(i)
+            );
+        }
+        assert_eq!(reversed.position_of_address(0), None);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let snapshot = fixture("golden.maps");
+        let begin = |m: Option<&Map>| m.map(|m| m.address_range.begin);
+
+        // In the hole between /bin/cat and the heap.
+        assert_eq!(
+            begin(snapshot.nearest_below(0x5610d5300000)),
+            Some(0x5610d5278000)
+        );
+        assert_eq!(
+            begin(snapshot.nearest_above(0x5610d5300000)),
+            Some(0x5610d54a5000)
+        );
+
+        // Exactly at the end of a map is in the hole after it.
+        assert_eq!(
+            begin(snapshot.nearest_below(0x7fee894fa000)),
+            Some(0x7fee894f6000)
+        );
+        assert_eq!(
+            begin(snapshot.nearest_above(0x7fee894fa000)),
+            Some(0x7fee89504000)
+        );
+
+        // Inside a map, both directions return it.
+        assert_eq!(
+            begin(snapshot.nearest_below(0x7fee89308123)),
+            Some(0x7fee89308000)
+        );
+        assert_eq!(
+            begin(snapshot.nearest_above(0x7fee89308123)),
+            Some(0x7fee89308000)
+        );
+
+        // Before the first map and after the last.
+        assert_eq!(snapshot.nearest_below(0x1000), None);
+        assert_eq!(begin(snapshot.nearest_above(0x1000)), Some(0x5610d526d000));
+        assert_eq!(
+            begin(snapshot.nearest_below(u64::MAX)),
+            Some(0xffffffffff600000)
+        );
+        assert_eq!(snapshot.nearest_above(u64::MAX), None);
+
+        assert_eq!(MapsSnapshot::default().nearest_below(0x1000), None);
+    }
+
+    #[test]
+    fn test_json() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.json");
+        let reference = MapsSnapshot::from_json(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(reference, fixture("golden.maps"));
+
+        let json = reference.to_json().unwrap();
+        assert_eq!(MapsSnapshot::from_json(&json).unwrap(), reference);
+
+        assert!(MapsSnapshot::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let snapshot = fixture("golden.maps");
+
+        // An address in libc's second (text) segment.
+        let resolved = snapshot.resolve(0x7fee8932d000 + 0x1234).unwrap();
+        assert_eq!(resolved.map.address_range.begin, 0x7fee8932d000);
+        assert_eq!(
+            resolved.module_path,
+            Some(Path::new("/lib/x86_64-linux-gnu/libc-2.31.so"))
+        );
+        assert_eq!(resolved.file_offset, Some(0x25000 + 0x1234));
+        assert_eq!(
+            resolved.module_relative,
+            Some(0x7fee8932d000 + 0x1234 - 0x7fee89308000)
+        );
+
+        // The last byte of libc's read-only data lands in that segment,
+        // not the following guard segment.
+        let resolved = snapshot.resolve(0x7fee894eefff).unwrap();
+        assert_eq!(resolved.file_offset, Some(0x19d000 + 0x49fff));
+
+        // Anonymous and pseudo-path maps resolve without module information.
+        let resolved = snapshot.resolve(0x7fffe35c4000).unwrap();
+        assert_eq!(resolved.map.pathname, Pathname::Stack);
+        assert_eq!(resolved.module_path, None);
+        assert_eq!(resolved.file_offset, None);
+
+        // Addresses in gaps don't resolve at all.
+        assert!(snapshot.resolve(0x1000).is_none());
+        assert!(snapshot.resolve(0x7fee894fa000).is_none());
+
+        // A file offset that would overflow isn't resolved, but the rest is.
+        let snapshot = from_str(
+            "1000-2000 r--p 00000000 08:01 42 /tmp/huge\n\
+             2000-3000 r-xp fffffffffffff800 08:01 42 /tmp/huge\n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        let resolved = snapshot.resolve(0x2fff).unwrap();
+        assert_eq!(resolved.file_offset, None);
+        assert_eq!(resolved.module_relative, Some(0x1fff));
+        assert_eq!(
+            snapshot.resolve(0x2000).unwrap().file_offset,
+            Some(0xfffffffffffff800)
+        );
+
+        // Module bases follow the pathnames through canonicalization.
+        let mut snapshot = from_str(
+            "1000-2000 r--p 00000000 08:01 42 /tmp/gone (deleted)\n\
+             2000-3000 r-xp 00001000 08:01 42 /tmp/gone (deleted)\n",
+        )
+        .collect::<Result<MapsSnapshot, _>>()
+        .unwrap();
+        assert_eq!(
+            snapshot.resolve(0x2010).unwrap().module_relative,
+            Some(0x1010)
+        );
+        snapshot.canonicalize();
+        let resolved = snapshot.resolve(0x2010).unwrap();
+        assert_eq!(resolved.module_path, Some(Path::new("/tmp/gone")));
+        assert_eq!(resolved.module_relative, Some(0x1010));
+    }
+
+    #[test]
+    fn test_resolve_many() {
+        let snapshot = fixture("golden.maps");
+        let addrs = [
+            0x7fffe35c4000,
+            0x1000,
+            0x7fee8932e000,
+            0x5610d526f010,
+            0x7fee894fa000,
+            0x7fee8932e000,
+        ];
+
+        assert_eq!(
+            snapshot.resolve_many(&addrs),
+            addrs
+                .iter()
+                .map(|addr| snapshot.resolve(*addr))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let mut snapshot = fixture("unusual.maps");
+        snapshot.canonicalize();
+
+        for pair in snapshot.windows(2) {
+            assert!(
+                (pair[0].address_range.begin, pair[0].address_range.end)
+                    <= (pair[1].address_range.begin, pair[1].address_range.end)
+            );
+        }
+
+        let deleted = snapshot.iter().filter(|m| m.deleted()).collect::<Vec<_>>();
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted
+            .iter()
+            .all(|m| matches!(&m.pathname, Pathname::Path(p) if !p.ends_with(DELETED_SUFFIX))));
+
+        // Canonicalization is idempotent.
+        let mut again = snapshot.clone();
+        again.canonicalize();
+        assert_eq!(snapshot, again);
+    }
+
+    #[test]
+    fn test_canonical_json_is_stable() {
+        for name in &["golden.maps", "unusual.maps"] {
+            let mut snapshot = fixture(name);
+            snapshot.canonicalize();
+
+            let json = snapshot.to_canonical_json();
+            assert!(json.ends_with("}\n]\n"));
+            let mut other = fixture(name);
+            other.canonicalize();
+            assert_eq!(json, other.to_canonical_json());
+
+            let roundtrip: MapsSnapshot = serde_json::from_str(&json).unwrap();
+            assert_eq!(roundtrip, snapshot);
+            assert_eq!(roundtrip.to_canonical_json(), json);
+        }
+    }
+}
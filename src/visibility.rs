@@ -0,0 +1,200 @@
+//! Detecting processes hidden by procfs's `hidepid` mount option.
+//!
+//! When `/proc` is mounted with `hidepid=invisible` (or `hidepid=2`), other users'
+//! `/proc/<pid>` directories don't exist at all, rather than failing with
+//! `EACCES`. Without extra care, a hidden process is indistinguishable from one
+//! that has exited.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rustix::io::Errno;
+use rustix::process;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::Pid;
+
+/// How much of other users' processes is visible in `/proc`, per its `hidepid`
+/// mount option. See `man 5 proc`.
+///
+/// Members of the group given by the `gid` mount option can see every process
+/// regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Visibility {
+    /// Every process is visible (`hidepid=off` or `hidepid=0`, the default).
+    All,
+    /// Every `/proc/<pid>` directory is visible, but other users' are inaccessible
+    /// (`hidepid=noaccess` or `hidepid=1`).
+    NoAccess,
+    /// Other users' `/proc/<pid>` directories are invisible
+    /// (`hidepid=invisible` or `hidepid=2`).
+    Invisible,
+    /// Only processes that we could ptrace are visible (`hidepid=ptraceable` or `hidepid=4`).
+    Ptraceable,
+    /// `/proc` isn't mounted, or its mount options couldn't be read.
+    Unknown,
+}
+
+impl Visibility {
+    /// Returns whether processes may be missing from `/proc` entirely, rather
+    /// than just being inaccessible.
+    pub fn hides_processes(&self) -> bool {
+        matches!(self, Visibility::Invisible | Visibility::Ptraceable)
+    }
+}
+
+/// Probes the `hidepid` setting of the `/proc` mount, via `/proc/mounts`.
+///
+/// This lets tools warn up front that other users' processes may be invisible.
+pub fn procfs_visibility() -> Visibility {
+    match fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => parse_mounts(&mounts),
+        Err(_) => Visibility::Unknown,
+    }
+}
+
+fn parse_mounts(mounts: &str) -> Visibility {
+    // NOTE(ww): /proc can be mounted more than once; the last mount is the one
+    // that's visible.
+    let options = mounts.lines().rev().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(_), Some("/proc"), Some("proc"), Some(options)) => Some(options),
+            _ => None,
+        }
+    });
+
+    let options = match options {
+        Some(options) => options,
+        None => return Visibility::Unknown,
+    };
+
+    let hidepid = options
+        .split(',')
+        .find_map(|option| option.strip_prefix("hidepid="));
+
+    match hidepid {
+        None | Some("0") | Some("off") => Visibility::All,
+        Some("1") | Some("noaccess") => Visibility::NoAccess,
+        Some("2") | Some("invisible") => Visibility::Invisible,
+        Some("4") | Some("ptraceable") => Visibility::Ptraceable,
+        Some(_) => Visibility::Unknown,
+    }
+}
+
+/// Given that `<proc_root>/<pid>/maps` doesn't exist, returns [`Error::Hidden`] if
+/// the process demonstrably exists but is hidden from us, and `err` otherwise.
+///
+/// The order of checks matters: the process's existence is only probed after
+/// the maps failed to open, and its `/proc` directory is re-checked afterwards.
+/// A process that exits in between is therefore reported as missing, and a pid
+/// that's reused in between is only reported as hidden if the new process is too.
+pub(crate) fn hidden_or(proc_root: &Path, pid: Pid, err: io::Error) -> Error {
+    // NOTE(ww): A pid of 0 addresses our process group rather than a process.
+    let alive = match process::Pid::from_raw(pid.as_raw()) {
+        // EPERM means that the process exists, but that we can't signal it.
+        Some(pid) => matches!(process::test_kill_process(pid), Ok(()) | Err(Errno::PERM)),
+        None => false,
+    };
+
+    if alive && !proc_root.join(pid.to_string()).exists() {
+        Error::Hidden(pid)
+    } else {
+        err.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_pid_in;
+
+    #[test]
+    fn test_parse_mounts() {
+        let mounts = |options: &str| {
+            format!(
+                "sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0\n\
+                 proc /proc proc {} 0 0\n\
+                 tmpfs /run tmpfs rw,nosuid,nodev,mode=755 0 0\n",
+                options
+            )
+        };
+
+        assert_eq!(
+            parse_mounts(&mounts("rw,nosuid,nodev,noexec,relatime")),
+            Visibility::All
+        );
+        assert_eq!(
+            parse_mounts(&mounts("rw,relatime,hidepid=off")),
+            Visibility::All
+        );
+        assert_eq!(
+            parse_mounts(&mounts("rw,relatime,hidepid=1")),
+            Visibility::NoAccess
+        );
+        assert_eq!(
+            parse_mounts(&mounts("rw,relatime,gid=10,hidepid=invisible")),
+            Visibility::Invisible
+        );
+        assert_eq!(
+            parse_mounts(&mounts("rw,relatime,hidepid=2")),
+            Visibility::Invisible
+        );
+        assert_eq!(
+            parse_mounts(&mounts("rw,relatime,hidepid=ptraceable")),
+            Visibility::Ptraceable
+        );
+        assert_eq!(
+            parse_mounts("tmpfs /run tmpfs rw 0 0\n"),
+            Visibility::Unknown
+        );
+
+        // The last mount of /proc wins.
+        assert_eq!(
+            parse_mounts(&format!(
+                "{}proc /proc proc rw,hidepid=2 0 0\n",
+                mounts("rw")
+            )),
+            Visibility::Invisible
+        );
+
+        assert!(Visibility::Invisible.hides_processes());
+        assert!(!Visibility::NoAccess.hides_processes());
+        assert_ne!(procfs_visibility(), Visibility::Unknown);
+    }
+
+    #[test]
+    fn test_hidden() {
+        let proc_root =
+            std::env::temp_dir().join(format!("rsprocmaps-hidepid-{}", std::process::id()));
+        let pid = Pid::from(std::process::id());
+        fs::create_dir_all(&proc_root).unwrap();
+
+        // We're certainly alive, but invisible in the fake /proc.
+        assert!(matches!(
+            from_pid_in(&proc_root, pid),
+            Err(Error::Hidden(hidden)) if hidden == pid
+        ));
+
+        // A process whose directory is visible, but whose maps are missing,
+        // isn't hidden.
+        fs::create_dir_all(proc_root.join(pid.to_string())).unwrap();
+        assert!(matches!(
+            from_pid_in(&proc_root, pid),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        // Nor is a process that doesn't exist.
+        assert!(matches!(
+            from_pid_in(&proc_root, Pid::from(libc::pid_t::MAX)),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        fs::write(proc_root.join(pid.to_string()).join("maps"), "").unwrap();
+        assert!(from_pid_in(&proc_root, pid).is_ok());
+
+        fs::remove_dir_all(&proc_root).unwrap();
+    }
+}
@@ -0,0 +1,671 @@
+//! Polling-based change detection for `/proc/<pid>/maps`.
+//!
+//! procfs doesn't support `inotify`, so the only way to notice changes
+//! to a process's memory layout is to re-read its maps periodically.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::{from_pid, Map, Maps, Pid};
+
+/// The default interval between polls of a process's maps.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Represents the difference between two successive reads of a process's maps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapsDiff {
+    /// Maps that are present in the newer read but not the older one.
+    pub added: Vec<Map>,
+    /// Maps that are present in the older read but not the newer one.
+    pub removed: Vec<Map>,
+}
+
+impl MapsDiff {
+    /// Computes the difference between `old` and `new`.
+    ///
+    /// Both inputs are expected to be sorted by address, as the kernel
+    /// emits them. A map whose permissions (or any other field) changed
+    /// appears as both removed and added.
+    pub fn between(old: &[Map], new: &[Map]) -> MapsDiff {
+        let mut diff: MapsDiff = Default::default();
+        let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+
+        loop {
+            match (old.peek(), new.peek()) {
+                (Some(o), Some(n)) => {
+                    let (o_key, n_key) = (
+                        (o.address_range.begin, o.address_range.end),
+                        (n.address_range.begin, n.address_range.end),
+                    );
+
+                    match o_key.cmp(&n_key) {
+                        Ordering::Less => diff.removed.push(old.next().unwrap().clone()),
+                        Ordering::Greater => diff.added.push(new.next().unwrap().clone()),
+                        Ordering::Equal => {
+                            let (o, n) = (old.next().unwrap(), new.next().unwrap());
+                            if o != n {
+                                diff.removed.push(o.clone());
+                                diff.added.push(n.clone());
+                            }
+                        }
+                    }
+                }
+                (Some(_), None) => diff.removed.extend(old.by_ref().cloned()),
+                (None, Some(_)) => diff.added.extend(new.by_ref().cloned()),
+                (None, None) => break,
+            }
+        }
+
+        diff
+    }
+
+    /// Returns whether this diff contains no changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Watches a process's maps on a background thread, emitting a [`MapsDiff`]
+/// whenever they change.
+///
+/// The background thread exits when the `Watch` is dropped, or when the
+/// process's maps can no longer be read (e.g. because the process has exited).
+pub struct Watch {
+    receiver: Receiver<MapsDiff>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    /// Creates a new `Watch` for the given pid, polling at [`DEFAULT_INTERVAL`].
+    pub fn new(pid: impl Into<Pid>) -> Result<Watch, Error> {
+        Watch::with_interval(pid, DEFAULT_INTERVAL)
+    }
+
+    /// Creates a new `Watch` for the given pid, polling at the given `interval`.
+    pub fn with_interval(pid: impl Into<Pid>, interval: Duration) -> Result<Watch, Error> {
+        let pid = pid.into();
+        // NOTE(ww): We take the initial read here rather than on the background
+        // thread, so that an invalid or inaccessible pid is reported to the caller.
+        let mut last = from_pid(pid)?.collect::<Result<Vec<_>, _>>()?;
+
+        let (sender, receiver) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let current =
+                    match from_pid(pid).and_then(|maps| maps.collect::<Result<Vec<_>, _>>()) {
+                        Ok(current) => current,
+                        Err(_) => break,
+                    };
+
+                let diff = MapsDiff::between(&last, &current);
+                if !diff.is_empty() && sender.send(diff).is_err() {
+                    break;
+                }
+
+                last = current;
+            }
+        });
+
+        Ok(Watch {
+            receiver,
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns the receiving end of this `Watch`'s channel of [`MapsDiff`]s.
+    pub fn receiver(&self) -> &Receiver<MapsDiff> {
+        &self.receiver
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        // NOTE(ww): Sending on (or dropping) the stop channel interrupts the
+        // thread's wait, so dropping a `Watch` doesn't block for an interval.
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A single change to a process's maps, as reported by a [`MapsMonitor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapEvent {
+    /// A map was added.
+    Added(Map),
+    /// A map was removed.
+    Removed(Map),
+    /// The map at an address range was replaced by another at the same range,
+    /// e.g. because its permissions changed.
+    Changed {
+        /// The map before the change.
+        old: Map,
+        /// The map after the change.
+        new: Map,
+    },
+    /// The process exited. This is always the last event.
+    Terminated,
+}
+
+impl MapEvent {
+    /// Converts a diff into events, coalescing each removal and addition of the
+    /// same address range into a single `Changed`.
+    fn from_diff(diff: MapsDiff) -> Vec<MapEvent> {
+        let mut added = diff.added.into_iter().peekable();
+        let mut events = vec![];
+
+        // NOTE(ww): Both sides of a diff are sorted by address, so this is a merge.
+        for old in diff.removed {
+            while let Some(new) = added.next_if(|n| {
+                (n.address_range.begin, n.address_range.end)
+                    < (old.address_range.begin, old.address_range.end)
+            }) {
+                events.push(MapEvent::Added(new));
+            }
+
+            match added.next_if(|n| n.address_range == old.address_range) {
+                Some(new) => events.push(MapEvent::Changed { old, new }),
+                None => events.push(MapEvent::Removed(old)),
+            }
+        }
+        events.extend(added.map(MapEvent::Added));
+
+        events
+    }
+}
+
+/// Monitors a process's maps on a background thread, invoking a callback for
+/// each change.
+///
+/// Unlike [`Watch`], this doesn't require the caller to own a loop: events are
+/// delivered straight to the callback. Changes are only observed at polling
+/// granularity, so a map that's added and removed within one interval is never
+/// seen, and a range that's removed and re-added (e.g. by `mprotect`) within one
+/// interval is reported as a single [`MapEvent::Changed`].
+pub struct MapsMonitor;
+
+impl MapsMonitor {
+    /// Starts monitoring the given pid, polling at the given `interval`.
+    ///
+    /// The monitor runs until it's stopped with [`MonitorHandle::stop`] (or its
+    /// handle is dropped), the process exits, the maps can't be read, or the
+    /// callback panics. The last two are reported by [`MonitorHandle::take_error`].
+    pub fn spawn<F>(
+        pid: impl Into<Pid>,
+        interval: Duration,
+        callback: F,
+    ) -> Result<MonitorHandle, Error>
+    where
+        F: Fn(MapEvent) + Send + 'static,
+    {
+        // NOTE(ww): Keeping the maps file open means that we can never read another
+        // process's maps after ours exits, even if its pid is reused.
+        let mut maps_file = MapsFile::open(pid)?;
+        let mut last = maps_file.snapshot()?;
+
+        let (stop, stopped) = mpsc::channel();
+        let error = Arc::new(Mutex::new(None));
+
+        let handle = {
+            let error = error.clone();
+            thread::spawn(move || {
+                let deliver = |event| {
+                    panic::catch_unwind(AssertUnwindSafe(|| callback(event))).map_err(|payload| {
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        Error::CallbackPanicked(message)
+                    })
+                };
+
+                let result = (|| -> Result<(), Error> {
+                    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                        let fingerprint = maps_file.last_fingerprint().unwrap_or_default();
+                        let current = match maps_file.changed_since(fingerprint) {
+                            Ok(Some(current)) => current,
+                            Ok(None) => continue,
+                            // NOTE(ww): Once the process has been reaped, reads of its
+                            // maps fail with ESRCH (or ENOENT, if it's reopened).
+                            Err(Error::Io(e))
+                                if e.raw_os_error() == Some(libc::ESRCH)
+                                    || e.kind() == io::ErrorKind::NotFound =>
+                            {
+                                return deliver(MapEvent::Terminated);
+                            }
+                            Err(e) => return Err(e),
+                        };
+
+                        // NOTE(ww): Every live process has at least one map, so an
+                        // empty read means that the process has exited.
+                        if current.is_empty() {
+                            return deliver(MapEvent::Terminated);
+                        }
+
+                        for event in MapEvent::from_diff(MapsDiff::between(&last, &current)) {
+                            deliver(event)?;
+                        }
+                        last = current;
+                    }
+
+                    Ok(())
+                })();
+
+                if let Err(e) = result {
+                    *error.lock().unwrap() = Some(e);
+                }
+            })
+        };
+
+        Ok(MonitorHandle {
+            stop: Some(stop),
+            handle: Some(handle),
+            error,
+        })
+    }
+}
+
+/// A handle to a running [`MapsMonitor`]. Dropping it stops the monitor.
+pub struct MonitorHandle {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    error: Arc<Mutex<Option<Error>>>,
+}
+
+impl MonitorHandle {
+    /// Stops the monitor, waiting for its thread to exit.
+    ///
+    /// The monitor's sleep is interrupted, so this returns promptly unless the
+    /// callback is in the middle of running.
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    /// Returns whether the monitor has stopped, for any reason.
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|h| h.is_finished())
+    }
+
+    /// Takes the error that stopped the monitor, if any: either a failure to read
+    /// the process's maps, or [`Error::CallbackPanicked`].
+    pub fn take_error(&self) -> Option<Error> {
+        self.error.lock().unwrap().take()
+    }
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A handle to a kept-open maps file, for cheaply re-reading it.
+///
+/// Re-reading an open `/proc/<pid>/maps` after rewinding it yields fresh
+/// contents, without the cost of re-opening it (and re-resolving the pid) on
+/// every poll. It also pins the process: once the process exits, reads return
+/// nothing rather than another process's maps, even if the pid is reused.
+///
+/// Each read is only as consistent as the kernel makes it: large maps files
+/// are produced over several `read(2)`s, between which the process can change
+/// its layout. A snapshot may therefore reflect a mix of states, and
+/// fingerprints of two reads of an unchanged layout can still differ if
+/// anything else in the file (e.g. a stack's bounds) moved.
+#[derive(Debug)]
+pub struct MapsFile {
+    file: File,
+    last_fingerprint: Option<u64>,
+}
+
+impl MapsFile {
+    /// Opens the maps file for the given pid.
+    pub fn open(pid: impl Into<Pid>) -> Result<MapsFile, Error> {
+        MapsFile::from_path(
+            Path::new("/proc")
+                .join(pid.into().validate()?.to_string())
+                .join("maps"),
+        )
+    }
+
+    /// Opens the maps file at the given path.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MapsFile, Error> {
+        Ok(MapsFile {
+            file: File::open(path)?,
+            last_fingerprint: None,
+        })
+    }
+
+    /// Rewinds and re-parses the maps file.
+    pub fn snapshot(&mut self) -> Result<Vec<Map>, Error> {
+        let contents = self.read()?;
+        parse(&contents)
+    }
+
+    /// Rewinds and re-reads the maps file, returning a fingerprint of its contents.
+    ///
+    /// Fingerprints are only comparable within a single run of a program.
+    pub fn fingerprint(&mut self) -> Result<u64, Error> {
+        self.read()?;
+        Ok(self.last_fingerprint.unwrap())
+    }
+
+    /// Rewinds and re-reads the maps file, returning its maps if its fingerprint
+    /// differs from `fingerprint` and `None` otherwise.
+    ///
+    /// The fingerprint of the contents read is available afterwards from
+    /// [`MapsFile::last_fingerprint`], for passing to the next call.
+    pub fn changed_since(&mut self, fingerprint: u64) -> Result<Option<Vec<Map>>, Error> {
+        let contents = self.read()?;
+        if self.last_fingerprint == Some(fingerprint) {
+            return Ok(None);
+        }
+
+        parse(&contents).map(Some)
+    }
+
+    /// Returns the fingerprint of the most recent read of the maps file, if any.
+    pub fn last_fingerprint(&self) -> Option<u64> {
+        self.last_fingerprint
+    }
+
+    fn read(&mut self) -> Result<Vec<u8>, Error> {
+        let mut contents = vec![];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut contents)?;
+
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        self.last_fingerprint = Some(hasher.finish());
+
+        Ok(contents)
+    }
+}
+
+fn parse(contents: &[u8]) -> Result<Vec<Map>, Error> {
+    Maps::new(contents).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    #[test]
+    fn test_maps_diff() {
+        let old = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-3000 r-xp 00001000 08:11 1 /bin/cat\n\
+             4000-5000 rw-p 00000000 00:00 0 \n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let new = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-3000 r--p 00001000 08:11 1 /bin/cat\n\
+             6000-7000 rw-p 00000000 00:00 0 \n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        let diff = MapsDiff::between(&old, &new);
+        assert_eq!(diff.removed, vec![old[1].clone(), old[2].clone()]);
+        assert_eq!(diff.added, vec![new[1].clone(), new[2].clone()]);
+
+        assert!(MapsDiff::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_maps_file() {
+        let mut maps_file = MapsFile::open(std::process::id()).unwrap();
+        assert_eq!(maps_file.last_fingerprint(), None);
+
+        let before = maps_file.snapshot().unwrap();
+        let fingerprint = maps_file.last_fingerprint().unwrap();
+
+        // NOTE(ww): Allocations this large are always serviced by a fresh mmap.
+        let buf = vec![1u8; 64 * 1024 * 1024];
+        let addr = buf.as_ptr() as u64;
+        let contains = |maps: &[Map]| {
+            maps.iter()
+                .any(|m| m.address_range.begin <= addr && addr < m.address_range.end)
+        };
+
+        let after = maps_file.changed_since(fingerprint).unwrap().unwrap();
+        assert!(!contains(&before));
+        assert!(contains(&after));
+        assert_ne!(maps_file.last_fingerprint(), Some(fingerprint));
+        assert_eq!(
+            maps_file.snapshot().unwrap().len(),
+            maps_file.snapshot().unwrap().len()
+        );
+
+        drop(buf);
+    }
+
+    #[test]
+    fn test_maps_file_unchanged() {
+        let mut maps_file = MapsFile::from_path(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/golden.maps"),
+        )
+        .unwrap();
+
+        let fingerprint = maps_file.fingerprint().unwrap();
+        assert_eq!(maps_file.fingerprint().unwrap(), fingerprint);
+        assert_eq!(maps_file.changed_since(fingerprint).unwrap(), None);
+        assert_eq!(
+            maps_file
+                .changed_since(fingerprint.wrapping_add(1))
+                .unwrap(),
+            Some(maps_file.snapshot().unwrap())
+        );
+    }
+
+    /// Not a real test: this is run in a child process by [`test_maps_monitor`],
+    /// to give the monitor a scripted sequence of maps to observe.
+    #[test]
+    #[ignore]
+    fn maps_monitor_child() {
+        if std::env::var_os("RSPROCMAPS_MONITOR_CHILD").is_none() {
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(300));
+        let buf = vec![1u8; 64 * 1024 * 1024];
+        println!("monitor-child-addr={:x}", buf.as_ptr() as u64);
+        thread::sleep(Duration::from_millis(300));
+        drop(buf);
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_maps_monitor() {
+        use std::io::{BufRead, BufReader};
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--ignored",
+                "--exact",
+                "watch::tests::maps_monitor_child",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env("RSPROCMAPS_MONITOR_CHILD", "1")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut monitor = {
+            let events = events.clone();
+            MapsMonitor::spawn(child.id(), Duration::from_millis(10), move |event| {
+                events.lock().unwrap().push(event)
+            })
+            .unwrap()
+        };
+
+        // NOTE(ww): We keep the child's stdout open until it exits, since the test
+        // harness in the child keeps writing to it after the address.
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let addr = stdout
+            .by_ref()
+            .lines()
+            .find_map(|line| {
+                // NOTE(ww): The test harness prints the test's name on the same line.
+                line.unwrap()
+                    .split_once("monitor-child-addr=")
+                    .map(|(_, addr)| u64::from_str_radix(addr.trim(), 16).unwrap())
+            })
+            .unwrap();
+
+        // NOTE(ww): We reap the child before the monitor has necessarily seen it
+        // exit, so that the monitor also has to handle reads of a reaped process.
+        child.wait().unwrap();
+        drop(stdout);
+        while !monitor.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        monitor.stop();
+        assert!(monitor.take_error().is_none(), "{:?}", monitor.take_error());
+
+        let events = events.lock().unwrap();
+        let contains = |map: &Map| map.address_range.begin <= addr && addr < map.address_range.end;
+        let added = events.iter().position(|e| match e {
+            MapEvent::Added(map) | MapEvent::Changed { new: map, .. } => contains(map),
+            _ => false,
+        });
+        let removed = events.iter().rposition(|e| match e {
+            MapEvent::Removed(map) | MapEvent::Changed { old: map, .. } => contains(map),
+            _ => false,
+        });
+
+        assert!(added.unwrap() < removed.unwrap(), "{:#?}", events);
+        assert_eq!(events.last(), Some(&MapEvent::Terminated));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| **e == MapEvent::Terminated)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_maps_monitor_stop() {
+        let mut monitor =
+            MapsMonitor::spawn(std::process::id(), Duration::from_secs(60), |_| {}).unwrap();
+
+        // The monitor's sleep is interruptible, so this shouldn't wait a minute.
+        let start = std::time::Instant::now();
+        monitor.stop();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(monitor.is_finished());
+        assert!(monitor.take_error().is_none());
+
+        assert!(MapsMonitor::spawn(libc::pid_t::MAX, DEFAULT_INTERVAL, |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_maps_monitor_panic() {
+        let mut monitor = MapsMonitor::spawn(std::process::id(), Duration::from_millis(1), |_| {
+            panic!("oops")
+        })
+        .unwrap();
+
+        // NOTE(ww): This forces at least one event, since the allocation is
+        // too large to be serviced from an existing map.
+        let buf = vec![1u8; 64 * 1024 * 1024];
+        while !monitor.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(buf);
+
+        monitor.stop();
+        assert_eq!(
+            monitor.take_error(),
+            Some(Error::CallbackPanicked("oops".into()))
+        );
+        assert_eq!(monitor.take_error(), None);
+    }
+
+    #[test]
+    fn test_map_event_from_diff() {
+        let old = from_str(
+            "1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-3000 r-xp 00001000 08:11 1 /bin/cat\n\
+             4000-5000 rw-p 00000000 00:00 0 \n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        let new = from_str(
+            "0-1000 rw-p 00000000 00:00 0 \n\
+             1000-2000 r--p 00000000 08:11 1 /bin/cat\n\
+             2000-3000 r--p 00001000 08:11 1 /bin/cat\n\
+             6000-7000 rw-p 00000000 00:00 0 \n",
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        assert_eq!(
+            MapEvent::from_diff(MapsDiff::between(&old, &new)),
+            vec![
+                MapEvent::Added(new[0].clone()),
+                MapEvent::Changed {
+                    old: old[1].clone(),
+                    new: new[2].clone(),
+                },
+                MapEvent::Removed(old[2].clone()),
+                MapEvent::Added(new[3].clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watch_invalid_pid() {
+        assert!(Watch::new(libc::pid_t::MAX).is_err());
+    }
+
+    #[test]
+    fn test_watch_drop() {
+        let watch = Watch::with_interval(std::process::id(), Duration::from_secs(60)).unwrap();
+
+        let start = std::time::Instant::now();
+        drop(watch);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_watch_self() {
+        let watch = Watch::with_interval(std::process::id(), Duration::from_millis(10)).unwrap();
+
+        // Give the watcher a chance to poll at least once; whatever it
+        // reports, it should never report an empty diff.
+        thread::sleep(Duration::from_millis(50));
+        for diff in watch.receiver().try_iter() {
+            assert!(!diff.is_empty());
+        }
+    }
+}